@@ -0,0 +1,350 @@
+// Local actor document
+// https://www.w3.org/TR/activitypub/#actor-objects
+// https://docs.joinmastodon.org/spec/activitypub/#as
+//
+// Our actors live under their federationId, https://host/users/name:
+//
+//   {id}/inbox, {id}/outbox, {id}/followers, {id}/following,
+//   {id}/collections/featured, https://host/inbox (shared),
+//   {id}#main-key (the keyId `send` signs with), https://host/@name (profile)
+//
+// The document is built from `user` and `signing_key`. Profile columns are
+// optional: displayName, note, avatar, header, manuallyApprovesFollowers,
+// discoverable, createdAt. Profile metadata comes from
+//
+// CREATE TABLE user_field (
+//     userId INTEGER NOT NULL,
+//     name TEXT NOT NULL,
+//     value TEXT NOT NULL,   -- html
+//     position INTEGER NOT NULL DEFAULT 0
+// );
+
+use anyhow::{anyhow, Result};
+use rsa::pkcs1::DecodeRsaPrivateKey;
+use rsa::pkcs8::{DecodePrivateKey, EncodePublicKey, LineEnding};
+use rsa::{RsaPrivateKey, RsaPublicKey};
+use spin_sdk::http::{IntoResponse, Params, Request, Response};
+use spin_sdk::sqlite::Value as SV;
+use std::collections::HashMap;
+use url::Url;
+
+use crate::apo::context;
+use crate::apo::{
+    Actor, ActorType, Endpoints, IdOrObject, Object, ObjectType, PublicKey,
+};
+use crate::db::placeholders;
+
+pub struct ActorBuilder {
+    actor: Actor,
+}
+
+impl ActorBuilder {
+    // Collections and endpoints are derived from `federation_id`.
+    pub fn new(federation_id: &str, username: &str) -> Result<Self> {
+        let origin = Url::parse(federation_id)?.origin();
+        if !origin.is_tuple() {
+            return Err(anyhow!("{federation_id} has no origin"));
+        }
+        let base = origin.ascii_serialization();
+        let id = federation_id.trim_end_matches('/').to_string();
+
+        let actor = Actor {
+            context: Some(context::outbound()),
+            kind: ActorType::Person,
+            preferred_username: Some(username.to_string()),
+            url: IdOrObject::from(format!("{base}/@{username}")).into(),
+            inbox: format!("{id}/inbox"),
+            outbox: format!("{id}/outbox"),
+            followers: Some(format!("{id}/followers")),
+            following: Some(format!("{id}/following")),
+            featured: Some(format!("{id}/collections/featured")),
+            endpoints: Some(Endpoints {
+                shared_inbox: Some(format!("{base}/inbox")),
+            }),
+            manually_approves_followers: Some(false),
+            discoverable: Some(true),
+            id,
+            ..Default::default()
+        };
+        Ok(Self { actor })
+    }
+
+    pub fn kind(mut self, kind: ActorType) -> Self {
+        self.actor.kind = kind;
+        self
+    }
+
+    pub fn name(mut self, name: &str) -> Self {
+        self.actor.name = Some(name.to_string());
+        self
+    }
+
+    pub fn summary(mut self, summary: &str) -> Self {
+        self.actor.summary = Some(summary.to_string());
+        self
+    }
+
+    // Avatar
+    pub fn icon(mut self, url: &str) -> Self {
+        self.actor.icon = Some(image(url));
+        self
+    }
+
+    // Header
+    pub fn image(mut self, url: &str) -> Self {
+        self.actor.image = Some(image(url));
+        self
+    }
+
+    // Profile metadata, shown as a table by Mastodon. `value` is html.
+    pub fn field(mut self, name: &str, value: &str) -> Self {
+        let mut field = Object::new(ObjectType::PropertyValue);
+        field.name = Some(name.to_string());
+        field.value = Some(value.to_string());
+        self.actor.attachment.push(field);
+        self
+    }
+
+    pub fn manually_approves_followers(mut self, locked: bool) -> Self {
+        self.actor.manually_approves_followers = Some(locked);
+        self
+    }
+
+    pub fn discoverable(mut self, discoverable: bool) -> Self {
+        self.actor.discoverable = Some(discoverable);
+        self
+    }
+
+    pub fn published(mut self, published: &str) -> Self {
+        self.actor.published = Some(published.to_string());
+        self
+    }
+
+    pub fn public_key(mut self, public_key_pem: &str) -> Self {
+        self.actor.public_key = Some(PublicKey {
+            id: format!("{}#main-key", self.actor.id),
+            owner: self.actor.id.clone(),
+            public_key_pem: public_key_pem.to_string(),
+        });
+        self
+    }
+
+    pub fn build(self) -> Actor {
+        self.actor
+    }
+
+    // Everything we know about local user `federation_id`.
+    pub async fn from_db(federation_id: &str) -> Result<Option<Self>> {
+        Ok(Self::from_db_many(&[federation_id.to_string()])
+            .await?
+            .pop())
+    }
+
+    // Batch of `from_db`, in two queries. Unknown ids are left out.
+    pub async fn from_db_many(federation_ids: &[String]) -> Result<Vec<Self>> {
+        if federation_ids.is_empty() {
+            return Ok(Vec::new());
+        }
+        let ids: Vec<SV> =
+            federation_ids.iter().map(|i| SV::Text(i.clone())).collect();
+        let qr = crate::db::Connection::builder()
+            .await
+            .execute(
+                &format!("SELECT user.*, signing_key.privateKey AS privateKey, group_actor.userId AS groupId FROM user LEFT JOIN signing_key ON user.id = signing_key.userId LEFT JOIN group_actor ON group_actor.userId = user.id WHERE user.federationId IN ({})", placeholders(ids.len())),
+                &ids,
+            )
+            .await;
+
+        let mut builders = Vec::new();
+        for row in qr.rows() {
+            let user_id = row.get::<i64>("id").unwrap_or_default();
+            let federation_id =
+                row.get::<&str>("federationId").unwrap_or_default();
+            let username = row.get::<&str>("name").unwrap_or_default();
+            let mut builder = Self::new(federation_id, username)?;
+
+            if row.get::<i64>("groupId").is_some() {
+                builder = builder.kind(ActorType::Group);
+            }
+            if let Some(n) = row.get::<&str>("displayName") {
+                builder = builder.name(n);
+            }
+            if let Some(n) = row.get::<&str>("note") {
+                builder = builder.summary(n);
+            }
+            if let Some(a) = row.get::<&str>("avatar") {
+                builder = builder.icon(a);
+            }
+            if let Some(h) = row.get::<&str>("header") {
+                builder = builder.image(h);
+            }
+            if let Some(l) = row.get::<i64>("manuallyApprovesFollowers") {
+                builder = builder.manually_approves_followers(l != 0);
+            }
+            if let Some(d) = row.get::<i64>("discoverable") {
+                builder = builder.discoverable(d != 0);
+            }
+            if let Some(c) = row.get::<&str>("createdAt") {
+                builder = builder.published(c);
+            }
+            if let Some(k) = row.get::<&str>("privateKey") {
+                builder = builder.public_key(&public_key_pem(k)?);
+            }
+            builders.push((user_id, builder));
+        }
+        if builders.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let user_ids: Vec<SV> =
+            builders.iter().map(|(id, _)| SV::Integer(*id)).collect();
+        let qr = crate::db::Connection::builder()
+            .await
+            .execute(
+                &format!("SELECT userId, name, value FROM user_field WHERE userId IN ({}) ORDER BY position", placeholders(user_ids.len())),
+                &user_ids,
+            )
+            .await;
+        let mut fields: HashMap<i64, Vec<(String, String)>> = HashMap::new();
+        for f in qr.rows() {
+            fields
+                .entry(f.get::<i64>("userId").unwrap_or_default())
+                .or_default()
+                .push((
+                    f.get::<&str>("name").unwrap_or_default().to_string(),
+                    f.get::<&str>("value").unwrap_or_default().to_string(),
+                ));
+        }
+
+        let builders = builders
+            .into_iter()
+            .map(|(user_id, mut builder)| {
+                for (name, value) in fields.remove(&user_id).unwrap_or_default()
+                {
+                    builder = builder.field(&name, &value);
+                }
+                builder
+            })
+            .collect();
+        Ok(builders)
+    }
+}
+
+// GET /users/:user
+// ActivityPub clients get the actor, browsers are sent to the profile page.
+pub async fn request(
+    req: Request,
+    params: Params,
+) -> Result<impl IntoResponse> {
+    let name = params.get("user").unwrap_or_default();
+    let qr = crate::db::Connection::builder()
+        .await
+        .execute(
+            "SELECT federationId FROM user WHERE name = ?",
+            &[SV::Text(name.to_string())],
+        )
+        .await;
+    let federation_id = qr
+        .rows()
+        .next()
+        .and_then(|r| r.get::<&str>("federationId"))
+        .map(|f| f.to_string());
+
+    let actor = match federation_id {
+        Some(f) => ActorBuilder::from_db(&f).await?.map(|b| b.build()),
+        None => None,
+    };
+    let actor = match actor {
+        Some(a) => a,
+        None => return Ok(Response::builder().status(404).build()),
+    };
+
+    let accept = req
+        .header("Accept")
+        .and_then(|a| a.as_str())
+        .unwrap_or_default();
+    if wants_activity_json(accept) {
+        return Ok(Response::builder()
+            .status(200)
+            .header("Content-Type", "application/activity+json; charset=utf-8")
+            .header("Vary", "Accept")
+            .body(serde_json::to_string(&actor)?)
+            .build());
+    }
+
+    match actor.url.iter().find_map(|u| u.id()) {
+        Some(profile) => Ok(Response::builder()
+            .status(302)
+            .header("Location", profile)
+            .header("Vary", "Accept")
+            .build()),
+        None => Ok(Response::builder().status(406).build()),
+    }
+}
+
+// application/activity+json, or ld+json with the ActivityStreams profile.
+pub fn wants_activity_json(accept: &str) -> bool {
+    accept.split(',').any(|a| {
+        let a = a.trim();
+        a.starts_with("application/activity+json")
+            || (a.starts_with("application/ld+json")
+                && (!a.contains("profile=")
+                    || a.contains("https://www.w3.org/ns/activitystreams")))
+    })
+}
+
+fn image(url: &str) -> Object {
+    let mut image = Object::new(ObjectType::Image);
+    image.url = IdOrObject::from(url.to_string()).into();
+    image.media_type = match url.rsplit('.').next() {
+        Some("png") => Some("image/png".to_string()),
+        Some("jpg") | Some("jpeg") => Some("image/jpeg".to_string()),
+        Some("gif") => Some("image/gif".to_string()),
+        Some("webp") => Some("image/webp".to_string()),
+        _ => None,
+    };
+    image
+}
+
+// signing_key keeps the private key only. PKCS#8 or PKCS#1.
+pub(crate) fn public_key_pem(private_key_pem: &str) -> Result<String> {
+    let private_key = RsaPrivateKey::from_pkcs8_pem(private_key_pem)
+        .or_else(|_| RsaPrivateKey::from_pkcs1_pem(private_key_pem))?;
+    Ok(RsaPublicKey::from(&private_key).to_public_key_pem(LineEnding::LF)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn builder_origin() {
+        let actor =
+            ActorBuilder::new("http://localhost:3000/users/alice", "alice")
+                .unwrap()
+                .build();
+        assert_eq!(
+            actor.url.iter().find_map(|u| u.id()),
+            Some("http://localhost:3000/@alice")
+        );
+        assert_eq!(actor.delivery_inbox(), "http://localhost:3000/inbox");
+        assert!(ActorBuilder::new("urn:example:alice", "alice").is_err());
+    }
+
+    #[test]
+    fn activity_json() {
+        assert!(wants_activity_json("application/activity+json"));
+        assert!(wants_activity_json(
+            "text/html, application/activity+json; charset=utf-8"
+        ));
+        assert!(wants_activity_json(
+            r#"application/ld+json; profile="https://www.w3.org/ns/activitystreams""#
+        ));
+        assert!(wants_activity_json("application/ld+json"));
+        assert!(!wants_activity_json(
+            r#"application/ld+json; profile="https://example.com/other""#
+        ));
+        assert!(!wants_activity_json("text/html"));
+        assert!(!wants_activity_json(""));
+    }
+}
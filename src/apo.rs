@@ -2,16 +2,31 @@
 use anyhow::Result;
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
-use std::collections::HashMap;
+use thiserror::Error;
+use url::Url;
 
-use crate::mastodon::strt::media::MediaAttachment;
-use crate::mastodon::strt::media::MediaType;
+pub mod activity;
+pub mod actor;
+pub mod collection;
+pub mod context;
+pub mod link;
+pub mod object;
+pub mod value;
+
+pub use activity::*;
+pub use actor::*;
+pub use collection::*;
+pub use context::{Context, ContextEntry};
+pub use link::*;
+pub use object::*;
+pub use value::*;
 
 #[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct AcceptedActivity {
     #[serde(rename = "@context")]
-    pub context: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub context: Option<Context>,
     pub id: String,
     #[serde(rename = "type")]
     pub kind: String,
@@ -24,8 +39,10 @@ impl AcceptedActivity {
         match self.kind.as_str() {
             "Accept" => Some(AcceptedTypes::Accept),
             "Announce" => Some(AcceptedTypes::Announce),
+            "Block" => Some(AcceptedTypes::Block),
             "Create" => Some(AcceptedTypes::Create),
             "Delete" => Some(AcceptedTypes::Delete),
+            "Flag" => Some(AcceptedTypes::Flag),
             "Follow" => Some(AcceptedTypes::Follow),
             "Reject" => Some(AcceptedTypes::Reject),
             "Update" => Some(AcceptedTypes::Update),
@@ -33,177 +50,594 @@ impl AcceptedActivity {
             _ => None,
         }
     }
+
+    // Same-origin rules, checked before anything else is done with the
+    // activity. `key_id` is the keyId the HTTP signature was verified with.
+    // https://www.w3.org/TR/activitypub/#security-verification
+    pub fn validate_origin(&self, key_id: &str) -> Result<(), OriginError> {
+        if !same_origin(&self.id, &self.actor) {
+            return Err(OriginError::ActivityId(self.id.clone()));
+        }
+        if !same_origin(key_id, &self.actor) {
+            return Err(OriginError::KeyId(key_id.to_string()));
+        }
+
+        match self.kind() {
+            // Only the owner can delete an object.
+            Some(AcceptedTypes::Delete) => {
+                let object_id = object_id(&self.object).unwrap_or_default();
+                if !same_origin(&object_id, &self.actor) {
+                    return Err(OriginError::Object(object_id));
+                }
+            }
+            // Only the actor of the original activity can undo it. That
+            // takes the activity embedded, an Undo by bare id is refused.
+            Some(AcceptedTypes::Undo) => {
+                let actor = self.object.get("actor").and_then(object_id);
+                if actor.as_deref() != Some(self.actor.as_str()) {
+                    return Err(OriginError::Actor(self.actor.clone()));
+                }
+            }
+            _ => {}
+        }
+
+        Ok(())
+    }
+
+    // The object of a Create, Update or Announce as its origin serves it.
+    // An embedded copy is trusted only when it lives on the actor's origin,
+    // anything else is fetched from its own id.
+    pub async fn authoritative_object(&self) -> Result<Value> {
+        let id = match object_id(&self.object) {
+            Some(id) => id,
+            None => return Err(OriginError::Object(String::new()).into()),
+        };
+
+        let object = if self.object.is_object() && same_origin(&id, &self.actor)
+        {
+            self.object.clone()
+        } else {
+            let fetched = crate::utils::fetch_object(&id).await?;
+            if object_id(&fetched).as_deref() != Some(id.as_str()) {
+                return Err(OriginError::Object(id).into());
+            }
+            fetched
+        };
+
+        self.check_owner(&id, &object)?;
+        Ok(object)
+    }
+
+    // Nobody can create or update someone else's object.
+    fn check_owner(&self, id: &str, object: &Value) -> Result<(), OriginError> {
+        match self.kind() {
+            Some(AcceptedTypes::Create) | Some(AcceptedTypes::Update) => {
+                let owner = match object.get("attributedTo") {
+                    Some(a) => object_id(a),
+                    // Actors updating themselves.
+                    None => Some(id.to_string()),
+                };
+                let owner = owner.unwrap_or_default();
+                if owner != self.actor || !same_origin(&owner, id) {
+                    return Err(OriginError::Actor(self.actor.clone()));
+                }
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+}
+
+#[derive(Error, Debug)]
+pub enum OriginError {
+    #[error("activity id is not on the actor's origin: {0}")]
+    ActivityId(String),
+    #[error("keyId is not on the actor's origin: {0}")]
+    KeyId(String),
+    #[error("object is not on the actor's origin: {0}")]
+    Object(String),
+    #[error("actor does not own the object: {0}")]
+    Actor(String),
+}
+
+pub fn same_origin(a: &str, b: &str) -> bool {
+    match (Url::parse(a), Url::parse(b)) {
+        (Ok(a), Ok(b)) => a.origin().is_tuple() && a.origin() == b.origin(),
+        _ => false,
+    }
+}
+
+// `id` of a reference that is either a bare id or an object. For arrays
+// (attributedTo can be one) the first entry wins.
+pub(crate) fn object_id(v: &Value) -> Option<String> {
+    match v {
+        Value::String(s) => Some(s.to_owned()),
+        Value::Object(o) => o.get("id").and_then(object_id),
+        Value::Array(a) => a.first().and_then(object_id),
+        _ => None,
+    }
 }
 
 #[derive(Clone, Debug, serde::Deserialize, serde::Serialize)]
 pub enum AcceptedTypes {
     Accept,
     Announce,
+    Block,
     Create,
     Delete,
+    Flag,
     Follow,
     Reject,
     Update,
     Undo,
 }
 
-pub enum ObjectTypes {
-    Note,
-    Create,
-    Replies,
-    RsaSignature2017,
-    OrderedCollection,
-    OrderedCollectionPage,
-}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
 
-#[derive(Serialize, Deserialize, Default, Debug, PartialEq, Eq, Clone)]
-#[serde(rename_all = "camelCase")]
-pub struct Object<T> {
-    #[serde(skip_deserializing)]
-    #[serde(rename = "@context")]
-    pub context: String,
-    pub id: String,
-    #[serde(rename = "type")]
-    pub kind: String,
-    pub actor: String,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub published: Option<String>,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub to: Option<Vec<String>>,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub cc: Option<Vec<String>>,
-    pub object: T,
-}
+    // Payloads as served by Mastodon 4.2.
+    const CREATE_NOTE: &str = r##"{
+      "@context": [
+        "https://www.w3.org/ns/activitystreams",
+        {
+          "ostatus": "http://ostatus.org#",
+          "atomUri": "ostatus:atomUri",
+          "inReplyToAtomUri": "ostatus:inReplyToAtomUri",
+          "conversation": "ostatus:conversation",
+          "sensitive": "as:sensitive",
+          "toot": "http://joinmastodon.org/ns#",
+          "votersCount": "toot:votersCount",
+          "blurhash": "toot:blurhash",
+          "focalPoint": { "@container": "@list", "@id": "toot:focalPoint" },
+          "Hashtag": "as:Hashtag"
+        }
+      ],
+      "id": "https://mastodon.social/users/Gargron/statuses/112006418417431516/activity",
+      "type": "Create",
+      "actor": "https://mastodon.social/users/Gargron",
+      "published": "2024-02-29T14:23:01Z",
+      "to": ["https://www.w3.org/ns/activitystreams#Public"],
+      "cc": [
+        "https://mastodon.social/users/Gargron/followers",
+        "https://mstd.seungjin.net/users/seungjin"
+      ],
+      "object": {
+        "id": "https://mastodon.social/users/Gargron/statuses/112006418417431516",
+        "type": "Note",
+        "summary": null,
+        "inReplyTo": "https://mstd.seungjin.net/users/seungjin/statuses/112006400000000000",
+        "published": "2024-02-29T14:23:01Z",
+        "url": "https://mastodon.social/@Gargron/112006418417431516",
+        "attributedTo": "https://mastodon.social/users/Gargron",
+        "to": ["https://www.w3.org/ns/activitystreams#Public"],
+        "cc": [
+          "https://mastodon.social/users/Gargron/followers",
+          "https://mstd.seungjin.net/users/seungjin"
+        ],
+        "sensitive": false,
+        "atomUri": "https://mastodon.social/users/Gargron/statuses/112006418417431516",
+        "inReplyToAtomUri": "https://mstd.seungjin.net/users/seungjin/statuses/112006400000000000",
+        "conversation": "tag:mastodon.social,2024-02-29:objectId=654321:objectType=Conversation",
+        "content": "<p><span class=\"h-card\" translate=\"no\"><a href=\"https://mstd.seungjin.net/@seungjin\" class=\"u-url mention\">@<span>seungjin</span></a></span> Welcome to the <a href=\"https://mastodon.social/tags/fediverse\" class=\"mention hashtag\" rel=\"tag\">#<span>fediverse</span></a> :blobcat:</p>",
+        "contentMap": {
+          "en": "<p><span class=\"h-card\" translate=\"no\"><a href=\"https://mstd.seungjin.net/@seungjin\" class=\"u-url mention\">@<span>seungjin</span></a></span> Welcome to the <a href=\"https://mastodon.social/tags/fediverse\" class=\"mention hashtag\" rel=\"tag\">#<span>fediverse</span></a> :blobcat:</p>"
+        },
+        "attachment": [
+          {
+            "type": "Document",
+            "mediaType": "image/png",
+            "url": "https://files.mastodon.social/media_attachments/files/112/006/418/original/ab12cd34.png",
+            "name": "A sparrow on a branch",
+            "blurhash": "UBL_:rOpGG-;~qRjWBae00ofj[WB%MayoLWB",
+            "focalPoint": [0.0, -0.5],
+            "width": 1200,
+            "height": 630
+          }
+        ],
+        "tag": [
+          {
+            "type": "Mention",
+            "href": "https://mstd.seungjin.net/users/seungjin",
+            "name": "@seungjin@mstd.seungjin.net"
+          },
+          {
+            "type": "Hashtag",
+            "href": "https://mastodon.social/tags/fediverse",
+            "name": "#fediverse"
+          },
+          {
+            "id": "https://mastodon.social/emojis/12345",
+            "type": "Emoji",
+            "name": ":blobcat:",
+            "updated": "2023-01-01T00:00:00Z",
+            "icon": {
+              "type": "Image",
+              "mediaType": "image/png",
+              "url": "https://files.mastodon.social/custom_emojis/images/000/012/345/original/blobcat.png"
+            }
+          }
+        ],
+        "replies": {
+          "id": "https://mastodon.social/users/Gargron/statuses/112006418417431516/replies",
+          "type": "Collection",
+          "first": {
+            "type": "CollectionPage",
+            "next": "https://mastodon.social/users/Gargron/statuses/112006418417431516/replies?only_other_accounts=true&page=true",
+            "partOf": "https://mastodon.social/users/Gargron/statuses/112006418417431516/replies",
+            "items": []
+          }
+        }
+      },
+      "signature": {
+        "type": "RsaSignature2017",
+        "creator": "https://mastodon.social/users/Gargron#main-key",
+        "created": "2024-02-29T14:23:02Z",
+        "signatureValue": "aGVsbG8gd29ybGQ="
+      }
+    }"##;
 
-#[derive(Serialize, Deserialize, Default, Debug, PartialEq, Eq, Clone)]
-#[serde(rename_all = "camelCase")]
-pub struct Follow {
-    #[serde(skip_deserializing)]
-    #[serde(rename = "@context")]
-    pub context: String,
-    pub id: String,
-    #[serde(rename = "type")]
-    pub kind: String,
-    pub actor: String,
-    pub object: String,
-}
+    const PERSON: &str = r##"{
+      "@context": [
+        "https://www.w3.org/ns/activitystreams",
+        "https://w3id.org/security/v1",
+        {
+          "manuallyApprovesFollowers": "as:manuallyApprovesFollowers",
+          "toot": "http://joinmastodon.org/ns#",
+          "featured": { "@id": "toot:featured", "@type": "@id" },
+          "featuredTags": { "@id": "toot:featuredTags", "@type": "@id" },
+          "alsoKnownAs": { "@id": "as:alsoKnownAs", "@type": "@id" },
+          "movedTo": { "@id": "as:movedTo", "@type": "@id" },
+          "schema": "http://schema.org#",
+          "PropertyValue": "schema:PropertyValue",
+          "value": "schema:value",
+          "discoverable": "toot:discoverable",
+          "Device": "toot:Device",
+          "devices": { "@id": "toot:devices", "@type": "@id" },
+          "indexable": "toot:indexable",
+          "memorial": "toot:memorial"
+        }
+      ],
+      "id": "https://mastodon.social/users/Gargron",
+      "type": "Person",
+      "following": "https://mastodon.social/users/Gargron/following",
+      "followers": "https://mastodon.social/users/Gargron/followers",
+      "inbox": "https://mastodon.social/users/Gargron/inbox",
+      "outbox": "https://mastodon.social/users/Gargron/outbox",
+      "featured": "https://mastodon.social/users/Gargron/collections/featured",
+      "featuredTags": "https://mastodon.social/users/Gargron/collections/tags",
+      "preferredUsername": "Gargron",
+      "name": "Eugen Rochko",
+      "summary": "<p>Founder, CEO and lead developer <span class=\"h-card\"><a href=\"https://mastodon.social/@Mastodon\" class=\"u-url mention\">@<span>Mastodon</span></a></span></p>",
+      "url": "https://mastodon.social/@Gargron",
+      "manuallyApprovesFollowers": false,
+      "discoverable": true,
+      "indexable": true,
+      "published": "2016-03-16T00:00:00Z",
+      "memorial": false,
+      "devices": "https://mastodon.social/users/Gargron/collections/devices",
+      "alsoKnownAs": ["https://mastodon.example/users/gargron"],
+      "publicKey": {
+        "id": "https://mastodon.social/users/Gargron#main-key",
+        "owner": "https://mastodon.social/users/Gargron",
+        "publicKeyPem": "-----BEGIN PUBLIC KEY-----\nMIIBIjANBgkqhkiG9w0BAQEFAAOCAQ8AMIIBCgKCAQEAvXc4vkECU2/CeuSo1wtn\n-----END PUBLIC KEY-----\n"
+      },
+      "tag": [],
+      "attachment": [
+        {
+          "type": "PropertyValue",
+          "name": "Patreon",
+          "value": "<a href=\"https://www.patreon.com/mastodon\" target=\"_blank\" rel=\"nofollow noopener noreferrer me\"><span class=\"invisible\">https://www.</span><span class=\"\">patreon.com/mastodon</span><span class=\"invisible\"></span></a>"
+        }
+      ],
+      "endpoints": { "sharedInbox": "https://mastodon.social/inbox" },
+      "icon": {
+        "type": "Image",
+        "mediaType": "image/jpeg",
+        "url": "https://files.mastodon.social/accounts/avatars/000/000/001/original/dc4286ceb8fab734.jpg"
+      },
+      "image": {
+        "type": "Image",
+        "mediaType": "image/jpeg",
+        "url": "https://files.mastodon.social/accounts/headers/000/000/001/original/3b91c9965d00888b.jpeg"
+      }
+    }"##;
+
+    const OUTBOX: &str = r##"{
+      "@context": "https://www.w3.org/ns/activitystreams",
+      "id": "https://mastodon.social/users/Gargron/outbox",
+      "type": "OrderedCollection",
+      "totalItems": 74325,
+      "first": "https://mastodon.social/users/Gargron/outbox?page=true",
+      "last": "https://mastodon.social/users/Gargron/outbox?min_id=0&page=true"
+    }"##;
+
+    const ACCEPT_FOLLOW: &str = r##"{
+      "@context": "https://www.w3.org/ns/activitystreams",
+      "id": "https://mastodon.social/users/Gargron#accepts/follows/123456",
+      "type": "Accept",
+      "actor": "https://mastodon.social/users/Gargron",
+      "object": {
+        "id": "https://mstd.seungjin.net/018e0cbe-6d3c-7b6e-a1e3-3c2b0f1d8a11",
+        "type": "Follow",
+        "actor": "https://mstd.seungjin.net/users/seungjin",
+        "object": "https://mastodon.social/users/Gargron"
+      }
+    }"##;
 
-impl Follow {
-    pub fn get_recipient(self) -> Result<String> {
-        Ok(self.object)
+    // What we expect back: the original minus null or empty properties,
+    // which are dropped on serialization.
+    fn expected(raw: &str) -> Value {
+        fn clean(v: Value) -> Value {
+            match v {
+                Value::Object(o) => Value::Object(
+                    o.into_iter()
+                        .filter(|(_, v)| {
+                            !v.is_null()
+                                && !v.as_array().is_some_and(|a| a.is_empty())
+                        })
+                        .map(|(k, v)| (k, clean(v)))
+                        .collect(),
+                ),
+                Value::Array(a) => {
+                    Value::Array(a.into_iter().map(clean).collect())
+                }
+                v => v,
+            }
+        }
+        clean(serde_json::from_str(raw).unwrap())
     }
-}
 
-#[derive(Serialize, Deserialize, Debug, PartialEq, Eq)]
-#[serde(rename_all = "camelCase")]
-pub struct Accept {
-    #[serde(skip_deserializing)]
-    #[serde(rename = "@context")]
-    pub context: String,
-    pub id: String,
-    #[serde(rename = "type")]
-    pub kind: String,
-    pub actor: String,
-    pub object: Follow,
-}
+    fn round_trip<T>(raw: &str) -> T
+    where
+        T: serde::de::DeserializeOwned
+            + Serialize
+            + PartialEq
+            + std::fmt::Debug,
+    {
+        let parsed: T = serde_json::from_str(raw).unwrap();
+        let serialized = serde_json::to_value(&parsed).unwrap();
+        assert_eq!(serialized, expected(raw));
+        let reparsed: T = serde_json::from_value(serialized).unwrap();
+        assert_eq!(reparsed, parsed);
+        // Mastodon already uses the plain terms, normalizing is a no-op.
+        assert_eq!(context::from_str::<T>(raw).unwrap(), parsed);
+        parsed
+    }
 
-#[derive(Serialize, Deserialize, Debug, PartialEq, Eq)]
-#[serde(rename_all = "camelCase")]
-pub struct Create<T> {
-    #[serde(skip_deserializing)]
-    #[serde(rename = "@context")]
-    pub context: Vec<String>,
-    pub id: String,
-    #[serde(rename = "type")]
-    pub kind: String,
-    pub actor: String,
-    pub published: String,
-    pub to: Vec<String>,
-    pub cc: Vec<String>,
-    pub object: T,
-}
+    #[test]
+    fn create_note_round_trip() {
+        let create: Create<Note> = round_trip(CREATE_NOTE);
+        assert_eq!(create.kind, ActivityType::Create);
+        assert_eq!(create.to.first().map(|s| s.as_str()), Some(PUBLIC));
 
-#[derive(Serialize, Deserialize, Debug, PartialEq, Eq)]
-#[serde(rename_all = "camelCase")]
-pub struct Note {
-    pub id: String,
-    #[serde(rename = "type")]
-    pub kind: String,
-    pub summary: Option<String>,
-    pub in_reply_to: Option<String>,
-    pub published: String,
-    pub url: String,
-    pub attributed_to: String,
-    pub to: Vec<String>,
-    pub cc: Vec<String>,
-    pub sensitive: bool,
-    pub atom_uri: String,
-    pub in_relpay_to_atom_uri: Option<bool>,
-    pub converation: String,
-    pub content: String,
-    pub content_map: Vec<HashMap<String, String>>,
-    pub attachment: Vec<MediaAttachment>,
-    pub tag: Vec<String>,
-    pub replies: Replies,
-    // pub signature: RsaSignature2017Object,
-}
+        let note = create.object;
+        assert_eq!(note.kind, ObjectType::Note);
+        assert_eq!(note.attributed_to_id(), Some(create.actor.as_str()));
+        assert_eq!(
+            note.url_href(),
+            Some("https://mastodon.social/@Gargron/112006418417431516")
+        );
+        assert_eq!(
+            note.in_reply_to_id(),
+            Some("https://mstd.seungjin.net/users/seungjin/statuses/112006400000000000")
+        );
+        assert_eq!(note.sensitive, Some(false));
+        assert!(note.content_map.unwrap().contains_key("en"));
 
-#[derive(Serialize, Deserialize, Debug, PartialEq, Eq)]
-#[serde(rename_all = "camelCase")]
-pub struct Replies {
-    pub id: String,
-    #[serde(rename = "type")]
-    pub kind: String,
-    pub first: CollectionPage,
-}
+        let attachment = note.attachment.first().unwrap();
+        assert_eq!(attachment.kind, ObjectType::Document);
+        assert_eq!(attachment.focal_point, Some([0.0, -0.5]));
+        assert_eq!(attachment.width, Some(1200));
 
-#[derive(Serialize, Deserialize, Debug, PartialEq, Eq)]
-#[serde(rename_all = "camelCase")]
-pub struct CollectionPage {
-    #[serde(rename = "type")]
-    pub kind: String,
-    pub next: String,
-    pub part_of: String,
-    pub items: Vec<String>,
-}
+        let tags: Vec<&Tag> = note.tag.iter().collect();
+        assert!(
+            matches!(tags[0], Tag::Mention(m) if m.href == "https://mstd.seungjin.net/users/seungjin")
+        );
+        assert!(matches!(tags[1], Tag::Hashtag(h) if h.name == "#fediverse"));
+        assert!(matches!(tags[2], Tag::Emoji(e) if e.name == ":blobcat:"));
 
-#[derive(Serialize, Deserialize, Debug, PartialEq, Eq)]
-#[serde(rename_all = "camelCase")]
-pub struct RsaSignature2017 {
-    #[serde(rename = "type")]
-    pub kind: String,
-    pub creator: String,
-    pub created: String,
-    pub signature_value: String,
-}
+        match note.replies.unwrap() {
+            IdOrObject::Object(replies) => {
+                assert_eq!(replies.kind, CollectionType::Collection);
+                assert!(replies.first.unwrap().object().is_some());
+            }
+            IdOrObject::Id(_) => panic!("replies should be embedded"),
+        }
+    }
 
-#[derive(Serialize, Deserialize, Debug, PartialEq, Eq)]
-#[serde(rename_all = "camelCase")]
-pub struct OrderedCollection {
-    #[serde(skip_deserializing)]
-    #[serde(rename = "@context")]
-    pub context: String,
-    pub id: String,
-    #[serde(rename = "type")]
-    pub kind: String,
-    pub total_items: i64,
-    pub first: String,
-}
+    #[test]
+    fn person_round_trip() {
+        let actor: Actor = round_trip(PERSON);
+        assert_eq!(actor.kind, ActorType::Person);
+        assert_eq!(actor.preferred_username.as_deref(), Some("Gargron"));
+        assert_eq!(actor.manually_approves_followers, Some(false));
+        assert_eq!(actor.delivery_inbox(), "https://mastodon.social/inbox");
+        assert_eq!(
+            actor.public_key.unwrap().owner,
+            "https://mastodon.social/users/Gargron"
+        );
+        let field = actor.attachment.first().unwrap();
+        assert_eq!(field.kind, ObjectType::PropertyValue);
+        assert_eq!(field.name.as_deref(), Some("Patreon"));
+    }
 
-#[derive(Serialize, Deserialize, Debug, PartialEq, Eq)]
-#[serde(rename_all = "camelCase")]
-pub struct OrderedCollectionPage {
-    #[serde(skip_deserializing)]
-    #[serde(rename = "@context")]
-    pub context: String,
-    pub id: String,
-    #[serde(rename = "type")]
-    pub kind: String,
-    pub total_items: i64,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub next: Option<String>,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub prev: Option<String>,
-    pub part_of: String,
-    pub ordered_items: Vec<String>,
+    #[test]
+    fn outbox_round_trip() {
+        let outbox: OrderedCollection = round_trip(OUTBOX);
+        assert_eq!(outbox.total_items, Some(74325));
+        assert_eq!(
+            outbox.first.unwrap().id(),
+            Some("https://mastodon.social/users/Gargron/outbox?page=true")
+        );
+    }
+
+    #[test]
+    fn accept_follow_round_trip() {
+        let accept: Accept = round_trip(ACCEPT_FOLLOW);
+        let follow = accept.object.object().unwrap();
+        assert_eq!(follow.kind, ActivityType::Follow);
+        assert_eq!(follow.object, accept.actor);
+    }
+
+    #[test]
+    fn one_or_many() {
+        let one: OneOrMany<String> =
+            serde_json::from_value(json!("a")).unwrap();
+        let many: OneOrMany<String> =
+            serde_json::from_value(json!(["a", "b"])).unwrap();
+        assert_eq!(one.len(), 1);
+        assert_eq!(many.into_vec(), vec!["a", "b"]);
+        assert_eq!(serde_json::to_value(&one).unwrap(), json!("a"));
+    }
+
+    #[test]
+    fn unknown_types_are_kept() {
+        let note: Note = serde_json::from_value(json!({
+            "id": "https://example.com/1",
+            "type": "ChatMessage",
+            "tag": [{ "type": "Edition", "href": "https://example.com/e" }]
+        }))
+        .unwrap();
+        assert_eq!(note.kind, ObjectType::Other("ChatMessage".to_string()));
+        assert!(matches!(note.tag.first(), Some(Tag::Unknown(_))));
+        assert_eq!(serde_json::to_value(&note).unwrap()["type"], "ChatMessage");
+    }
+
+    #[test]
+    fn languages() {
+        let note: Note = serde_json::from_value(json!({
+            "type": "Note",
+            "content": "<p>Bonjour</p>",
+            "contentMap": { "en": "<p>Hello</p>", "fr-CA": "<p>Bonjour</p>" }
+        }))
+        .unwrap();
+        assert_eq!(note.language().as_deref(), Some("fr"));
+
+        let note: Note = serde_json::from_value(json!({
+            "@context": [context::ACTIVITYSTREAMS, { "@language": "de" }],
+            "type": "Note",
+            "contentMap": { "und": "<p>?</p>", "ja": "<p>こんにちは</p>" }
+        }))
+        .unwrap();
+        assert_eq!(note.content_text(), Some("<p>こんにちは</p>"));
+        assert_eq!(note.language().as_deref(), Some("ja"));
+
+        let note: Note = serde_json::from_value(json!({
+            "@context": [context::ACTIVITYSTREAMS, { "@language": "de" }],
+            "type": "Note",
+            "content": "<p>Hallo</p>"
+        }))
+        .unwrap();
+        assert_eq!(note.language().as_deref(), Some("de"));
+
+        let mut local = Note::new(ObjectType::Note);
+        local.content = Some("<p>Hej</p>".to_string());
+        local.summary = Some("cw".to_string());
+        local.set_language("sv");
+        let v = serde_json::to_value(&local).unwrap();
+        assert_eq!(v["contentMap"]["sv"], "<p>Hej</p>");
+        assert_eq!(v["summaryMap"]["sv"], "cw");
+        assert_eq!(local.language().as_deref(), Some("sv"));
+    }
+
+    fn activity(
+        kind: &str,
+        id: &str,
+        actor: &str,
+        object: Value,
+    ) -> AcceptedActivity {
+        AcceptedActivity {
+            id: id.to_string(),
+            kind: kind.to_string(),
+            actor: actor.to_string(),
+            object,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn origins() {
+        const BOB: &str = "https://remote.example/users/bob";
+        const KEY: &str = "https://remote.example/users/bob#main-key";
+
+        assert!(same_origin(BOB, "https://remote.example/notes/1"));
+        assert!(!same_origin(BOB, "http://remote.example/notes/1"));
+        assert!(!same_origin(BOB, "https://remote.example:8443/notes/1"));
+        assert!(!same_origin(BOB, "https://other.example/notes/1"));
+        assert!(!same_origin(BOB, "not a url"));
+
+        let ok = activity(
+            "Create",
+            "https://remote.example/1",
+            BOB,
+            json!("https://remote.example/notes/1"),
+        );
+        assert!(ok.validate_origin(KEY).is_ok());
+
+        let forged = activity(
+            "Create",
+            "https://other.example/1",
+            BOB,
+            json!("https://remote.example/notes/1"),
+        );
+        assert!(matches!(
+            forged.validate_origin(KEY),
+            Err(OriginError::ActivityId(_))
+        ));
+        assert!(matches!(
+            ok.validate_origin("https://other.example/actor#main-key"),
+            Err(OriginError::KeyId(_))
+        ));
+
+        let delete = activity(
+            "Delete",
+            "https://remote.example/2",
+            BOB,
+            json!({ "id": "https://other.example/notes/1", "type": "Tombstone" }),
+        );
+        assert!(matches!(
+            delete.validate_origin(KEY),
+            Err(OriginError::Object(_))
+        ));
+
+        let undo =
+            |object| activity("Undo", "https://remote.example/3", BOB, object);
+        let follow = json!({
+            "id": "https://remote.example/4",
+            "type": "Follow",
+            "actor": BOB,
+            "object": "https://sparrow.example/users/alice"
+        });
+        assert!(undo(follow.clone()).validate_origin(KEY).is_ok());
+        let mut stolen = follow;
+        stolen["actor"] = json!("https://remote.example/users/eve");
+        assert!(undo(stolen).validate_origin(KEY).is_err());
+        assert!(undo(json!("https://remote.example/4"))
+            .validate_origin(KEY)
+            .is_err());
+
+        let note = |attributed_to: &str| {
+            json!({
+                "id": "https://remote.example/notes/1",
+                "type": "Note",
+                "attributedTo": attributed_to
+            })
+        };
+        let create = activity(
+            "Create",
+            "https://remote.example/5",
+            BOB,
+            note("https://remote.example/users/eve"),
+        );
+        assert!(create
+            .check_owner("https://remote.example/notes/1", &create.object)
+            .is_err());
+        let create =
+            activity("Create", "https://remote.example/5", BOB, note(BOB));
+        assert!(create
+            .check_owner("https://remote.example/notes/1", &create.object)
+            .is_ok());
+    }
 }
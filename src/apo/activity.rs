@@ -0,0 +1,204 @@
+// Activities
+// https://www.w3.org/TR/activitystreams-vocabulary/#activity-types
+// https://www.w3.org/TR/activitypub/#server-to-server-interactions
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+
+use super::context::Context;
+use super::object::Object;
+use super::value::{IdOrObject, Identified, OneOrMany};
+
+pub const PUBLIC: &str = "https://www.w3.org/ns/activitystreams#Public";
+
+#[derive(Serialize, Deserialize, Default, Debug, PartialEq, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct Activity<T> {
+    #[serde(rename = "@context")]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub context: Option<Context>,
+    pub id: String,
+    #[serde(rename = "type")]
+    pub kind: ActivityType,
+    pub actor: String,
+    pub object: T,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub target: Option<IdOrObject<Object>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub origin: Option<IdOrObject<Object>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub published: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub updated: Option<String>,
+    #[serde(default, skip_serializing_if = "OneOrMany::is_empty")]
+    pub to: OneOrMany<String>,
+    #[serde(default, skip_serializing_if = "OneOrMany::is_empty")]
+    pub bto: OneOrMany<String>,
+    #[serde(default, skip_serializing_if = "OneOrMany::is_empty")]
+    pub cc: OneOrMany<String>,
+    #[serde(default, skip_serializing_if = "OneOrMany::is_empty")]
+    pub bcc: OneOrMany<String>,
+    #[serde(default, skip_serializing_if = "OneOrMany::is_empty")]
+    pub audience: OneOrMany<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub summary: Option<String>,
+    // Flag comment, Like/EmojiReact emoji.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub content: Option<String>,
+    // Linked Data Signature attached by Mastodon.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub signature: Option<RsaSignature2017>,
+}
+
+impl<T> Activity<T> {
+    pub fn new(
+        kind: ActivityType,
+        id: String,
+        actor: String,
+        object: T,
+    ) -> Self {
+        Self {
+            context: Some(super::context::outbound()),
+            id,
+            kind,
+            actor,
+            object,
+            target: None,
+            origin: None,
+            published: None,
+            updated: None,
+            to: OneOrMany::default(),
+            bto: OneOrMany::default(),
+            cc: OneOrMany::default(),
+            bcc: OneOrMany::default(),
+            audience: OneOrMany::default(),
+            summary: None,
+            content: None,
+            signature: None,
+        }
+    }
+}
+
+impl<T> Identified for Activity<T> {
+    fn id(&self) -> Option<&str> {
+        Some(self.id.as_str())
+    }
+}
+
+pub type Accept = Activity<IdOrObject<Follow>>;
+pub type Add = Activity<IdOrObject<Object>>;
+pub type Announce = Activity<IdOrObject<Object>>;
+pub type Block = Activity<String>;
+pub type Create<T> = Activity<T>;
+pub type Delete = Activity<IdOrObject<Object>>;
+pub type Flag = Activity<OneOrMany<String>>;
+pub type Follow = Activity<String>;
+pub type Like = Activity<String>;
+pub type Reject = Activity<IdOrObject<Follow>>;
+pub type Remove = Activity<IdOrObject<Object>>;
+pub type Undo<T> = Activity<T>;
+pub type Update<T> = Activity<T>;
+
+impl Activity<String> {
+    pub fn get_recipient(self) -> Result<String> {
+        Ok(self.object)
+    }
+}
+
+impl Activity<OneOrMany<String>> {
+    // Mastodon sends the reported account followed by the reported statuses.
+    pub fn get_objects(&self) -> Vec<String> {
+        self.object.iter().cloned().collect()
+    }
+}
+
+#[derive(Serialize, Deserialize, Default, Debug, PartialEq, Eq, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct RsaSignature2017 {
+    #[serde(rename = "type")]
+    pub kind: String,
+    pub creator: String,
+    pub created: String,
+    pub signature_value: String,
+}
+
+#[derive(Serialize, Deserialize, Default, Debug, PartialEq, Eq, Clone)]
+#[serde(from = "String", into = "String")]
+pub enum ActivityType {
+    Accept,
+    Add,
+    Announce,
+    Arrive,
+    Block,
+    #[default]
+    Create,
+    Delete,
+    Dislike,
+    Flag,
+    Follow,
+    Ignore,
+    Invite,
+    Join,
+    Leave,
+    Like,
+    Listen,
+    Move,
+    Offer,
+    Read,
+    Reject,
+    Remove,
+    TentativeAccept,
+    TentativeReject,
+    Travel,
+    Undo,
+    Update,
+    View,
+    // Misskey and Pleroma reactions
+    EmojiReact,
+    Other(String),
+}
+
+impl From<String> for ActivityType {
+    fn from(s: String) -> Self {
+        match s.as_str() {
+            "Accept" => ActivityType::Accept,
+            "Add" => ActivityType::Add,
+            "Announce" => ActivityType::Announce,
+            "Arrive" => ActivityType::Arrive,
+            "Block" => ActivityType::Block,
+            "Create" => ActivityType::Create,
+            "Delete" => ActivityType::Delete,
+            "Dislike" => ActivityType::Dislike,
+            "Flag" => ActivityType::Flag,
+            "Follow" => ActivityType::Follow,
+            "Ignore" => ActivityType::Ignore,
+            "Invite" => ActivityType::Invite,
+            "Join" => ActivityType::Join,
+            "Leave" => ActivityType::Leave,
+            "Like" => ActivityType::Like,
+            "Listen" => ActivityType::Listen,
+            "Move" => ActivityType::Move,
+            "Offer" => ActivityType::Offer,
+            "Read" => ActivityType::Read,
+            "Reject" => ActivityType::Reject,
+            "Remove" => ActivityType::Remove,
+            "TentativeAccept" => ActivityType::TentativeAccept,
+            "TentativeReject" => ActivityType::TentativeReject,
+            "Travel" => ActivityType::Travel,
+            "Undo" => ActivityType::Undo,
+            "Update" => ActivityType::Update,
+            "View" => ActivityType::View,
+            "EmojiReact" => ActivityType::EmojiReact,
+            _ => ActivityType::Other(s),
+        }
+    }
+}
+
+impl From<ActivityType> for String {
+    fn from(t: ActivityType) -> Self {
+        match t {
+            ActivityType::Other(s) => s,
+            t => format!("{t:?}"),
+        }
+    }
+}
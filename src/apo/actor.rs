@@ -0,0 +1,161 @@
+// Actors
+// https://www.w3.org/TR/activitypub/#actor-objects
+// https://docs.joinmastodon.org/spec/activitypub/#as
+
+use serde::{Deserialize, Serialize};
+
+use super::context::Context;
+use super::link::{Link, Tag};
+use super::object::Object;
+use super::value::{IdOrObject, Identified, OneOrMany};
+
+// inbox and outbox are required by ActivityPub, but actors embedded in
+// other objects (PeerTube's attributedTo, ...) often carry only id and type.
+#[derive(Serialize, Deserialize, Default, Debug, PartialEq, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct Actor {
+    #[serde(rename = "@context")]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub context: Option<Context>,
+    pub id: String,
+    #[serde(rename = "type")]
+    pub kind: ActorType,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub preferred_username: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub summary: Option<String>,
+    #[serde(default, skip_serializing_if = "OneOrMany::is_empty")]
+    pub url: OneOrMany<IdOrObject<Link>>,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub inbox: String,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub outbox: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub followers: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub following: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub liked: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub endpoints: Option<Endpoints>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub public_key: Option<PublicKey>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub icon: Option<Object>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub image: Option<Object>,
+    #[serde(default, skip_serializing_if = "OneOrMany::is_empty")]
+    pub attachment: OneOrMany<Object>,
+    #[serde(default, skip_serializing_if = "OneOrMany::is_empty")]
+    pub tag: OneOrMany<Tag>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub published: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub updated: Option<String>,
+    #[serde(default, skip_serializing_if = "OneOrMany::is_empty")]
+    pub also_known_as: OneOrMany<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub moved_to: Option<String>,
+    // as:manuallyApprovesFollowers
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub manually_approves_followers: Option<bool>,
+    // toot:
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub featured: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub featured_tags: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub discoverable: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub indexable: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub memorial: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub suspended: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub devices: Option<String>,
+    // lemmy: groups
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub moderators: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub posting_restricted_to_mods: Option<bool>,
+}
+
+impl Actor {
+    pub fn is_group(&self) -> bool {
+        self.kind == ActorType::Group
+    }
+
+    // sharedInbox when there is one, inbox otherwise.
+    pub fn delivery_inbox(&self) -> &str {
+        match self
+            .endpoints
+            .as_ref()
+            .and_then(|e| e.shared_inbox.as_deref())
+        {
+            Some(shared_inbox) => shared_inbox,
+            None => self.inbox.as_str(),
+        }
+    }
+}
+
+impl Identified for Actor {
+    fn id(&self) -> Option<&str> {
+        Some(self.id.as_str())
+    }
+}
+
+#[derive(Serialize, Deserialize, Default, Debug, PartialEq, Eq, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct Endpoints {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub shared_inbox: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Default, Debug, PartialEq, Eq, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct PublicKey {
+    pub id: String,
+    pub owner: String,
+    pub public_key_pem: String,
+}
+
+#[derive(Serialize, Deserialize, Default, Debug, PartialEq, Eq, Clone)]
+#[serde(from = "String", into = "String")]
+pub enum ActorType {
+    #[default]
+    Person,
+    Application,
+    Group,
+    Organization,
+    Service,
+    Other(String),
+}
+
+impl From<String> for ActorType {
+    fn from(s: String) -> Self {
+        match s.as_str() {
+            "Person" => ActorType::Person,
+            "Application" => ActorType::Application,
+            "Group" => ActorType::Group,
+            "Organization" => ActorType::Organization,
+            "Service" => ActorType::Service,
+            _ => ActorType::Other(s),
+        }
+    }
+}
+
+impl From<ActorType> for String {
+    fn from(t: ActorType) -> Self {
+        match t {
+            ActorType::Person => "Person".to_string(),
+            ActorType::Application => "Application".to_string(),
+            ActorType::Group => "Group".to_string(),
+            ActorType::Organization => "Organization".to_string(),
+            ActorType::Service => "Service".to_string(),
+            ActorType::Other(s) => s,
+        }
+    }
+}
@@ -0,0 +1,131 @@
+// Collections
+// https://www.w3.org/TR/activitystreams-core/#collections
+// https://www.w3.org/TR/activitypub/#collections
+
+use serde::{Deserialize, Serialize};
+
+use super::context::Context;
+use super::value::{IdOrObject, Identified};
+
+#[derive(Serialize, Deserialize, Default, Debug, PartialEq, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct Collection<T = String> {
+    #[serde(rename = "@context")]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub context: Option<Context>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    #[serde(rename = "type")]
+    pub kind: CollectionType,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub total_items: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub first: Option<IdOrObject<CollectionPage<T>>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last: Option<IdOrObject<CollectionPage<T>>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub current: Option<IdOrObject<CollectionPage<T>>>,
+    #[serde(default = "Vec::new", skip_serializing_if = "Vec::is_empty")]
+    pub items: Vec<T>,
+}
+
+#[derive(Serialize, Deserialize, Default, Debug, PartialEq, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct OrderedCollection<T = String> {
+    #[serde(rename = "@context")]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub context: Option<Context>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    #[serde(rename = "type")]
+    pub kind: CollectionType,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub total_items: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub first: Option<IdOrObject<OrderedCollectionPage<T>>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last: Option<IdOrObject<OrderedCollectionPage<T>>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub current: Option<IdOrObject<OrderedCollectionPage<T>>>,
+    #[serde(default = "Vec::new", skip_serializing_if = "Vec::is_empty")]
+    pub ordered_items: Vec<T>,
+}
+
+#[derive(Serialize, Deserialize, Default, Debug, PartialEq, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct CollectionPage<T = String> {
+    #[serde(rename = "@context")]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub context: Option<Context>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    #[serde(rename = "type")]
+    pub kind: CollectionType,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub total_items: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub part_of: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub next: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub prev: Option<String>,
+    #[serde(default = "Vec::new", skip_serializing_if = "Vec::is_empty")]
+    pub items: Vec<T>,
+}
+
+#[derive(Serialize, Deserialize, Default, Debug, PartialEq, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct OrderedCollectionPage<T = String> {
+    #[serde(rename = "@context")]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub context: Option<Context>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    #[serde(rename = "type")]
+    pub kind: CollectionType,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub total_items: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub part_of: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub next: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub prev: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub start_index: Option<u64>,
+    #[serde(default = "Vec::new", skip_serializing_if = "Vec::is_empty")]
+    pub ordered_items: Vec<T>,
+}
+
+impl<T> Identified for Collection<T> {
+    fn id(&self) -> Option<&str> {
+        self.id.as_deref()
+    }
+}
+
+impl<T> Identified for OrderedCollection<T> {
+    fn id(&self) -> Option<&str> {
+        self.id.as_deref()
+    }
+}
+
+impl<T> Identified for CollectionPage<T> {
+    fn id(&self) -> Option<&str> {
+        self.id.as_deref()
+    }
+}
+
+impl<T> Identified for OrderedCollectionPage<T> {
+    fn id(&self) -> Option<&str> {
+        self.id.as_deref()
+    }
+}
+
+#[derive(Serialize, Deserialize, Default, Debug, PartialEq, Eq, Clone)]
+pub enum CollectionType {
+    #[default]
+    Collection,
+    OrderedCollection,
+    CollectionPage,
+    OrderedCollectionPage,
+}
@@ -0,0 +1,304 @@
+// JSON-LD @context handling
+// https://www.w3.org/TR/activitystreams-core/#jsonld
+// https://docs.joinmastodon.org/spec/activitypub/#extensions
+//
+// We don't run a JSON-LD processor. Incoming documents are normalized so
+// that every property and type the fediverse uses ends up under the plain
+// term our structs expect:
+//
+// - compacted IRIs and full IRIs ("as:sensitive", "toot:Emoji",
+//   "https://www.w3.org/ns/activitystreams#Public") become plain terms,
+// - terms the document's own @context aliases to a known IRI are renamed,
+// - "Public" and "as:Public" in addressing become the full Public IRI,
+// - "@id"/"@type" keys and "@list"/"@value" wrappers are unwrapped.
+
+use anyhow::Result;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map, Value};
+use std::collections::HashMap;
+
+use super::activity::PUBLIC;
+use super::value::OneOrMany;
+
+pub const ACTIVITYSTREAMS: &str = "https://www.w3.org/ns/activitystreams";
+pub const SECURITY: &str = "https://w3id.org/security/v1";
+
+// Namespaces whose terms we use unprefixed.
+const NAMESPACES: [(&str, &str); 13] = [
+    ("as", "https://www.w3.org/ns/activitystreams#"),
+    ("sec", "https://w3id.org/security#"),
+    ("ldp", "http://www.w3.org/ns/ldp#"),
+    ("toot", "http://joinmastodon.org/ns#"),
+    ("schema", "http://schema.org#"),
+    ("ostatus", "http://ostatus.org#"),
+    ("litepub", "http://litepub.social/ns#"),
+    ("misskey", "https://misskey-hub.net/ns#"),
+    ("fedibird", "http://fedibird.com/ns#"),
+    ("lemmy", "https://join-lemmy.org/ns#"),
+    ("pt", "https://joinpeertube.org/ns#"),
+    ("mz", "https://joinmobilizon.org/ns#"),
+    ("fep044f", "https://w3id.org/fep/044f#"),
+];
+
+const ADDRESSING: [&str; 5] = ["to", "cc", "bto", "bcc", "audience"];
+
+// One entry of an @context: a context IRI or a block of term definitions.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+#[serde(untagged)]
+pub enum ContextEntry {
+    Iri(String),
+    Terms(Map<String, Value>),
+}
+
+pub type Context = OneOrMany<ContextEntry>;
+
+// @context for documents we send. Same terms Mastodon declares so remote
+// JSON-LD processors (and LD signature checks) see what they expect.
+pub fn outbound() -> Context {
+    let terms = json!({
+        "manuallyApprovesFollowers": "as:manuallyApprovesFollowers",
+        "sensitive": "as:sensitive",
+        "Hashtag": "as:Hashtag",
+        "quoteUrl": "as:quoteUrl",
+        "alsoKnownAs": { "@id": "as:alsoKnownAs", "@type": "@id" },
+        "movedTo": { "@id": "as:movedTo", "@type": "@id" },
+        "toot": "http://joinmastodon.org/ns#",
+        "Emoji": "toot:Emoji",
+        "featured": { "@id": "toot:featured", "@type": "@id" },
+        "featuredTags": { "@id": "toot:featuredTags", "@type": "@id" },
+        "discoverable": "toot:discoverable",
+        "indexable": "toot:indexable",
+        "memorial": "toot:memorial",
+        "votersCount": "toot:votersCount",
+        "blurhash": "toot:blurhash",
+        "focalPoint": { "@container": "@list", "@id": "toot:focalPoint" },
+        "schema": "http://schema.org#",
+        "PropertyValue": "schema:PropertyValue",
+        "value": "schema:value",
+        "ostatus": "http://ostatus.org#",
+        "atomUri": "ostatus:atomUri",
+        "inReplyToAtomUri": "ostatus:inReplyToAtomUri",
+        "conversation": "ostatus:conversation",
+        "misskey": "https://misskey-hub.net/ns#",
+        "_misskey_quote": "misskey:_misskey_quote",
+        "fedibird": "http://fedibird.com/ns#",
+        "quoteUri": "fedibird:quoteUri",
+        "quote": { "@id": "https://w3id.org/fep/044f#quote", "@type": "@id" }
+    });
+    let terms = match terms {
+        Value::Object(m) => m,
+        _ => Map::new(),
+    };
+    OneOrMany::Many(vec![
+        ContextEntry::Iri(ACTIVITYSTREAMS.to_string()),
+        ContextEntry::Iri(SECURITY.to_string()),
+        ContextEntry::Terms(terms),
+    ])
+}
+
+// Normalize, then deserialize. Use this for anything received from remote.
+pub fn from_str<T: DeserializeOwned>(raw: &str) -> Result<T> {
+    let doc: Value = serde_json::from_str(raw)?;
+    Ok(serde_json::from_value(normalize(doc))?)
+}
+
+pub fn normalize(doc: Value) -> Value {
+    normalize_with(doc, &HashMap::new())
+}
+
+fn normalize_with(v: Value, terms: &HashMap<String, String>) -> Value {
+    match v {
+        Value::Object(o) => {
+            // Embedded documents may bring their own @context.
+            let mut terms = terms.clone();
+            if let Some(c) = o.get("@context") {
+                collect_terms(c, &mut terms);
+            }
+
+            // {"@list": [...]} / {"@value": ...}
+            if o.len() == 1 {
+                if let Some(inner) = o.get("@list").or(o.get("@value")) {
+                    return normalize_with(inner.clone(), &terms);
+                }
+            }
+
+            let mut out = Map::new();
+            for (k, v) in o {
+                if k == "@context" {
+                    out.insert(k, v);
+                    continue;
+                }
+                let key = compact_key(&k, &terms);
+                let v = match key.as_str() {
+                    "type" => map_strings(v, |s| compact(s, &terms)),
+                    k if ADDRESSING.contains(&k) => {
+                        map_strings(v, |s| expand_public(s, &terms))
+                    }
+                    _ => normalize_with(v, &terms),
+                };
+                // Don't let an alias clobber the plain term.
+                if !out.contains_key(&key) {
+                    out.insert(key, v);
+                }
+            }
+            Value::Object(out)
+        }
+        Value::Array(a) => Value::Array(
+            a.into_iter().map(|v| normalize_with(v, terms)).collect(),
+        ),
+        v => v,
+    }
+}
+
+// term -> IRI (or compacted IRI) from an @context.
+fn collect_terms(context: &Value, terms: &mut HashMap<String, String>) {
+    match context {
+        Value::Array(a) => a.iter().for_each(|c| collect_terms(c, terms)),
+        Value::Object(o) => {
+            for (term, def) in o {
+                let iri = match def {
+                    Value::String(s) => Some(s.as_str()),
+                    Value::Object(d) => d.get("@id").and_then(|i| i.as_str()),
+                    _ => None,
+                };
+                if let Some(iri) = iri {
+                    terms.insert(term.to_owned(), iri.to_string());
+                }
+            }
+        }
+        // Remote contexts aren't fetched. The ones that matter are known.
+        _ => {}
+    }
+}
+
+// Full IRI for `term`, if we can tell.
+fn expand(term: &str, terms: &HashMap<String, String>) -> Option<String> {
+    if term.starts_with("http://") || term.starts_with("https://") {
+        return Some(term.to_string());
+    }
+    if let Some(iri) = terms.get(term) {
+        if iri != term {
+            return expand(iri, terms).or(Some(iri.to_owned()));
+        }
+    }
+    let (prefix, suffix) = term.split_once(':')?;
+    let ns = match terms.get(prefix) {
+        Some(ns) => ns.as_str(),
+        None => NAMESPACES.iter().find(|(p, _)| *p == prefix)?.1,
+    };
+    Some(format!("{ns}{suffix}"))
+}
+
+// Plain term for a known IRI, the input otherwise.
+fn compact(term: &str, terms: &HashMap<String, String>) -> String {
+    if let Some(keyword) = term.strip_prefix('@') {
+        return keyword.to_string();
+    }
+    // Plain terms nobody redefined are already what we want.
+    if !term.contains(':') && !terms.contains_key(term) {
+        return term.to_string();
+    }
+    match expand(term, terms) {
+        Some(iri) => NAMESPACES
+            .iter()
+            .find_map(|(_, ns)| iri.strip_prefix(ns))
+            .filter(|local| !local.is_empty())
+            .map(|local| local.to_string())
+            .unwrap_or(term.to_string()),
+        None => term.to_string(),
+    }
+}
+
+fn compact_key(key: &str, terms: &HashMap<String, String>) -> String {
+    match key {
+        "@id" => "id".to_string(),
+        "@type" => "type".to_string(),
+        k => compact(k, terms),
+    }
+}
+
+fn expand_public(s: &str, terms: &HashMap<String, String>) -> String {
+    if s == "Public" || s == "as:Public" || s == PUBLIC {
+        return PUBLIC.to_string();
+    }
+    match expand(s, terms) {
+        Some(iri) if iri == PUBLIC => iri,
+        _ => s.to_string(),
+    }
+}
+
+fn map_strings(v: Value, f: impl Fn(&str) -> String + Copy) -> Value {
+    match v {
+        Value::String(s) => Value::String(f(&s)),
+        Value::Array(a) => {
+            Value::Array(a.into_iter().map(|v| map_strings(v, f)).collect())
+        }
+        v => v,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn public_aliases() {
+        let doc = normalize(json!({
+            "@context": "https://www.w3.org/ns/activitystreams",
+            "type": "Create",
+            "to": "as:Public",
+            "cc": ["Public", "https://example.com/users/a/followers"],
+            "object": { "type": "Note", "to": [PUBLIC] }
+        }));
+        assert_eq!(doc["to"], PUBLIC);
+        assert_eq!(doc["cc"][0], PUBLIC);
+        assert_eq!(doc["cc"][1], "https://example.com/users/a/followers");
+        assert_eq!(doc["object"]["to"][0], PUBLIC);
+    }
+
+    #[test]
+    fn compacted_iris_and_aliases() {
+        let doc = normalize(json!({
+            "@context": [
+                "https://www.w3.org/ns/activitystreams",
+                {
+                    "t": "http://joinmastodon.org/ns#",
+                    "nsfw": "as:sensitive",
+                    "pinned": { "@id": "t:featured", "@type": "@id" },
+                    "uuid": "http://schema.org/identifier"
+                }
+            ],
+            "@id": "https://example.com/users/a",
+            "@type": "as:Person",
+            "nsfw": true,
+            "pinned": "https://example.com/users/a/featured",
+            "toot:discoverable": true,
+            "https://www.w3.org/ns/activitystreams#manuallyApprovesFollowers": false,
+            "uuid": "1234",
+            "tag": [{ "type": "t:Emoji", "name": ":a:" }],
+            "attachment": [{ "focalPoint": { "@list": [0.5, 0.5] } }]
+        }));
+        assert_eq!(doc["id"], "https://example.com/users/a");
+        assert_eq!(doc["type"], "Person");
+        assert_eq!(doc["sensitive"], true);
+        assert_eq!(doc["featured"], "https://example.com/users/a/featured");
+        assert_eq!(doc["discoverable"], true);
+        assert_eq!(doc["manuallyApprovesFollowers"], false);
+        assert_eq!(doc["uuid"], "1234");
+        assert_eq!(doc["tag"][0]["type"], "Emoji");
+        assert_eq!(doc["attachment"][0]["focalPoint"], json!([0.5, 0.5]));
+    }
+
+    #[test]
+    fn context_round_trip() {
+        let context = serde_json::to_value(outbound()).unwrap();
+        assert_eq!(context[0], ACTIVITYSTREAMS);
+        assert_eq!(context[2]["sensitive"], "as:sensitive");
+        let parsed: Context = serde_json::from_value(context.clone()).unwrap();
+        assert_eq!(parsed, outbound());
+        let single: Context =
+            serde_json::from_value(json!(ACTIVITYSTREAMS)).unwrap();
+        assert_eq!(single.len(), 1);
+    }
+}
@@ -0,0 +1,208 @@
+// Link and tag types
+// https://www.w3.org/TR/activitystreams-vocabulary/#dfn-link
+// https://www.w3.org/TR/activitystreams-vocabulary/#dfn-mention
+// https://docs.joinmastodon.org/spec/activitypub/#Hashtag
+// https://docs.joinmastodon.org/spec/activitypub/#Emoji
+// https://codeberg.org/fediverse/fep/src/branch/main/fep/e232/fep-e232.md
+
+use serde::{Deserialize, Deserializer, Serialize};
+use serde_json::Value;
+
+use super::object::Object;
+use super::value::{Identified, OneOrMany};
+
+#[derive(Serialize, Deserialize, Default, Debug, PartialEq, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct Link {
+    #[serde(rename = "type")]
+    pub kind: LinkType,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub href: Option<String>,
+    #[serde(default, skip_serializing_if = "OneOrMany::is_empty")]
+    pub rel: OneOrMany<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub media_type: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub hreflang: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub height: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub width: Option<u32>,
+}
+
+// Media types of a FEP-e232 object link.
+pub const OBJECT_LINK_TYPES: [&str; 2] = [
+    r#"application/ld+json; profile="https://www.w3.org/ns/activitystreams""#,
+    "application/activity+json",
+];
+
+impl Link {
+    // FEP-e232 link to the object quoted.
+    pub fn quote(href: &str) -> Self {
+        Self {
+            kind: LinkType::Link,
+            href: Some(href.to_string()),
+            media_type: Some(OBJECT_LINK_TYPES[0].to_string()),
+            name: Some(format!("RE: {href}")),
+            ..Default::default()
+        }
+    }
+
+    pub fn is_object_link(&self) -> bool {
+        self.media_type
+            .as_deref()
+            .is_some_and(|m| OBJECT_LINK_TYPES.contains(&m))
+    }
+}
+
+impl Identified for Link {
+    fn id(&self) -> Option<&str> {
+        self.id.as_deref().or(self.href.as_deref())
+    }
+}
+
+#[derive(Serialize, Deserialize, Default, Debug, PartialEq, Eq, Clone)]
+#[serde(from = "String", into = "String")]
+pub enum LinkType {
+    #[default]
+    Link,
+    Mention,
+    Other(String),
+}
+
+impl From<String> for LinkType {
+    fn from(s: String) -> Self {
+        match s.as_str() {
+            "Link" => LinkType::Link,
+            "Mention" => LinkType::Mention,
+            _ => LinkType::Other(s),
+        }
+    }
+}
+
+impl From<LinkType> for String {
+    fn from(t: LinkType) -> Self {
+        match t {
+            LinkType::Link => "Link".to_string(),
+            LinkType::Mention => "Mention".to_string(),
+            LinkType::Other(s) => s,
+        }
+    }
+}
+
+// as:Mention. `href` is the actor, `name` its handle ("@user@host").
+#[derive(Serialize, Deserialize, Default, Debug, PartialEq, Eq, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct Mention {
+    #[serde(rename = "type")]
+    pub kind: String,
+    pub href: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+}
+
+// as:Hashtag. `name` carries the leading '#'.
+#[derive(Serialize, Deserialize, Default, Debug, PartialEq, Eq, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct Hashtag {
+    #[serde(rename = "type")]
+    pub kind: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub href: Option<String>,
+    pub name: String,
+}
+
+// toot:Emoji. `name` is the shortcode with colons, `icon` the image.
+#[derive(Serialize, Deserialize, Default, Debug, PartialEq, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct Emoji {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    #[serde(rename = "type")]
+    pub kind: String,
+    pub name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub updated: Option<String>,
+    pub icon: Object,
+}
+
+// Entry of an object's `tag` array.
+// Entries of a type we don't know, or that don't parse, are kept as is.
+#[derive(Serialize, Debug, PartialEq, Clone)]
+#[serde(untagged)]
+pub enum Tag {
+    Mention(Mention),
+    Hashtag(Hashtag),
+    Emoji(Box<Emoji>),
+    Link(Link),
+    Unknown(Value),
+}
+
+impl<'de> Deserialize<'de> for Tag {
+    fn deserialize<D: Deserializer<'de>>(d: D) -> Result<Self, D::Error> {
+        let v = Value::deserialize(d)?;
+        // Let OneOrMany<Tag> fall through to its Many arm.
+        if v.is_array() {
+            return Err(serde::de::Error::custom("tag is an array"));
+        }
+        let tag = match v.get("type").and_then(|t| t.as_str()) {
+            Some("Mention") => {
+                serde_json::from_value(v.clone()).map(Tag::Mention).ok()
+            }
+            Some("Hashtag") => {
+                serde_json::from_value(v.clone()).map(Tag::Hashtag).ok()
+            }
+            Some("Emoji") => serde_json::from_value(v.clone())
+                .map(|e| Tag::Emoji(Box::new(e)))
+                .ok(),
+            Some("Link") => {
+                serde_json::from_value(v.clone()).map(Tag::Link).ok()
+            }
+            _ => None,
+        };
+        Ok(tag.unwrap_or(Tag::Unknown(v)))
+    }
+}
+
+impl Tag {
+    pub fn as_mention(&self) -> Option<&Mention> {
+        match self {
+            Tag::Mention(m) => Some(m),
+            _ => None,
+        }
+    }
+
+    pub fn as_hashtag(&self) -> Option<&Hashtag> {
+        match self {
+            Tag::Hashtag(h) => Some(h),
+            _ => None,
+        }
+    }
+
+    pub fn as_emoji(&self) -> Option<&Emoji> {
+        match self {
+            Tag::Emoji(e) => Some(e),
+            _ => None,
+        }
+    }
+
+    // Object links are quotes unless their rel says otherwise.
+    pub fn as_quote(&self) -> Option<&str> {
+        match self {
+            Tag::Link(l) if l.is_object_link() => {
+                let quote = l.rel.is_empty()
+                    || l.rel.iter().any(|r| r.ends_with("_misskey_quote"));
+                if quote {
+                    l.href.as_deref()
+                } else {
+                    None
+                }
+            }
+            _ => None,
+        }
+    }
+}
@@ -0,0 +1,401 @@
+// Object and its subtypes
+// https://www.w3.org/TR/activitystreams-vocabulary/#dfn-object
+// https://www.w3.org/TR/activitystreams-vocabulary/#object-types
+//
+// AS2 defines every property on Object itself, so Note, Article, Image, ...
+// share one struct and differ only in `kind`.
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use super::actor::Actor;
+use super::collection::Collection;
+use super::context::{Context, ContextEntry};
+use super::link::{Link, Tag};
+use super::value::{IdOrObject, Identified, LanguageMap, OneOrMany};
+
+pub type Note = Object;
+pub type Question = Object;
+
+#[derive(Serialize, Deserialize, Default, Debug, PartialEq, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct Object {
+    #[serde(rename = "@context")]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub context: Option<Context>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    #[serde(rename = "type")]
+    pub kind: ObjectType,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name_map: Option<LanguageMap>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub summary: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub summary_map: Option<LanguageMap>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub content: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub content_map: Option<LanguageMap>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub media_type: Option<String>,
+    // What `content` was rendered from.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub source: Option<Source>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub in_reply_to: Option<IdOrObject<Object>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub published: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub updated: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub start_time: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub end_time: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub duration: Option<String>,
+    #[serde(default, skip_serializing_if = "OneOrMany::is_empty")]
+    pub url: OneOrMany<IdOrObject<Link>>,
+    #[serde(default, skip_serializing_if = "OneOrMany::is_empty")]
+    pub attributed_to: OneOrMany<IdOrObject<Actor>>,
+    #[serde(default, skip_serializing_if = "OneOrMany::is_empty")]
+    pub to: OneOrMany<String>,
+    #[serde(default, skip_serializing_if = "OneOrMany::is_empty")]
+    pub bto: OneOrMany<String>,
+    #[serde(default, skip_serializing_if = "OneOrMany::is_empty")]
+    pub cc: OneOrMany<String>,
+    #[serde(default, skip_serializing_if = "OneOrMany::is_empty")]
+    pub bcc: OneOrMany<String>,
+    #[serde(default, skip_serializing_if = "OneOrMany::is_empty")]
+    pub audience: OneOrMany<String>,
+    #[serde(rename = "context")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub thread: Option<IdOrObject<Object>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub generator: Option<IdOrObject<Object>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub location: Option<IdOrObject<Object>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub icon: Option<OneOrMany<Object>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub image: Option<OneOrMany<Object>>,
+    #[serde(default, skip_serializing_if = "OneOrMany::is_empty")]
+    pub attachment: OneOrMany<Object>,
+    #[serde(default, skip_serializing_if = "OneOrMany::is_empty")]
+    pub tag: OneOrMany<Tag>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub replies: Option<IdOrObject<Collection<IdOrObject<Object>>>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub likes: Option<IdOrObject<Collection<IdOrObject<Object>>>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub shares: Option<IdOrObject<Collection<IdOrObject<Object>>>>,
+    // Question. Options are Notes with a `name` and a `replies` count.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub one_of: Vec<Object>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub any_of: Vec<Object>,
+    // xsd:dateTime or xsd:boolean
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub closed: Option<Value>,
+    // Link in `attachment` (Lemmy link posts)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub href: Option<String>,
+    // Document
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub width: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub height: Option<u32>,
+    // Tombstone
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub former_type: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub deleted: Option<String>,
+    // as:sensitive
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sensitive: Option<bool>,
+    // ostatus:
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub atom_uri: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub in_reply_to_atom_uri: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub conversation: Option<String>,
+    // Quote posts: FEP-044f (Mastodon), as:quoteUrl (Akkoma, Misskey),
+    // fedibird:quoteUri and misskey:_misskey_quote. FEP-e232 links in `tag`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub quote: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub quote_url: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub quote_uri: Option<String>,
+    #[serde(rename = "_misskey_quote")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub misskey_quote: Option<String>,
+    // toot:
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub blurhash: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub focal_point: Option<[f64; 2]>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub voters_count: Option<u64>,
+    // schema:PropertyValue
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub value: Option<String>,
+}
+
+// https://www.w3.org/TR/activitypub/#source-property
+#[derive(Serialize, Deserialize, Default, Debug, PartialEq, Eq, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct Source {
+    pub content: String,
+    pub media_type: String,
+}
+
+impl Object {
+    pub fn new(kind: ObjectType) -> Self {
+        Self {
+            kind,
+            ..Default::default()
+        }
+    }
+
+    // Where a human can see the object.
+    pub fn url_href(&self) -> Option<&str> {
+        self.url.iter().find_map(|u| match u {
+            IdOrObject::Id(href) => Some(href.as_str()),
+            IdOrObject::Object(l) => match &l.media_type {
+                Some(m) if m != "text/html" => None,
+                _ => l.href.as_deref(),
+            },
+        })
+    }
+
+    // First url of any media type. Images, icons, attachments.
+    pub fn url_any(&self) -> Option<&str> {
+        self.url
+            .iter()
+            .find_map(|u| u.id())
+            .or(self.href.as_deref())
+    }
+
+    pub fn attributed_to_id(&self) -> Option<&str> {
+        self.attributed_to.iter().find_map(|a| a.id())
+    }
+
+    pub fn in_reply_to_id(&self) -> Option<&str> {
+        self.in_reply_to.as_ref().and_then(|r| r.id())
+    }
+
+    // Options of a Question and whether more than one can be picked.
+    pub fn poll_options(&self) -> (&[Object], bool) {
+        if self.any_of.is_empty() {
+            (&self.one_of, false)
+        } else {
+            (&self.any_of, true)
+        }
+    }
+
+    pub fn poll_options_mut(&mut self) -> &mut Vec<Object> {
+        if self.any_of.is_empty() {
+            &mut self.one_of
+        } else {
+            &mut self.any_of
+        }
+    }
+
+    // Id of the quoted object, whichever way it is given.
+    pub fn quote_id(&self) -> Option<&str> {
+        self.quote
+            .as_deref()
+            .or(self.quote_url.as_deref())
+            .or(self.quote_uri.as_deref())
+            .or(self.misskey_quote.as_deref())
+            .or_else(|| self.tag.iter().find_map(Tag::as_quote))
+    }
+
+    // Language as Mastodon reports it (primary subtag, lowercase). From the
+    // natural language maps, content first, else the @context's @language.
+    pub fn language(&self) -> Option<String> {
+        let tag = map_language(&self.content_map, &self.content)
+            .or_else(|| map_language(&self.summary_map, &self.summary))
+            .or_else(|| map_language(&self.name_map, &self.name))
+            .or_else(|| self.context_language())?;
+        let primary = tag.split(['-', '_']).next().unwrap_or_default();
+        match primary.to_lowercase().as_str() {
+            "" | "und" => None,
+            p => Some(p.to_string()),
+        }
+    }
+
+    // `content`, else a contentMap entry, the one `language` reports.
+    pub fn content_text(&self) -> Option<&str> {
+        map_text(&self.content_map, &self.content)
+    }
+
+    pub fn summary_text(&self) -> Option<&str> {
+        map_text(&self.summary_map, &self.summary)
+    }
+
+    pub fn name_text(&self) -> Option<&str> {
+        map_text(&self.name_map, &self.name)
+    }
+
+    // Mirror content, summary and name into their maps under `lang`.
+    pub fn set_language(&mut self, lang: &str) {
+        let pairs = [
+            (&self.content, &mut self.content_map),
+            (&self.summary, &mut self.summary_map),
+            (&self.name, &mut self.name_map),
+        ];
+        for (text, map) in pairs {
+            if let Some(t) = text {
+                map.get_or_insert_with(LanguageMap::new)
+                    .insert(lang.to_string(), t.clone());
+            }
+        }
+    }
+
+    fn context_language(&self) -> Option<&str> {
+        self.context
+            .iter()
+            .flat_map(|c| c.iter())
+            .find_map(|e| match e {
+                ContextEntry::Terms(t) => t.get("@language")?.as_str(),
+                _ => None,
+            })
+    }
+
+    pub fn replies_count(&self) -> Option<u64> {
+        match &self.replies {
+            Some(IdOrObject::Object(c)) => c.total_items,
+            _ => None,
+        }
+    }
+}
+
+// Key of the entry `map_text` stands for: the one that is `plain` (or the
+// only one), else the one it picked.
+fn map_language<'a>(
+    map: &'a Option<LanguageMap>,
+    plain: &Option<String>,
+) -> Option<&'a str> {
+    let map = map.as_ref()?;
+    if map.len() == 1 {
+        return map.keys().next().map(|k| k.as_str());
+    }
+    match plain {
+        Some(plain) => map
+            .iter()
+            .find(|(_, v)| *v == plain)
+            .map(|(k, _)| k.as_str()),
+        None => map.keys().min().map(|k| k.as_str()),
+    }
+}
+
+// `plain` if set, else the entry with the smallest key, so the pick is the
+// same every time.
+fn map_text<'a>(
+    map: &'a Option<LanguageMap>,
+    plain: &'a Option<String>,
+) -> Option<&'a str> {
+    if let Some(p) = plain {
+        return Some(p);
+    }
+    let map = map.as_ref()?;
+    map.keys()
+        .min()
+        .and_then(|k| map.get(k))
+        .map(|v| v.as_str())
+}
+
+impl Identified for Object {
+    fn id(&self) -> Option<&str> {
+        self.id.as_deref()
+    }
+}
+
+#[derive(Serialize, Deserialize, Default, Debug, PartialEq, Eq, Clone)]
+#[serde(from = "String", into = "String")]
+pub enum ObjectType {
+    #[default]
+    Object,
+    Article,
+    Audio,
+    Document,
+    Event,
+    Image,
+    Note,
+    Page,
+    Place,
+    Profile,
+    Question,
+    Relationship,
+    Tombstone,
+    Video,
+    PropertyValue,
+    Other(String),
+}
+
+impl ObjectType {
+    // Posts from non-microblog servers (WriteFreely, Lemmy, Mobilizon,
+    // PeerTube, ...). Mastodon shows them as a title and a link.
+    pub fn is_converted(&self) -> bool {
+        matches!(
+            self,
+            ObjectType::Article
+                | ObjectType::Audio
+                | ObjectType::Event
+                | ObjectType::Image
+                | ObjectType::Page
+                | ObjectType::Video
+        )
+    }
+}
+
+impl From<String> for ObjectType {
+    fn from(s: String) -> Self {
+        match s.as_str() {
+            "Object" => ObjectType::Object,
+            "Article" => ObjectType::Article,
+            "Audio" => ObjectType::Audio,
+            "Document" => ObjectType::Document,
+            "Event" => ObjectType::Event,
+            "Image" => ObjectType::Image,
+            "Note" => ObjectType::Note,
+            "Page" => ObjectType::Page,
+            "Place" => ObjectType::Place,
+            "Profile" => ObjectType::Profile,
+            "Question" => ObjectType::Question,
+            "Relationship" => ObjectType::Relationship,
+            "Tombstone" => ObjectType::Tombstone,
+            "Video" => ObjectType::Video,
+            "PropertyValue" => ObjectType::PropertyValue,
+            _ => ObjectType::Other(s),
+        }
+    }
+}
+
+impl From<ObjectType> for String {
+    fn from(t: ObjectType) -> Self {
+        match t {
+            ObjectType::Object => "Object".to_string(),
+            ObjectType::Article => "Article".to_string(),
+            ObjectType::Audio => "Audio".to_string(),
+            ObjectType::Document => "Document".to_string(),
+            ObjectType::Event => "Event".to_string(),
+            ObjectType::Image => "Image".to_string(),
+            ObjectType::Note => "Note".to_string(),
+            ObjectType::Page => "Page".to_string(),
+            ObjectType::Place => "Place".to_string(),
+            ObjectType::Profile => "Profile".to_string(),
+            ObjectType::Question => "Question".to_string(),
+            ObjectType::Relationship => "Relationship".to_string(),
+            ObjectType::Tombstone => "Tombstone".to_string(),
+            ObjectType::Video => "Video".to_string(),
+            ObjectType::PropertyValue => "PropertyValue".to_string(),
+            ObjectType::Other(s) => s,
+        }
+    }
+}
@@ -0,0 +1,151 @@
+// Value types for properties that take more than one shape.
+// https://www.w3.org/TR/activitystreams-core/#extension-round-tripping
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::HashMap;
+
+// Natural language values keyed by BCP47 language tag.
+// https://www.w3.org/TR/activitystreams-core/#naturalLanguageValues
+pub type LanguageMap = HashMap<String, String>;
+
+// A property that is either a single value or an array of them.
+// `One` is boxed so objects can nest themselves (attachment, icon, ...).
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+#[serde(untagged)]
+pub enum OneOrMany<T> {
+    One(Box<T>),
+    Many(Vec<T>),
+}
+
+impl<T> OneOrMany<T> {
+    pub fn iter(&self) -> std::slice::Iter<'_, T> {
+        match self {
+            OneOrMany::One(t) => std::slice::from_ref(&**t).iter(),
+            OneOrMany::Many(v) => v.iter(),
+        }
+    }
+
+    pub fn iter_mut(&mut self) -> std::slice::IterMut<'_, T> {
+        match self {
+            OneOrMany::One(t) => std::slice::from_mut(&mut **t).iter_mut(),
+            OneOrMany::Many(v) => v.iter_mut(),
+        }
+    }
+
+    pub fn first(&self) -> Option<&T> {
+        self.iter().next()
+    }
+
+    pub fn len(&self) -> usize {
+        self.iter().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn push(&mut self, t: T) {
+        let mut v = std::mem::take(self).into_vec();
+        v.push(t);
+        *self = OneOrMany::Many(v);
+    }
+
+    pub fn into_vec(self) -> Vec<T> {
+        match self {
+            OneOrMany::One(t) => vec![*t],
+            OneOrMany::Many(v) => v,
+        }
+    }
+}
+
+impl<T> Default for OneOrMany<T> {
+    fn default() -> Self {
+        OneOrMany::Many(Vec::new())
+    }
+}
+
+impl<T> From<T> for OneOrMany<T> {
+    fn from(t: T) -> Self {
+        OneOrMany::One(Box::new(t))
+    }
+}
+
+impl<T> From<Vec<T>> for OneOrMany<T> {
+    fn from(v: Vec<T>) -> Self {
+        OneOrMany::Many(v)
+    }
+}
+
+impl<T> IntoIterator for OneOrMany<T> {
+    type Item = T;
+    type IntoIter = std::vec::IntoIter<T>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.into_vec().into_iter()
+    }
+}
+
+impl<'a, T> IntoIterator for &'a OneOrMany<T> {
+    type Item = &'a T;
+    type IntoIter = std::slice::Iter<'a, T>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+// A reference to another object, either by its id or embedded.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+#[serde(untagged)]
+pub enum IdOrObject<T> {
+    Id(String),
+    Object(Box<T>),
+}
+
+impl<T: Identified> IdOrObject<T> {
+    pub fn id(&self) -> Option<&str> {
+        match self {
+            IdOrObject::Id(id) => Some(id.as_str()),
+            IdOrObject::Object(o) => o.id(),
+        }
+    }
+}
+
+impl<T> IdOrObject<T> {
+    pub fn object(&self) -> Option<&T> {
+        match self {
+            IdOrObject::Id(_) => None,
+            IdOrObject::Object(o) => Some(o),
+        }
+    }
+}
+
+impl<T> From<String> for IdOrObject<T> {
+    fn from(id: String) -> Self {
+        IdOrObject::Id(id)
+    }
+}
+
+impl<T> From<&str> for IdOrObject<T> {
+    fn from(id: &str) -> Self {
+        IdOrObject::Id(id.to_string())
+    }
+}
+
+// Anything that can be referenced by an IRI.
+pub trait Identified {
+    fn id(&self) -> Option<&str>;
+}
+
+impl Identified for Value {
+    fn id(&self) -> Option<&str> {
+        self.get("id").and_then(|i| i.as_str())
+    }
+}
+
+impl Identified for String {
+    fn id(&self) -> Option<&str> {
+        Some(self.as_str())
+    }
+}
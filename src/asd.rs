@@ -1,24 +0,0 @@
-//  Activity Streams Document
-
-pub struct Type {}
-
-#[derive(Serialize, Deserialize, Default, Debug, PartialEq, Eq, Clone)]
-#[serde(rename_all = "camelCase")]
-pub struct ObjectType<T> {
-    #[serde(skip_deserializing)]
-    #[serde(rename = "@context")]
-    pub context: String,
-    pub id: String,
-    #[serde(rename = "type")]
-    pub kind: String,
-    pub actor: String,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub published: Option<String>,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub to: Option<Vec<String>>,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub cc: Option<Vec<String>>,
-    pub object: T,
-}
-
-pub struct LinkType {}
@@ -0,0 +1,208 @@
+// Block activity
+// https://www.w3.org/TR/activitypub/#block-activity-outbox
+// https://docs.joinmastodon.org/spec/activitypub/#Block
+//
+// blocking: local user -> remote actor. We send the Block so the remote side
+// drops its follow relationship and stops delivering to us.
+// blocked_by: remote actor -> local user. We keep the record so the app can
+// hide that actor from the user and skip delivering the user's posts there.
+
+use anyhow::Result;
+use serde_json::Value;
+use spin_sdk::sqlite::Value as SV;
+use tracing::debug;
+use url::Url;
+use uuid::Uuid;
+
+use crate::apo::{AcceptedActivity, ActivityType, Block, Undo};
+
+pub async fn block(my_actor: Url, recipient_actor: Url) -> Result<()> {
+    let uuid = Uuid::now_v7().to_string();
+    let id = format!(
+        "{}://{}/{}",
+        my_actor.scheme(),
+        my_actor.host_str().unwrap(),
+        uuid
+    );
+
+    let block = Block::new(
+        ActivityType::Block,
+        id,
+        my_actor.to_string(),
+        recipient_actor.to_string(),
+    );
+    let body = serde_json::to_string(&block)?;
+    debug!("block -> {body}");
+
+    let status =
+        crate::send::foo(recipient_actor.to_string(), body.clone()).await?;
+    debug!("block status --> {status}");
+
+    // Blocking implies unfollowing.
+    let _ = crate::db::Connection::builder()
+        .await
+        .execute(
+            "DELETE FROM following WHERE userId = (SELECT id FROM user WHERE federationId = ?) AND federationId = ?",
+            &[
+                SV::Text(my_actor.to_string()),
+                SV::Text(recipient_actor.to_string()),
+            ],
+        )
+        .await;
+
+    let _ = crate::db::Connection::builder()
+        .await
+        .execute(
+            r#"INSERT OR IGNORE INTO blocking(userId, federationId, object) VALUES((SELECT id FROM user WHERE federationId = ?),?,json(?))"#,
+            &[
+                SV::Text(my_actor.to_string()),
+                SV::Text(recipient_actor.to_string()),
+                SV::Text(body),
+            ],
+        )
+        .await;
+
+    Ok(())
+}
+
+pub async fn unblock(my_actor: Url, recipient_actor: Url) -> Result<()> {
+    let qr = crate::db::Connection::builder()
+        .await
+        .execute(
+            "SELECT object FROM blocking WHERE userId = (SELECT id FROM user WHERE federationId = ?) AND federationId = ?",
+            &[
+                SV::Text(my_actor.to_string()),
+                SV::Text(recipient_actor.to_string()),
+            ],
+        )
+        .await;
+
+    let block: Block = match qr.rows().next() {
+        Some(row) => serde_json::from_str(row.get::<&str>("object").unwrap())?,
+        None => return Ok(()),
+    };
+
+    let uuid = Uuid::now_v7().to_string();
+    let id = format!(
+        "{}://{}/{}",
+        my_actor.scheme(),
+        my_actor.host_str().unwrap(),
+        uuid
+    );
+    let undo: Undo<Block> =
+        Undo::new(ActivityType::Undo, id, my_actor.to_string(), block);
+    let body = serde_json::to_string(&undo)?;
+    debug!("undo block -> {body}");
+
+    let status = crate::send::foo(recipient_actor.to_string(), body).await?;
+    debug!("undo block status --> {status}");
+
+    let _ = crate::db::Connection::builder()
+        .await
+        .execute(
+            "DELETE FROM blocking WHERE userId = (SELECT id FROM user WHERE federationId = ?) AND federationId = ?",
+            &[
+                SV::Text(my_actor.to_string()),
+                SV::Text(recipient_actor.to_string()),
+            ],
+        )
+        .await;
+
+    Ok(())
+}
+
+// Inbound Block. `object` is one of our actors.
+pub async fn blocked_by(activity: &AcceptedActivity) -> Result<()> {
+    let target = match activity.object.as_str() {
+        Some(t) => t.to_string(),
+        None => return Err(anyhow::Error::msg("Block without object")),
+    };
+
+    let _ = crate::db::Connection::builder()
+        .await
+        .execute(
+            r#"INSERT OR IGNORE INTO blocked_by(userId, federationId, object) VALUES((SELECT id FROM user WHERE federationId = ?),?,json(?))"#,
+            &[
+                SV::Text(target.clone()),
+                SV::Text(activity.actor.clone()),
+                SV::Text(serde_json::to_string(activity)?),
+            ],
+        )
+        .await;
+
+    // The remote side dropped the relationship, so do we.
+    let _ = crate::db::Connection::builder()
+        .await
+        .execute(
+            "DELETE FROM following WHERE userId = (SELECT id FROM user WHERE federationId = ?) AND federationId = ?",
+            &[SV::Text(target), SV::Text(activity.actor.clone())],
+        )
+        .await;
+
+    Ok(())
+}
+
+// Inbound Undo{Block}. `object` is the original Block, embedded or by id.
+pub async fn unblocked_by(activity: &AcceptedActivity) -> Result<()> {
+    let target = match &activity.object {
+        Value::Object(o) => o.get("object").and_then(|t| t.as_str()),
+        _ => None,
+    };
+
+    let qr = match target {
+        Some(t) => {
+            crate::db::Connection::builder()
+                .await
+                .execute(
+                    "DELETE FROM blocked_by WHERE userId = (SELECT id FROM user WHERE federationId = ?) AND federationId = ?",
+                    &[SV::Text(t.to_string()), SV::Text(activity.actor.clone())],
+                )
+                .await
+        }
+        None => {
+            crate::db::Connection::builder()
+                .await
+                .execute(
+                    "DELETE FROM blocked_by WHERE federationId = ? AND json_extract(object, '$.id') = ?",
+                    &[
+                        SV::Text(activity.actor.clone()),
+                        SV::Text(
+                            activity.object.as_str().unwrap_or("").to_string(),
+                        ),
+                    ],
+                )
+                .await
+        }
+    };
+    debug!("{qr:?}");
+
+    Ok(())
+}
+
+pub async fn is_blocking(my_actor: &str, remote_actor: &str) -> Result<bool> {
+    let qr = crate::db::Connection::builder()
+        .await
+        .execute(
+            "SELECT 1 FROM blocking WHERE userId = (SELECT id FROM user WHERE federationId = ?) AND federationId = ?",
+            &[
+                SV::Text(my_actor.to_string()),
+                SV::Text(remote_actor.to_string()),
+            ],
+        )
+        .await;
+    Ok(!qr.rows.is_empty())
+}
+
+pub async fn is_blocked_by(my_actor: &str, remote_actor: &str) -> Result<bool> {
+    let qr = crate::db::Connection::builder()
+        .await
+        .execute(
+            "SELECT 1 FROM blocked_by WHERE userId = (SELECT id FROM user WHERE federationId = ?) AND federationId = ?",
+            &[
+                SV::Text(my_actor.to_string()),
+                SV::Text(remote_actor.to_string()),
+            ],
+        )
+        .await;
+    Ok(!qr.rows.is_empty())
+}
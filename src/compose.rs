@@ -0,0 +1,479 @@
+// Posting statuses
+// https://docs.joinmastodon.org/methods/statuses/#create
+// https://docs.joinmastodon.org/spec/activitypub/#Note
+//
+// POST /api/v1/statuses
+//
+// What a local user writes becomes a Note (a Question with a poll), stored
+// with its thread and sent to its audience in a Create. Addressing follows
+// Mastodon's visibilities:
+//
+//   public     to: Public     cc: followers, mentions
+//   unlisted   to: followers  cc: Public, mentions
+//   private    to: followers  cc: mentions
+//   direct     to: mentions
+//
+// Followers' shared inboxes and the inboxes of mentioned remote actors get
+// the activity through the delivery queue.
+
+use anyhow::{anyhow, Result};
+use serde_json::Value;
+use spin_sdk::http::{IntoResponse, Params, Request, Response};
+use spin_sdk::sqlite::Value as SV;
+use tracing::debug;
+use url::Url;
+use uuid::Uuid;
+
+use crate::apo::{
+    Activity, ActivityType, IdOrObject, Note, Object, ObjectType, PUBLIC,
+};
+use crate::auth::{check_api_auth, TokenAuth};
+use crate::format::Format;
+use crate::mastodon::strt::media::MediaAttachment;
+use crate::mastodon::strt::status::Status;
+use crate::thread::Resolver;
+use crate::utils::get_current_time_in_iso_8601;
+
+// Same limits as Mastodon.
+const MAX_CHARS: usize = 500;
+const MAX_MEDIA: usize = 4;
+const MAX_POLL_OPTIONS: usize = 4;
+const POLL_EXPIRES_IN: std::ops::RangeInclusive<i64> = 300..=2_629_746;
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct NewStatus {
+    pub status: Option<String>,
+    pub in_reply_to_id: Option<String>,
+    pub media_ids: Vec<String>,
+    pub sensitive: bool,
+    pub spoiler_text: Option<String>,
+    pub visibility: Option<String>,
+    pub language: Option<String>,
+    pub poll: Option<NewPoll>,
+    // text/plain or text/markdown
+    pub content_type: Option<String>,
+    pub quoted_status_id: Option<String>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct NewPoll {
+    pub options: Vec<String>,
+    pub expires_in: i64,
+    pub multiple: bool,
+}
+
+// Rejected parameters, answered with 422.
+#[derive(thiserror::Error, Debug, PartialEq, Eq)]
+#[error("{0}")]
+pub struct Invalid(pub String);
+
+impl NewStatus {
+    // application/x-www-form-urlencoded, arrays as `media_ids[]` and
+    // `poll[options][]`.
+    pub fn from_form(body: &[u8]) -> Self {
+        let mut new = Self::default();
+        let mut poll = NewPoll::default();
+        let mut has_poll = false;
+        for (k, v) in url::form_urlencoded::parse(body) {
+            let v = v.to_string();
+            match k.as_ref() {
+                "status" => new.status = Some(v),
+                "in_reply_to_id" => new.in_reply_to_id = Some(v),
+                "media_ids[]" | "media_ids" => new.media_ids.push(v),
+                "sensitive" => new.sensitive = truthy(&v),
+                "spoiler_text" => new.spoiler_text = Some(v),
+                "visibility" => new.visibility = Some(v),
+                "language" => new.language = Some(v),
+                "content_type" => new.content_type = Some(v),
+                "quoted_status_id" => new.quoted_status_id = Some(v),
+                "poll[options][]" | "poll[options]" => {
+                    has_poll = true;
+                    poll.options.push(v);
+                }
+                "poll[expires_in]" => {
+                    has_poll = true;
+                    poll.expires_in = v.parse().unwrap_or_default();
+                }
+                "poll[multiple]" => poll.multiple = truthy(&v),
+                _ => {}
+            }
+        }
+        if has_poll {
+            new.poll = Some(poll);
+        }
+        new
+    }
+
+    // application/json. Clients send booleans and numbers as strings too.
+    pub fn from_json(v: &Value) -> Self {
+        let text = |v: &Value, k: &str| match v.get(k) {
+            Some(Value::String(s)) => Some(s.to_owned()),
+            Some(Value::Number(n)) => Some(n.to_string()),
+            _ => None,
+        };
+        let flag = |v: &Value, k: &str| match v.get(k) {
+            Some(Value::Bool(b)) => *b,
+            Some(Value::String(s)) => truthy(s),
+            _ => false,
+        };
+        let list = |v: &Value, k: &str| -> Vec<String> {
+            v.get(k)
+                .and_then(|l| l.as_array())
+                .map(|l| {
+                    l.iter()
+                        .filter_map(|i| match i {
+                            Value::String(s) => Some(s.to_owned()),
+                            Value::Number(n) => Some(n.to_string()),
+                            _ => None,
+                        })
+                        .collect()
+                })
+                .unwrap_or_default()
+        };
+
+        Self {
+            status: text(v, "status"),
+            in_reply_to_id: text(v, "in_reply_to_id"),
+            media_ids: list(v, "media_ids"),
+            sensitive: flag(v, "sensitive"),
+            spoiler_text: text(v, "spoiler_text"),
+            visibility: text(v, "visibility"),
+            language: text(v, "language"),
+            poll: v.get("poll").filter(|p| p.is_object()).map(|p| NewPoll {
+                options: list(p, "options"),
+                expires_in: text(p, "expires_in")
+                    .and_then(|e| e.parse().ok())
+                    .unwrap_or_default(),
+                multiple: flag(p, "multiple"),
+            }),
+            content_type: text(v, "content_type"),
+            quoted_status_id: text(v, "quoted_status_id"),
+        }
+    }
+
+    pub fn validate(&self) -> Result<(), Invalid> {
+        let text = self.status.as_deref().unwrap_or_default().trim();
+        let spoiler = self.spoiler_text.as_deref().unwrap_or_default();
+        if text.is_empty() && self.media_ids.is_empty() && self.poll.is_none() {
+            return Err(Invalid(
+                "Validation failed: Text can't be blank".into(),
+            ));
+        }
+        let length = text.chars().count() + spoiler.chars().count();
+        if length > MAX_CHARS {
+            return Err(Invalid(format!(
+                "Validation failed: Text character limit of {MAX_CHARS} exceeded"
+            )));
+        }
+        if self.media_ids.len() > MAX_MEDIA {
+            return Err(Invalid(format!(
+                "Validation failed: Too many media, {MAX_MEDIA} at most"
+            )));
+        }
+        if let Some(poll) = &self.poll {
+            if !self.media_ids.is_empty() {
+                return Err(Invalid(
+                    "Validation failed: Cannot attach both media and a poll"
+                        .into(),
+                ));
+            }
+            if !(2..=MAX_POLL_OPTIONS).contains(&poll.options.len())
+                || poll.options.iter().any(|o| o.trim().is_empty())
+            {
+                return Err(Invalid(format!(
+                    "Validation failed: Poll needs 2 to {MAX_POLL_OPTIONS} options"
+                )));
+            }
+            if !POLL_EXPIRES_IN.contains(&poll.expires_in) {
+                return Err(Invalid(
+                    "Validation failed: Poll duration is out of range".into(),
+                ));
+            }
+        }
+        match self.visibility.as_deref() {
+            None | Some("public") | Some("unlisted") | Some("private")
+            | Some("direct") => Ok(()),
+            Some(v) => {
+                Err(Invalid(format!("Validation failed: Visibility {v}")))
+            }
+        }
+    }
+}
+
+// Post `new` as local actor `my_actor`, `base` being this server. Returns
+// the status as the author sees it.
+pub async fn create(
+    my_actor: &str,
+    new: NewStatus,
+    base: &str,
+) -> Result<Status> {
+    new.validate()?;
+    let visibility = new.visibility.as_deref().unwrap_or("public");
+    let followers = format!("{my_actor}/followers");
+    let uuid = Uuid::now_v7();
+    let id = format!("{my_actor}/statuses/{uuid}");
+    let name = my_actor.rsplit('/').next().unwrap_or_default();
+
+    let mut note = match &new.poll {
+        Some(p) => crate::poll::question(
+            id.clone(),
+            my_actor,
+            String::new(),
+            &p.options,
+            p.multiple,
+            p.expires_in,
+        ),
+        None => {
+            let mut note = Note::new(ObjectType::Note);
+            note.id = Some(id.clone());
+            note.attributed_to = IdOrObject::from(my_actor.to_string()).into();
+            note.published = Some(get_current_time_in_iso_8601().await);
+            note
+        }
+    };
+    note.url = IdOrObject::from(format!("{base}/@{name}/{uuid}")).into();
+    match visibility {
+        "public" => {
+            note.to.push(PUBLIC.to_string());
+            note.cc.push(followers);
+        }
+        "unlisted" => {
+            note.to.push(followers);
+            note.cc.push(PUBLIC.to_string());
+        }
+        "private" => note.to.push(followers),
+        _ => {}
+    }
+
+    let parent = match &new.in_reply_to_id {
+        Some(p) => Some(Resolver::new().signed_by(my_actor).load(p).await?),
+        None => None,
+    };
+    if let Some(parent) = &parent {
+        let parent_id = parent.id.clone().unwrap_or_default();
+        note.in_reply_to = Some(IdOrObject::Id(parent_id.clone()));
+        note.conversation = crate::thread::thread_of(&parent_id).await;
+    }
+    if note.conversation.is_none() {
+        note.conversation = Some(id.clone());
+    }
+
+    let spoiler = new.spoiler_text.as_deref().unwrap_or_default().trim();
+    if !spoiler.is_empty() {
+        note.summary = Some(spoiler.to_string());
+    }
+    note.sensitive = Some(new.sensitive || !spoiler.is_empty());
+
+    let text = new.status.as_deref().unwrap_or_default();
+    let format = Format::from_content_type(new.content_type.as_deref());
+    crate::format::format(text, format, base)
+        .await?
+        .apply(&mut note, new.language.as_deref());
+
+    // The author replied to hears of it, as Mastodon does.
+    if let Some(author) = parent.as_ref().and_then(|p| p.attributed_to_id()) {
+        let addressed =
+            note.to.iter().chain(note.cc.iter()).any(|a| a == author);
+        if author != my_actor && !addressed {
+            match visibility {
+                "direct" => note.to.push(author.to_string()),
+                _ => note.cc.push(author.to_string()),
+            }
+        }
+    }
+    if let Some(quoted) = &new.quoted_status_id {
+        let quoted = Resolver::new()
+            .signed_by(my_actor)
+            .load(quoted)
+            .await
+            .ok()
+            .and_then(|q| q.id)
+            .ok_or_else(|| Invalid(format!("Unknown status {quoted}")))?;
+        crate::quote::link(&mut note, &quoted);
+    }
+
+    for media_id in &new.media_ids {
+        let media = MediaAttachment::uploaded_by(media_id, my_actor)
+            .await
+            .ok_or_else(|| Invalid(format!("Unknown media {media_id}")))?;
+        note.attachment.push(document(&media));
+    }
+
+    crate::thread::store(&note).await?;
+    if new.poll.is_some() {
+        crate::poll::save(&note).await?;
+    }
+
+    let mut activity = Activity::new(
+        ActivityType::Create,
+        format!("{id}/activity"),
+        my_actor.to_string(),
+        note.clone(),
+    );
+    activity.to = note.to.clone();
+    activity.cc = note.cc.clone();
+    activity.published = note.published.clone();
+    let body = serde_json::to_string(&activity)?;
+    debug!("create -> {body}");
+
+    let inboxes = inboxes(my_actor, &note).await?;
+    crate::delivery::enqueue(my_actor, &inboxes, &body).await;
+    if let Err(e) = crate::delivery::run(inboxes.len()).await {
+        tracing::error!("delivering {id}: {e}");
+    }
+
+    let status = Status::load(&id, Some(my_actor), base).await?;
+    Ok(status.unwrap_or_else(|| Status::from_note(&note, base)))
+}
+
+// Inboxes the Create goes to: followers when they are addressed, and every
+// addressed remote actor.
+pub(crate) async fn inboxes(my_actor: &str, note: &Note) -> Result<Vec<String>> {
+    let followers = format!("{my_actor}/followers");
+    let my_host = Url::parse(my_actor)?.host_str().map(|h| h.to_string());
+
+    let mut inboxes = Vec::new();
+    for recipient in note.to.iter().chain(note.cc.iter()) {
+        if *recipient == PUBLIC {
+            continue;
+        }
+        if *recipient == followers {
+            let qr = crate::db::Connection::builder()
+                .await
+                .execute(
+                    "SELECT id FROM user WHERE federationId = ?",
+                    &[SV::Text(my_actor.to_string())],
+                )
+                .await;
+            let user_id = qr.rows().next().and_then(|r| r.get::<i64>("id"));
+            if let Some(user_id) = user_id {
+                inboxes.extend(
+                    crate::inbox::follower_inboxes(user_id, None).await?,
+                );
+            }
+            continue;
+        }
+        let host = Url::parse(recipient)
+            .ok()
+            .and_then(|u| u.host_str().map(|h| h.to_string()));
+        if host.is_none() || host == my_host {
+            continue;
+        }
+        match crate::remote_actor::get(recipient).await {
+            Ok(a) => inboxes.push(a.delivery_inbox().to_string()),
+            Err(e) => debug!("no inbox for {recipient}: {e}"),
+        }
+    }
+    inboxes.sort();
+    inboxes.dedup();
+    Ok(inboxes)
+}
+
+// Attachment for one of our uploads.
+fn document(media: &MediaAttachment) -> Object {
+    let url = media.url.clone().unwrap_or_default();
+    let mut document = Object::new(ObjectType::Document);
+    document.media_type = match url.rsplit('.').next() {
+        Some("png") => Some("image/png".to_string()),
+        Some("jpg") | Some("jpeg") => Some("image/jpeg".to_string()),
+        Some("gif") => Some("image/gif".to_string()),
+        Some("webp") => Some("image/webp".to_string()),
+        Some("mp4") => Some("video/mp4".to_string()),
+        Some("webm") => Some("video/webm".to_string()),
+        Some("mp3") => Some("audio/mpeg".to_string()),
+        _ => None,
+    };
+    document.url = IdOrObject::from(url).into();
+    document.name = media.description.clone();
+    document.blurhash = media.blurhash.clone();
+    let original = media.meta.as_ref().and_then(|m| m.get("original"));
+    let size = |k: &str| {
+        original
+            .and_then(|o| o.get(k))
+            .and_then(|v| v.as_u64())
+            .map(|v| v as u32)
+    };
+    document.width = size("width");
+    document.height = size("height");
+    document
+}
+
+pub async fn request(
+    req: Request,
+    _params: Params,
+) -> Result<impl IntoResponse> {
+    if req.header("Authorization").is_none() {
+        return Ok(error(401, "The access token is invalid"));
+    }
+    let user_id = match check_api_auth(&req).await? {
+        TokenAuth::Valid(id) => id,
+        _ => return Ok(error(401, "The access token is invalid")),
+    };
+    let (my_actor, _) = crate::utils::get_local_user(user_id as i64).await?;
+    let base = Url::parse(&my_actor)?.origin().ascii_serialization();
+
+    let content_type = req
+        .header("Content-Type")
+        .and_then(|c| c.as_str())
+        .unwrap_or_default();
+    let new = if content_type.starts_with("application/json") {
+        match serde_json::from_slice::<Value>(req.body()) {
+            Ok(v) => NewStatus::from_json(&v),
+            Err(e) => return Ok(error(400, &e.to_string())),
+        }
+    } else {
+        NewStatus::from_form(req.body())
+    };
+
+    match create(&my_actor, new, &base).await {
+        Ok(status) => Ok(Response::builder()
+            .status(200)
+            .header("Content-Type", "application/json")
+            .body(serde_json::to_string(&status)?)
+            .build()),
+        Err(e) => match e.downcast_ref::<Invalid>() {
+            Some(invalid) => Ok(error(422, &invalid.0)),
+            None => Err(anyhow!("posting for {my_actor}: {e}")),
+        },
+    }
+}
+
+fn truthy(v: &str) -> bool {
+    matches!(v, "true" | "1" | "on")
+}
+
+pub(crate) fn error(status: u16, message: &str) -> Response {
+    Response::builder()
+        .status(status)
+        .header("Content-Type", "application/json")
+        .body(serde_json::json!({ "error": message }).to_string())
+        .build()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parameters() {
+        let form = NewStatus::from_form(
+            b"status=Hi&visibility=unlisted&poll%5Boptions%5D%5B%5D=a&poll%5Boptions%5D%5B%5D=b&poll%5Bexpires_in%5D=3600",
+        );
+        let json = NewStatus::from_json(&serde_json::json!({
+            "status": "Hi",
+            "visibility": "unlisted",
+            "poll": { "options": ["a", "b"], "expires_in": "3600" }
+        }));
+        assert_eq!(form, json);
+        assert_eq!(form.poll.as_ref().map(|p| p.options.len()), Some(2));
+        assert_eq!(form.validate(), Ok(()));
+
+        let mut new = form.clone();
+        new.media_ids.push("1".to_string());
+        assert!(new.validate().is_err());
+        new.poll = None;
+        new.visibility = Some("everyone".to_string());
+        assert!(new.validate().is_err());
+        assert!(NewStatus::default().validate().is_err());
+    }
+}
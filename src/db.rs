@@ -129,6 +129,11 @@ impl ConnectionBuilder {
     }
 }
 
+// "?,?,?" for an `IN (...)` list of n values.
+pub fn placeholders(n: usize) -> String {
+    vec!["?"; n].join(",")
+}
+
 // pub async fn resultset_to_queryresult(rs: ResultSet) -> QueryResult {
 //     // From https://fermyon.github.io/rust-docs/spin/main/spin_sdk/sqlite/struct.QueryResult.html
 //     // To https://docs.rs/libsql-client/latest/libsql_client/struct.ResultSet.html
@@ -0,0 +1,194 @@
+// Outgoing delivery queue
+// https://www.w3.org/TR/activitypub/#delivery
+//
+// Activities our users send are queued per inbox, then `run` posts whatever
+// is due. It is called right after queueing and is meant to be called again
+// periodically (a cron trigger) for the retries. Failed deliveries are tried
+// again with a growing delay, MAX_ATTEMPTS times at most. Inboxes that
+// refuse the activity for good (4xx other than 408 and 429) are dropped
+// right away. Activities that supersede each other, like poll tallies, are
+// queued with a key and sent at most once per delay, the latest one only.
+//
+// CREATE TABLE delivery (
+//     id INTEGER PRIMARY KEY AUTOINCREMENT,
+//     signer TEXT NOT NULL,          -- local actor the request is signed as
+//     inbox TEXT NOT NULL,
+//     body TEXT NOT NULL,            -- activity json, sent as is
+//     attempts INTEGER NOT NULL DEFAULT 0,
+//     nextAttemptAt TEXT NOT NULL,
+//     lastError TEXT,
+//     collapseKey TEXT,              -- pending copies replaced by newer ones
+//     createdAt TEXT NOT NULL
+// );
+// CREATE INDEX delivery_next_attempt_at ON delivery(nextAttemptAt);
+
+use anyhow::Result;
+use chrono::{DateTime, Duration, Utc};
+use spin_sdk::sqlite::Value as SV;
+use tracing::debug;
+
+use crate::utils::get_current_time_in_iso_8601;
+
+const MAX_ATTEMPTS: i64 = 8;
+
+// Queue `body` for each of `inboxes`.
+pub async fn enqueue(signer: &str, inboxes: &[String], body: &str) {
+    let now = get_current_time_in_iso_8601().await;
+    for inbox in inboxes {
+        let _ = crate::db::Connection::builder()
+            .await
+            .execute(
+                "INSERT INTO delivery(signer, inbox, body, attempts, nextAttemptAt, createdAt) VALUES(?,?,?,0,?,?)",
+                &[
+                    SV::Text(signer.to_string()),
+                    SV::Text(inbox.to_string()),
+                    SV::Text(body.to_string()),
+                    SV::Text(now.clone()),
+                    SV::Text(now.clone()),
+                ],
+            )
+            .await;
+    }
+}
+
+// Queue `body` for each of `inboxes`, due in `delay` at the latest. Copies
+// with the same `key` that weren't tried yet are replaced, keeping their due
+// time, so a burst of updates goes out once.
+pub async fn enqueue_collapsed(
+    signer: &str,
+    inboxes: &[String],
+    body: &str,
+    key: &str,
+    delay: Duration,
+) {
+    let qr = crate::db::Connection::builder()
+        .await
+        .execute(
+            "SELECT MIN(nextAttemptAt) AS due FROM delivery WHERE collapseKey = ? AND attempts = 0",
+            &[SV::Text(key.to_string())],
+        )
+        .await;
+    let due = match qr
+        .rows()
+        .next()
+        .and_then(|r| r.get::<&str>("due").map(|d| d.to_string()))
+    {
+        Some(due) => due,
+        None => timestamp(Utc::now() + delay),
+    };
+    let _ = crate::db::Connection::builder()
+        .await
+        .execute(
+            "DELETE FROM delivery WHERE collapseKey = ? AND attempts = 0",
+            &[SV::Text(key.to_string())],
+        )
+        .await;
+
+    let now = get_current_time_in_iso_8601().await;
+    for inbox in inboxes {
+        let _ = crate::db::Connection::builder()
+            .await
+            .execute(
+                "INSERT INTO delivery(signer, inbox, body, attempts, nextAttemptAt, collapseKey, createdAt) VALUES(?,?,?,0,?,?,?)",
+                &[
+                    SV::Text(signer.to_string()),
+                    SV::Text(inbox.to_string()),
+                    SV::Text(body.to_string()),
+                    SV::Text(due.clone()),
+                    SV::Text(key.to_string()),
+                    SV::Text(now.clone()),
+                ],
+            )
+            .await;
+    }
+}
+
+// Deliver up to `limit` due activities. Returns how many went through.
+pub async fn run(limit: usize) -> Result<usize> {
+    let now = get_current_time_in_iso_8601().await;
+    let qr = crate::db::Connection::builder()
+        .await
+        .execute(
+            "SELECT id, signer, inbox, body, attempts FROM delivery WHERE nextAttemptAt <= ? ORDER BY nextAttemptAt LIMIT ?",
+            &[SV::Text(now), SV::Integer(limit as i64)],
+        )
+        .await;
+    let due: Vec<(i64, String, String, String, i64)> = qr
+        .rows()
+        .filter_map(|r| {
+            Some((
+                r.get::<i64>("id")?,
+                r.get::<&str>("signer")?.to_string(),
+                r.get::<&str>("inbox")?.to_string(),
+                r.get::<&str>("body")?.to_string(),
+                r.get::<i64>("attempts").unwrap_or_default(),
+            ))
+        })
+        .collect();
+
+    let mut delivered = 0;
+    for (id, signer, inbox, body, attempts) in due {
+        let error = match crate::send::deliver(&signer, &inbox, &body).await {
+            Ok(status) if (200..300).contains(&status) => {
+                debug!("delivered to {inbox} --> {status}");
+                delivered += 1;
+                remove(id).await;
+                continue;
+            }
+            Ok(status) if gone(status) => {
+                debug!("{inbox} refused the delivery --> {status}");
+                remove(id).await;
+                continue;
+            }
+            Ok(status) => format!("status {status}"),
+            Err(e) => e.to_string(),
+        };
+
+        let attempts = attempts + 1;
+        if attempts >= MAX_ATTEMPTS {
+            tracing::error!("giving up on {inbox} after {attempts}: {error}");
+            remove(id).await;
+            continue;
+        }
+        debug!("delivery to {inbox} failed ({error}), retrying later");
+        retry(id, attempts, &error).await;
+    }
+    Ok(delivered)
+}
+
+// Won't get any better by trying again.
+fn gone(status: u16) -> bool {
+    (400..500).contains(&status) && status != 408 && status != 429
+}
+
+// 5 minutes after the first failure, doubling up to about 10 hours.
+fn backoff(attempts: i64) -> Duration {
+    Duration::minutes(5 * 2_i64.pow(attempts.clamp(1, 8) as u32 - 1))
+}
+
+async fn retry(id: i64, attempts: i64, error: &str) {
+    let next = timestamp(Utc::now() + backoff(attempts));
+    let _ = crate::db::Connection::builder()
+        .await
+        .execute(
+            "UPDATE delivery SET attempts = ?, nextAttemptAt = ?, lastError = ? WHERE id = ?",
+            &[
+                SV::Integer(attempts),
+                SV::Text(next),
+                SV::Text(error.to_string()),
+                SV::Integer(id),
+            ],
+        )
+        .await;
+}
+
+fn timestamp(t: DateTime<Utc>) -> String {
+    t.format("%Y-%m-%dT%H:%M:%SZ").to_string()
+}
+
+async fn remove(id: i64) {
+    let _ = crate::db::Connection::builder()
+        .await
+        .execute("DELETE FROM delivery WHERE id = ?", &[SV::Integer(id)])
+        .await;
+}
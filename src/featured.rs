@@ -0,0 +1,449 @@
+// Featured (pinned) posts
+// https://docs.joinmastodon.org/spec/activitypub/#featured
+//
+// An actor's `featured` collection lists the statuses pinned to its profile.
+// Pinning sends followers Add{object: note, target: featured}, unpinning
+// Remove. Remote collections are read when a profile is viewed.
+//
+// POST /api/v1/statuses/:id/pin
+// POST /api/v1/statuses/:id/unpin
+// GET /api/v1/accounts/:id/statuses?pinned=true
+//
+// CREATE TABLE pinned_status (
+//     userId INTEGER NOT NULL,
+//     federationId TEXT NOT NULL,   -- Note id, served from thread_object
+//     createdAt TEXT NOT NULL,
+//     UNIQUE(userId, federationId)
+// );
+
+use anyhow::Result;
+use async_trait::async_trait;
+use serde_json::Value;
+use spin_sdk::http::{IntoResponse, Params, Request, Response};
+use spin_sdk::sqlite::Value as SV;
+use std::collections::HashMap;
+use tracing::debug;
+use url::Url;
+use uuid::Uuid;
+
+use crate::apo::context;
+use crate::apo::{
+    same_origin, Activity, ActivityType, Actor, IdOrObject, Note, PUBLIC,
+};
+use crate::auth::{check_api_auth, TokenAuth};
+use crate::compose::{error, Invalid};
+use crate::mastodon::strt::status::Status;
+use crate::pagination::{fetch_rows, BadCursor, Cursor, Paginator, Source};
+use crate::thread::{authored_on_origin, Resolver};
+use crate::utils::get_current_time_in_iso_8601;
+use crate::walker::Walker;
+
+// Same as Mastodon.
+pub const MAX_PINNED: i64 = 5;
+
+// Statuses pinned by `user` (a local federationId), last pinned first.
+pub struct Featured {
+    pub user: String,
+}
+
+#[async_trait(?Send)]
+impl Source for Featured {
+    type Item = Value;
+
+    async fn count(&self) -> Result<u64> {
+        Ok(pinned_count(&self.user).await as u64)
+    }
+
+    async fn fetch(
+        &self,
+        cursor: &Cursor,
+        limit: usize,
+    ) -> Result<Vec<(String, Value)>> {
+        let pinned = fetch_rows(
+            "pinned_status",
+            "userId = (SELECT id FROM user WHERE federationId = ?)",
+            vec![SV::Text(self.user.clone())],
+            cursor,
+            limit,
+            |r| {
+                Ok(r.get::<&str>("federationId")
+                    .unwrap_or_default()
+                    .to_string())
+            },
+        )
+        .await?;
+
+        // The notes as they are now, edits included. Deleted ones are gone.
+        let ids: Vec<String> =
+            pinned.iter().map(|(_, id)| id.clone()).collect();
+        let mut notes = current(&ids).await;
+        Ok(pinned
+            .into_iter()
+            .filter_map(|(c, id)| notes.remove(&id).map(|n| (c, n)))
+            .collect())
+    }
+}
+
+// GET /users/:user/collections/featured
+pub async fn request(
+    req: Request,
+    params: Params,
+) -> Result<impl IntoResponse> {
+    let name = params.get("user").unwrap_or_default();
+    let qr = crate::db::Connection::builder()
+        .await
+        .execute(
+            "SELECT federationId FROM user WHERE name = ?",
+            &[SV::Text(name.to_string())],
+        )
+        .await;
+    let user =
+        match qr.rows().next().and_then(|r| r.get::<&str>("federationId")) {
+            Some(u) => u.to_string(),
+            None => return Ok(Response::builder().status(404).build()),
+        };
+
+    let paginator = Paginator::new(&featured_url(&user), Featured { user })
+        .limit(MAX_PINNED as usize);
+    let body = match paginator.respond(req.query()).await {
+        Ok(b) => b,
+        Err(e) if e.is::<BadCursor>() => {
+            return Ok(Response::builder().status(400).build())
+        }
+        Err(e) => return Err(e),
+    };
+    Ok(Response::builder()
+        .status(200)
+        .header("Content-Type", "application/activity+json; charset=utf-8")
+        .body(serde_json::to_string(&body)?)
+        .build())
+}
+
+// POST /api/v1/statuses/:id/pin and /unpin
+// `id` is the status' federationId, url encoded. Answers the status.
+pub async fn pin_request(
+    req: Request,
+    params: Params,
+) -> Result<impl IntoResponse> {
+    if req.header("Authorization").is_none() {
+        return Ok(error(401, "The access token is invalid"));
+    }
+    let user_id = match check_api_auth(&req).await? {
+        TokenAuth::Valid(id) => id,
+        _ => return Ok(error(401, "The access token is invalid")),
+    };
+    let (my_actor, _) = crate::utils::get_local_user(user_id as i64).await?;
+    let id = urlencoding::decode(params.get("id").unwrap_or_default())?;
+    let url = Url::parse(req.uri())?;
+    let base = url.origin().ascii_serialization();
+
+    let done = if url.path().ends_with("/unpin") {
+        unpin(Url::parse(&my_actor)?, &id).await
+    } else {
+        match Resolver::new().stored_only().load(&id).await {
+            Ok(note) => pin(Url::parse(&my_actor)?, &note).await,
+            Err(_) => return Ok(error(404, "Record not found")),
+        }
+    };
+    if let Err(e) = done {
+        return match e.downcast_ref::<Invalid>() {
+            Some(invalid) => Ok(error(422, &invalid.0)),
+            None => Err(e),
+        };
+    }
+
+    match Status::load(&id, Some(&my_actor), &base).await? {
+        Some(status) => Ok(Response::builder()
+            .status(200)
+            .header("Content-Type", "application/json")
+            .body(serde_json::to_string(&status)?)
+            .build()),
+        None => Ok(error(404, "Record not found")),
+    }
+}
+
+// GET /api/v1/accounts/:id/statuses?pinned=true
+// Pinned statuses of account `id` (a federationId, url encoded), as its
+// profile shows them. Remote collections are read on every view.
+pub async fn pinned_request(
+    req: Request,
+    params: Params,
+) -> Result<impl IntoResponse> {
+    let id = urlencoding::decode(params.get("id").unwrap_or_default())?;
+    let base = Url::parse(req.uri())
+        .map(|u| u.origin().ascii_serialization())
+        .unwrap_or_default();
+    let viewer = match req.header("Authorization") {
+        None => None,
+        Some(_) => match check_api_auth(&req).await? {
+            TokenAuth::Valid(user_id) => {
+                Some(crate::utils::get_local_user(user_id as i64).await?.0)
+            }
+            _ => return Ok(error(401, "The access token is invalid")),
+        },
+    };
+    let viewer = viewer.as_deref();
+
+    let ids = if is_local(&id).await {
+        pinned_ids(&id).await
+    } else {
+        let actor = match crate::remote_actor::get(&id).await {
+            Ok(a) => a.actor,
+            Err(e) => {
+                debug!("pinned statuses of {id}: {e}");
+                return Ok(error(404, "Record not found"));
+            }
+        };
+        fetch_remote(&actor, viewer)
+            .await?
+            .into_iter()
+            .filter_map(|n| n.id)
+            .collect()
+    };
+
+    // Only what anybody may see, but to the author.
+    let statuses: Vec<Status> = Status::load_many(&ids, viewer, &base)
+        .await?
+        .into_iter()
+        .filter(|s| {
+            matches!(s.visibility.as_str(), "public" | "unlisted")
+                || viewer == Some(s.account.id.as_str())
+        })
+        .collect();
+    Ok(Response::builder()
+        .status(200)
+        .header("Content-Type", "application/json")
+        .body(serde_json::to_string(&statuses)?)
+        .build())
+}
+
+// Pin `note`, a status of `my_actor`, and tell followers.
+pub async fn pin(my_actor: Url, note: &Note) -> Result<()> {
+    let note_id = match &note.id {
+        Some(id) => id.to_owned(),
+        None => return Err(anyhow::Error::msg("Note without id")),
+    };
+    if note.attributed_to_id() != Some(my_actor.as_str()) {
+        return Err(Invalid("only own statuses can be pinned".into()).into());
+    }
+    if is_pinned(my_actor.as_str(), &note_id).await {
+        return Ok(());
+    }
+    if pinned_count(my_actor.as_str()).await >= MAX_PINNED {
+        return Err(Invalid("too many pinned statuses".into()).into());
+    }
+
+    let _ = crate::db::Connection::builder()
+        .await
+        .execute(
+            "INSERT INTO pinned_status(userId, federationId, createdAt) VALUES((SELECT id FROM user WHERE federationId = ?),?,?)",
+            &[
+                SV::Text(my_actor.to_string()),
+                SV::Text(note_id.clone()),
+                SV::Text(get_current_time_in_iso_8601().await),
+            ],
+        )
+        .await;
+
+    announce(ActivityType::Add, &my_actor, note_id).await
+}
+
+pub async fn unpin(my_actor: Url, note_id: &str) -> Result<()> {
+    if !is_pinned(my_actor.as_str(), note_id).await {
+        return Ok(());
+    }
+    let _ = crate::db::Connection::builder()
+        .await
+        .execute(
+            "DELETE FROM pinned_status WHERE userId = (SELECT id FROM user WHERE federationId = ?) AND federationId = ?",
+            &[
+                SV::Text(my_actor.to_string()),
+                SV::Text(note_id.to_string()),
+            ],
+        )
+        .await;
+
+    announce(ActivityType::Remove, &my_actor, note_id.to_string()).await
+}
+
+pub async fn is_pinned(user: &str, note_id: &str) -> bool {
+    let qr = crate::db::Connection::builder()
+        .await
+        .execute(
+            "SELECT 1 FROM pinned_status WHERE userId = (SELECT id FROM user WHERE federationId = ?) AND federationId = ?",
+            &[SV::Text(user.to_string()), SV::Text(note_id.to_string())],
+        )
+        .await;
+    !qr.rows.is_empty()
+}
+
+// Statuses pinned by remote `actor`, read with `viewer`'s key if there is
+// one, and stored. Items given by id are loaded, anything not authored by
+// `actor` is dropped.
+pub async fn fetch_remote(
+    actor: &Actor,
+    viewer: Option<&str>,
+) -> Result<Vec<Note>> {
+    let featured = match &actor.featured {
+        Some(f) => f,
+        None => return Ok(Vec::new()),
+    };
+    let mut walker = Walker::new(featured)
+        .max_pages(3)
+        .max_items(MAX_PINNED as usize * 4);
+    let mut resolver = Resolver::new();
+    if let Some(v) = viewer {
+        walker = walker.signed_by(v);
+        resolver = resolver.signed_by(v);
+    }
+
+    let mut notes = Vec::new();
+    for item in walker.collect().await? {
+        let note = match item {
+            IdOrObject::Id(id) if same_origin(&id, &actor.id) => {
+                match resolver.load(&id).await {
+                    Ok(n) => n,
+                    Err(e) => {
+                        debug!("featured item {id}: {e}");
+                        continue;
+                    }
+                }
+            }
+            IdOrObject::Id(_) => continue,
+            IdOrObject::Object(o) => {
+                let mut note: Note =
+                    match serde_json::from_value(context::normalize(*o)) {
+                        Ok(n) => n,
+                        Err(_) => continue,
+                    };
+                crate::sanitize::object(&mut note);
+                let id = note.id.as_deref().unwrap_or_default();
+                if !same_origin(id, &actor.id) || !authored_on_origin(&note) {
+                    continue;
+                }
+                crate::thread::store(&note).await?;
+                note
+            }
+        };
+        if note.attributed_to_id() == Some(actor.id.as_str()) {
+            notes.push(note);
+        }
+    }
+    Ok(notes)
+}
+
+pub fn featured_url(user: &str) -> String {
+    format!("{}/collections/featured", user.trim_end_matches('/'))
+}
+
+async fn is_local(id: &str) -> bool {
+    let qr = crate::db::Connection::builder()
+        .await
+        .execute(
+            "SELECT 1 FROM user WHERE federationId = ?",
+            &[SV::Text(id.to_string())],
+        )
+        .await;
+    !qr.rows.is_empty()
+}
+
+// Ids of the statuses pinned by local `user`, last pinned first.
+async fn pinned_ids(user: &str) -> Vec<String> {
+    let qr = crate::db::Connection::builder()
+        .await
+        .execute(
+            "SELECT federationId FROM pinned_status WHERE userId = (SELECT id FROM user WHERE federationId = ?) ORDER BY rowid DESC",
+            &[SV::Text(user.to_string())],
+        )
+        .await;
+    let ids = qr
+        .rows()
+        .filter_map(|r| r.get::<&str>("federationId").map(|i| i.to_string()))
+        .collect();
+    ids
+}
+
+// Stored objects for `ids`, by id.
+async fn current(ids: &[String]) -> HashMap<String, Value> {
+    if ids.is_empty() {
+        return HashMap::new();
+    }
+    let params: Vec<SV> = ids.iter().map(|i| SV::Text(i.clone())).collect();
+    let qr = crate::db::Connection::builder()
+        .await
+        .execute(
+            &format!(
+                "SELECT federationId, object FROM thread_object WHERE federationId IN ({})",
+                crate::db::placeholders(ids.len())
+            ),
+            &params,
+        )
+        .await;
+    let objects = qr
+        .rows()
+        .filter_map(|r| {
+            let object = serde_json::from_str(r.get::<&str>("object")?).ok()?;
+            Some((r.get::<&str>("federationId")?.to_string(), object))
+        })
+        .collect();
+    objects
+}
+
+async fn pinned_count(user: &str) -> i64 {
+    let qr = crate::db::Connection::builder()
+        .await
+        .execute(
+            "SELECT COUNT(*) AS count FROM pinned_status WHERE userId = (SELECT id FROM user WHERE federationId = ?)",
+            &[SV::Text(user.to_string())],
+        )
+        .await;
+    let count = qr
+        .rows()
+        .next()
+        .and_then(|r| r.get::<i64>("count"))
+        .unwrap_or_default();
+    count
+}
+
+// Add or Remove `note_id` to/from our featured collection, sent to followers.
+async fn announce(
+    kind: ActivityType,
+    my_actor: &Url,
+    note_id: String,
+) -> Result<()> {
+    let host = match my_actor.host_str() {
+        Some(h) => h,
+        None => {
+            return Err(anyhow::Error::msg(format!("no host in {my_actor}")))
+        }
+    };
+    let id = format!("{}://{host}/{}", my_actor.scheme(), Uuid::now_v7());
+    let actor = my_actor.to_string();
+    let mut activity = Activity::new(kind, id, actor.clone(), note_id);
+    activity.target = Some(IdOrObject::Id(featured_url(&actor)));
+    activity.to = PUBLIC.to_string().into();
+    activity.cc = format!("{}/followers", actor.trim_end_matches('/')).into();
+    let body = serde_json::to_string(&activity)?;
+    debug!("featured -> {body}");
+
+    let qr = crate::db::Connection::builder()
+        .await
+        .execute(
+            "SELECT id FROM user WHERE federationId = ?",
+            &[SV::Text(actor.clone())],
+        )
+        .await;
+    let user_id = match qr.rows().next().and_then(|r| r.get::<i64>("id")) {
+        Some(id) => id,
+        None => {
+            return Err(anyhow::Error::msg(format!("unknown user {actor}")))
+        }
+    };
+
+    let inboxes = crate::inbox::follower_inboxes(user_id, None).await?;
+    crate::delivery::enqueue(&actor, &inboxes, &body).await;
+    if let Err(e) = crate::delivery::run(inboxes.len()).await {
+        tracing::error!("delivering {}: {e}", activity.id);
+    }
+    Ok(())
+}
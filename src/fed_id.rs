@@ -1,35 +1,324 @@
-use anyhow;
+// Federation ids
+// https://www.rfc-editor.org/rfc/rfc7033 (WebFinger)
+// https://www.rfc-editor.org/rfc/rfc6415 (host-meta, LRDD)
+// https://docs.joinmastodon.org/spec/webfinger/
+//
+// Ways people write an account, all resolving to its actor url:
+//
+// @seungjin@mstd.seungjin.net
+// seungjin@mstd.seungjin.net
+// mstd.seungjin.net/@seungjin
+// https://mstd.seungjin.net/users/seungjin
+// https://mstd.seungjin.net/@seungjin
+//
+// Handles go through WebFinger on their host. When the host doesn't answer
+// it, host-meta tells where its LRDD (WebFinger) endpoint is. The subject we
+// get back is the account's canonical handle, possibly on another domain
+// (example.com delegating to social.example.com): it must resolve to the
+// same actor before we believe it. Results are cached.
+//
+// CREATE TABLE webfinger_cache (
+//     acct TEXT PRIMARY KEY,       -- user@host as queried, lowercase
+//     actor TEXT NOT NULL,
+//     subject TEXT NOT NULL,       -- canonical user@host
+//     fetchedAt TEXT NOT NULL
+// );
 
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, Duration, Utc};
 use regex::Regex;
+use spin_sdk::http::{Method, Request, Response};
+use spin_sdk::sqlite::Value as SV;
+use std::fmt;
+use std::str::FromStr;
+use thiserror::Error;
+use tracing::debug;
+use url::Url;
 
-// @seungjin@mstd.seungjin.net ->
-// seungjin@mstd.seungjin.net ->
-// mstd.seungjin.net/@seungjin
-// https://mstd.seungjin.net/users/seungjin ->
-// https://mstd.seungjin.net/@seungjin ->
+use crate::utils::get_current_time_in_iso_8601;
+use crate::webfinger::Jrd;
 
-struct FederationId {
-    id: String,
+const CACHE_DAYS: i64 = 3;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum FederationId {
+    Acct { user: String, host: String },
+    Url(Url),
 }
 
-impl From for FederationId {
-    
-    fn from_string(arg: String) -> self {
+#[derive(Error, Debug, PartialEq, Eq)]
+#[error("not a federation id: {0}")]
+pub struct ParseError(String);
+
+impl FromStr for FederationId {
+    type Err = ParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let err = || ParseError(s.to_string());
+
+        if s.starts_with("https://") || s.starts_with("http://") {
+            let url = Url::parse(s).map_err(|_| err())?;
+            if url.host_str().is_none() {
+                return Err(err());
+            }
+            return Ok(FederationId::Url(url));
+        }
+
+        // Groups are addressed as !community@host
+        let (user, host) = match s.split_once("/@") {
+            Some((host, user)) => (user, host),
+            None => match s.trim_start_matches(['@', '!']).split_once('@') {
+                Some((user, host)) => (user, host),
+                None => return Err(err()),
+            },
+        };
+        let user_re = Regex::new(r"^[\w.~-]+$").unwrap();
+        let host_re = Regex::new(r"^[a-zA-Z0-9.-]+(:\d+)?$").unwrap();
+        if !user_re.is_match(user) || !host_re.is_match(host) {
+            return Err(err());
+        }
+        Ok(FederationId::Acct {
+            user: user.to_string(),
+            host: host.to_lowercase(),
+        })
+    }
+}
+
+impl fmt::Display for FederationId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            FederationId::Acct { user, host } => write!(f, "{user}@{host}"),
+            FederationId::Url(url) => write!(f, "{url}"),
+        }
+    }
+}
+
+impl FederationId {
+    pub fn host(&self) -> &str {
+        match self {
+            FederationId::Acct { host, .. } => host,
+            FederationId::Url(url) => url.host_str().unwrap_or_default(),
+        }
+    }
+
+    // user@host, for handles.
+    pub fn acct(&self) -> Option<String> {
+        match self {
+            FederationId::Acct { .. } => Some(self.to_string()),
+            FederationId::Url(_) => None,
+        }
+    }
+
+    // Actor url.
+    pub async fn resolve(&self) -> Result<String> {
+        match self {
+            FederationId::Acct { .. } => {
+                let acct = self.to_string().to_lowercase();
+                if let Some(actor) = cached(&acct).await {
+                    return Ok(actor);
+                }
+                let (actor, subject) = resolve_acct(&acct).await?;
+                cache(&acct, &actor, &subject).await;
+                Ok(actor)
+            }
+            FederationId::Url(url) => resolve_url(url).await,
+        }
+    }
+}
 
-        // starting with http, need to http request
-        if start_with_http(arg) {
+// (actor, subject) for `acct` (user@host).
+async fn resolve_acct(acct: &str) -> Result<(String, String)> {
+    let jrd = webfinger(acct).await?;
+    let actor = jrd
+        .actor()
+        .ok_or_else(|| anyhow!("no actor link for {acct}"))?
+        .to_string();
 
+    let subject = jrd
+        .subject
+        .strip_prefix("acct:")
+        .unwrap_or(acct)
+        .to_lowercase();
+    if subject != acct {
+        // Delegated domain, or the host answered for someone else. The
+        // subject's own host must agree.
+        let confirmed = webfinger(&subject).await?;
+        if confirmed.actor() != Some(actor.as_str()) {
+            return Err(anyhow!(
+                "{acct}: subject {subject} doesn't round-trip"
+            ));
         }
+    }
+    Ok((actor, subject))
+}
+
+async fn webfinger(acct: &str) -> Result<Jrd> {
+    let host = acct.rsplit('@').next().unwrap_or_default();
+    let resource = urlencoding::encode(&format!("acct:{acct}")).into_owned();
+
+    let url =
+        format!("https://{host}/.well-known/webfinger?resource={resource}");
+    match fetch_jrd(&url).await {
+        Ok(jrd) => return Ok(jrd),
+        Err(e) => debug!("webfinger {acct}: {e}, trying host-meta"),
+    }
+
+    let template = lrdd_template(host).await?;
+    fetch_jrd(&template.replace("{uri}", &resource)).await
+}
 
+async fn fetch_jrd(url: &str) -> Result<Jrd> {
+    let (status, content_type, body) =
+        get(url, "application/jrd+json, application/json").await?;
+    if status != 200 {
+        return Err(anyhow!("{url}: {status}"));
     }
+    if !content_type.contains("json") {
+        return Err(anyhow!("{url}: not a JRD ({content_type})"));
+    }
+    Ok(serde_json::from_str(&body)?)
+}
+
+// LRDD template from the host's host-meta, XRD or JSON.
+async fn lrdd_template(host: &str) -> Result<String> {
+    let url = format!("https://{host}/.well-known/host-meta");
+    let (status, content_type, body) =
+        get(&url, "application/xrd+xml, application/json").await?;
+    if status != 200 {
+        return Err(anyhow!("{url}: {status}"));
+    }
+    let template = if content_type.contains("json") {
+        let jrd: serde_json::Value = serde_json::from_str(&body)?;
+        jrd.get("links")
+            .and_then(|l| l.as_array())
+            .and_then(|links| {
+                links.iter().find(|l| {
+                    l.get("rel").and_then(|r| r.as_str()) == Some("lrdd")
+                })
+            })
+            .and_then(|l| l.get("template"))
+            .and_then(|t| t.as_str())
+            .map(|t| t.to_string())
+    } else {
+        xrd_lrdd(&body)
+    };
+    template
+        .filter(|t| t.starts_with("https://") && t.contains("{uri}"))
+        .ok_or_else(|| anyhow!("{host}: no LRDD template"))
+}
+
+fn xrd_lrdd(xrd: &str) -> Option<String> {
+    let link = Regex::new(r"<Link\b[^>]*>").unwrap();
+    let rel = Regex::new(r#"\brel\s*=\s*["']lrdd["']"#).unwrap();
+    let template = Regex::new(r#"\btemplate\s*=\s*["']([^"']+)["']"#).unwrap();
+    let lrdd = link
+        .find_iter(xrd)
+        .map(|l| l.as_str())
+        .filter(|l| rel.is_match(l))
+        .find_map(|l| template.captures(l))
+        .map(|c| c[1].replace("&amp;", "&"));
+    lrdd
 }
 
-async fn start_with_http(a: String) -> bool {
-    let re = Regex::new(r"^(http|https):\/\/").unwrap();
-    re.is_match(a)
+// Actor behind an actor or profile url. Mastodon serves the actor at its
+// profile page too, when asked for ActivityPub.
+async fn resolve_url(url: &Url) -> Result<String> {
+    let actor = crate::remote_actor::lookup(url.as_str()).await?;
+    Ok(actor.id().to_string())
 }
 
-pub async fn validate_fed_id() -> Result<boolean> {
-    Ok(true)
+pub(crate) async fn get(
+    url: &str,
+    accept: &str,
+) -> Result<(u16, String, String)> {
+    let req = Request::builder()
+        .method(Method::Get)
+        .uri(url)
+        .header("Accept", accept)
+        .build();
+    let resp: Response = spin_sdk::http::send(req).await?;
+    let content_type = resp
+        .header("Content-Type")
+        .and_then(|c| c.as_str())
+        .unwrap_or_default()
+        .to_string();
+    let body = String::from_utf8(resp.body().to_vec())?;
+    Ok((*resp.status(), content_type, body))
 }
 
+async fn cached(acct: &str) -> Option<String> {
+    let qr = crate::db::Connection::builder()
+        .await
+        .execute(
+            "SELECT actor, fetchedAt FROM webfinger_cache WHERE acct = ?",
+            &[SV::Text(acct.to_string())],
+        )
+        .await;
+    let row = qr.rows().next()?;
+    let fetched_at: DateTime<Utc> =
+        row.get::<&str>("fetchedAt")?.parse().ok()?;
+    if Utc::now() - fetched_at > Duration::days(CACHE_DAYS) {
+        return None;
+    }
+    row.get::<&str>("actor").map(|a| a.to_string())
+}
+
+async fn cache(acct: &str, actor: &str, subject: &str) {
+    let _ = crate::db::Connection::builder()
+        .await
+        .execute(
+            "INSERT INTO webfinger_cache(acct, actor, subject, fetchedAt) VALUES(?,?,?,?) ON CONFLICT(acct) DO UPDATE SET actor = excluded.actor, subject = excluded.subject, fetchedAt = excluded.fetchedAt",
+            &[
+                SV::Text(acct.to_string()),
+                SV::Text(actor.to_string()),
+                SV::Text(subject.to_string()),
+                SV::Text(get_current_time_in_iso_8601().await),
+            ],
+        )
+        .await;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn forms() {
+        let acct = FederationId::Acct {
+            user: "seungjin".to_string(),
+            host: "mstd.seungjin.net".to_string(),
+        };
+        for s in [
+            "@seungjin@mstd.seungjin.net",
+            "seungjin@mstd.seungjin.net",
+            "mstd.seungjin.net/@seungjin",
+            "seungjin@MSTD.seungjin.net",
+        ] {
+            assert_eq!(s.parse::<FederationId>(), Ok(acct.clone()), "{s}");
+        }
+        assert!(matches!(
+            "https://mstd.seungjin.net/users/seungjin".parse(),
+            Ok(FederationId::Url(_))
+        ));
+        assert!(matches!(
+            "https://mstd.seungjin.net/@seungjin".parse(),
+            Ok(FederationId::Url(_))
+        ));
+        for s in ["seungjin", "@seungjin", "a b@host", "u@", "https://"] {
+            assert!(s.parse::<FederationId>().is_err(), "{s}");
+        }
+    }
+
+    #[test]
+    fn host_meta() {
+        let xrd = r#"<?xml version="1.0" encoding="UTF-8"?>
+<XRD xmlns="http://docs.oasis-open.org/ns/xri/xrd-1.0">
+  <Link template="https://social.example.com/.well-known/webfinger?resource={uri}&amp;x=1" type="application/xrd+xml" rel="lrdd"/>
+</XRD>"#;
+        assert_eq!(
+            xrd_lrdd(xrd).as_deref(),
+            Some("https://social.example.com/.well-known/webfinger?resource={uri}&x=1")
+        );
+    }
+}
@@ -0,0 +1,148 @@
+// Flag activity (reports)
+// https://www.w3.org/TR/activitystreams-vocabulary/#dfn-flag
+// https://docs.joinmastodon.org/spec/activitypub/#Flag
+//
+// Inbound reports land in the `report` table for moderators.
+// Outbound reports are delivered to the reported actor's server.
+
+use anyhow::Result;
+use serde_json::Value;
+use spin_sdk::sqlite::Value as SV;
+use tracing::debug;
+use url::Url;
+use uuid::Uuid;
+
+use crate::apo::{ActivityType, Flag};
+
+pub async fn received(body: &str) -> Result<()> {
+    let flag: Flag = crate::apo::context::from_str(body)?;
+    let objects = flag.get_objects();
+
+    // First object is the reported account. The rest are statuses.
+    let (account, statuses) = match objects.split_first() {
+        Some((account, statuses)) => (account.to_owned(), statuses.to_vec()),
+        None => return Err(anyhow::Error::msg("Flag without object")),
+    };
+    debug!("report on {account} by {}", flag.actor);
+
+    let qr = crate::db::Connection::builder()
+        .await
+        .execute(
+            "SELECT id FROM user WHERE federationId = ?",
+            &[SV::Text(account.clone())],
+        )
+        .await;
+    if qr.rows.is_empty() {
+        return Err(anyhow::Error::msg(format!(
+            "reported account is not local: {account}"
+        )));
+    }
+
+    let _ = crate::db::Connection::builder()
+        .await
+        .execute(
+            r#"INSERT OR IGNORE INTO report(federationId, actor, userId, statuses, content, object, createdAt) VALUES(?,?,(SELECT id FROM user WHERE federationId = ?),json(?),?,json(?),?)"#,
+            &[
+                SV::Text(flag.id.clone()),
+                SV::Text(flag.actor.clone()),
+                SV::Text(account),
+                SV::Text(Value::from(statuses).to_string()),
+                SV::Text(flag.content.clone().unwrap_or_default()),
+                SV::Text(body.to_string()),
+                SV::Text(crate::utils::get_current_time_in_iso_8601().await),
+            ],
+        )
+        .await;
+
+    Ok(())
+}
+
+// Report `target_actor` (and optionally some of its statuses) to its server.
+// Sent by the instance actor of `my_actor`'s server, the reporter stays
+// anonymous.
+pub async fn report(
+    my_actor: Url,
+    target_actor: Url,
+    statuses: Vec<String>,
+    comment: String,
+) -> Result<u16> {
+    let base = my_actor.origin().ascii_serialization();
+    let instance = crate::instance::actor_id(&base);
+    let id = format!("{base}/{}", Uuid::now_v7());
+    let flag = flag(id, &instance, &target_actor, statuses, comment);
+    let body = serde_json::to_string(&flag)?;
+    debug!("flag -> {body}");
+
+    let inbox = crate::remote_actor::get(target_actor.as_str())
+        .await?
+        .actor
+        .inbox;
+    crate::send::deliver(&instance, &inbox, &body).await
+}
+
+// The reported account first, then its statuses. Nothing about the
+// reporter but the comment.
+fn flag(
+    id: String,
+    actor: &str,
+    target_actor: &Url,
+    statuses: Vec<String>,
+    comment: String,
+) -> Flag {
+    let mut object = vec![target_actor.to_string()];
+    object.extend(statuses);
+
+    let mut flag =
+        Flag::new(ActivityType::Flag, id, actor.to_string(), object.into());
+    flag.content = Some(comment);
+    flag
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn outbound() {
+        let target = Url::parse("https://remote.example/users/bob").unwrap();
+        let flag = flag(
+            "https://sparrow.example/1".to_string(),
+            "https://sparrow.example/actor",
+            &target,
+            vec!["https://remote.example/notes/1".to_string()],
+            "spam".to_string(),
+        );
+        let v = serde_json::to_value(&flag).unwrap();
+        assert_eq!(v["type"], "Flag");
+        assert_eq!(v["actor"], "https://sparrow.example/actor");
+        assert_eq!(
+            flag.get_objects(),
+            vec![
+                "https://remote.example/users/bob",
+                "https://remote.example/notes/1"
+            ]
+        );
+        assert_eq!(v["content"], "spam");
+    }
+
+    #[test]
+    fn inbound() {
+        // As Mastodon sends it, object as a plain string when alone.
+        let flag: Flag = crate::apo::context::from_str(
+            r#"{
+              "@context": "https://www.w3.org/ns/activitystreams",
+              "id": "https://mastodon.example/0b2c",
+              "type": "Flag",
+              "actor": "https://mastodon.example/actor",
+              "content": "Please take a look",
+              "object": "https://sparrow.example/users/alice"
+            }"#,
+        )
+        .unwrap();
+        assert_eq!(
+            flag.get_objects(),
+            vec!["https://sparrow.example/users/alice"]
+        );
+        assert_eq!(flag.content.as_deref(), Some("Please take a look"));
+    }
+}
@@ -17,6 +17,7 @@ use url::Url;
 use uuid::Uuid;
 
 use crate::utils::get_current_time_in_rfc_1123;
+use crate::utils::get_inbox_from_actor;
 
 pub async fn following_request(
     my_actor: Url,
@@ -71,7 +72,11 @@ pub async fn following_request(
     );
     debug!("digest --> {digest}");
 
-    let recipient_server: &str = recipient_actor.host_str().unwrap();
+    // Groups (Lemmy communities, friendica forums, ...) don't keep their inbox
+    // under the actor url, so use the one the actor advertises.
+    let inbox =
+        Url::parse(&get_inbox_from_actor(recipient_actor.to_string()).await?)?;
+    let recipient_server: &str = inbox.host_str().unwrap();
 
     // FIXME: Doing this becaues Spin add port into its Host header value
     let hostname = recipient_server.to_string();
@@ -79,8 +84,7 @@ pub async fn following_request(
     //    hostname = format!("{}:443", hostname);
     //}
 
-    // TODO: inbox path should get from actor (actor created from http request)
-    let inbox_path = format!("{}/inbox", recipient_actor.path());
+    let inbox_path = inbox.path();
     //let inbox_path = "/inbox";
     let signature_string = format!(
         "(request-target): post {}\nhost: {}\ndate: {}\ndigest: {}\ncontent-type: {}",
@@ -108,17 +112,14 @@ pub async fn following_request(
 
     debug!("sig_header --> {sig_header}");
 
-    let request = RequestBuilder::new(
-        Method::Post,
-        format!("{}/inbox", recipient_actor),
-    ) // TODO: recipient uri should get from actor.
-    .header("Date", date)
-    .header("Signature", sig_header)
-    .header("Digest", digest)
-    .header("Content-Type", &content_type)
-    .header("Accept", &content_type)
-    .body(request_body.to_string())
-    .build();
+    let request = RequestBuilder::new(Method::Post, inbox.as_str())
+        .header("Date", date)
+        .header("Signature", sig_header)
+        .header("Digest", digest)
+        .header("Content-Type", &content_type)
+        .header("Accept", &content_type)
+        .body(request_body.to_string())
+        .build();
     let response: IncomingResponse = http::send(request).await?;
     let status = response.status();
 
@@ -127,7 +128,8 @@ pub async fn following_request(
     debug!("response body -->\n{body}");
 
     // INSERT INTO DB
-    if status == 202u16 {
+    // Mastodon answers 202, Lemmy 200.
+    if (200..300).contains(&status) {
         let object = json!({
             "id": id,
             "type": "Follow",
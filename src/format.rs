@@ -0,0 +1,438 @@
+// Rendering local posts
+// https://docs.joinmastodon.org/spec/activitypub/#Mention
+// https://docs.joinmastodon.org/spec/activitypub/#Hashtag
+//
+// What a local user types becomes the note's html `content`, the way
+// Mastodon renders it: paragraphs, links, mentions as h-card links and
+// hashtags as tag links. Mentions are resolved with WebFinger (or from our
+// own users) and go into `tag` and the addressing, hashtags into `tag`.
+// The text itself is kept in `source`.
+//
+// Markdown is a subset: emphasis, strikethrough, code, quotes, lists and
+// links. Headings render as bold paragraphs, raw html as text, images as
+// links. Only tags our sanitizer allows come out.
+
+use anyhow::Result;
+use once_cell::sync::Lazy;
+use pulldown_cmark::{
+    CowStr, Event, Options, Parser, Tag as MdTag, TagEnd, TextMergeStream,
+};
+use regex::Regex;
+use spin_sdk::sqlite::Value as SV;
+use std::collections::HashMap;
+use tracing::debug;
+use url::Url;
+
+use crate::apo::{Hashtag, Mention, Note, Source, Tag};
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Format {
+    #[default]
+    Plain,
+    Markdown,
+}
+
+impl Format {
+    // Mastodon's `content_type` status parameter.
+    pub fn from_content_type(content_type: Option<&str>) -> Self {
+        match content_type {
+            Some("text/markdown") => Format::Markdown,
+            _ => Format::Plain,
+        }
+    }
+
+    pub fn media_type(&self) -> &'static str {
+        match self {
+            Format::Plain => "text/plain",
+            Format::Markdown => "text/markdown",
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Formatted {
+    pub html: String,
+    pub source: Source,
+    pub mentions: Vec<Mention>,
+    pub hashtags: Vec<Hashtag>,
+}
+
+impl Formatted {
+    // Set content, source, tags and mention addressing of `note`. With a
+    // `language` (ISO 639-1) content and summary also go into their maps, so
+    // set the summary first. The maps hold html like `content`, the plain
+    // text is in `source`. Mentioned actors are cc'd, or addressed directly
+    // when the note has no other audience yet.
+    pub fn apply(self, note: &mut Note, language: Option<&str>) {
+        note.content = Some(self.html);
+        note.source = Some(self.source);
+        if let Some(lang) = language {
+            note.set_language(lang);
+        }
+
+        let direct = note.to.is_empty();
+        for m in self.mentions {
+            let addressed =
+                note.to.iter().chain(note.cc.iter()).any(|a| *a == m.href);
+            if !addressed {
+                if direct {
+                    note.to.push(m.href.clone());
+                } else {
+                    note.cc.push(m.href.clone());
+                }
+            }
+            note.tag.push(Tag::Mention(m));
+        }
+        for h in self.hashtags {
+            note.tag.push(Tag::Hashtag(h));
+        }
+    }
+}
+
+// `text` as typed by a user of this server, `base` (https://host).
+pub async fn format(
+    text: &str,
+    format: Format,
+    base: &str,
+) -> Result<Formatted> {
+    let host = Url::parse(base)?.host_str().unwrap_or_default().to_string();
+    let mentions = resolve_mentions(text, &host).await;
+
+    let mut linkifier = Linkifier {
+        base,
+        mentions: &mentions,
+        hashtags: Vec::new(),
+        used: Vec::new(),
+    };
+    let html = match format {
+        Format::Plain => plain(text, &mut linkifier),
+        Format::Markdown => markdown(text, &mut linkifier),
+    };
+
+    let mut hashtags: Vec<Hashtag> = Vec::new();
+    for name in linkifier.hashtags {
+        hashtags.push(Hashtag {
+            kind: "Hashtag".to_string(),
+            href: Some(format!("{base}/tags/{}", name.to_lowercase())),
+            name: format!("#{name}"),
+        });
+    }
+    let mentions = linkifier
+        .used
+        .iter()
+        .filter_map(|h| mentions.get(h))
+        .map(|(href, acct)| Mention {
+            kind: "Mention".to_string(),
+            href: href.to_owned(),
+            name: Some(format!("@{acct}")),
+        })
+        .collect();
+
+    Ok(Formatted {
+        html,
+        source: Source {
+            content: text.to_string(),
+            media_type: format.media_type().to_string(),
+        },
+        mentions,
+        hashtags,
+    })
+}
+
+// handle as typed (without '@') -> (actor id, user@host)
+type Mentions = HashMap<String, (String, String)>;
+
+static MENTION: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(
+        r"(?i)(^|[^/\w])@([a-z0-9_]+(?:[a-z0-9_.-]*[a-z0-9_])?)(?:@([a-z0-9.-]+\.[a-z]{2,}))?",
+    )
+    .unwrap()
+});
+
+// Urls, mentions and hashtags in running text.
+static LINKS: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(
+        r"(?i)(https?://[^\s<>]+[^\s<>.,:;!?)\]'])|(?:^|[^/\w])(@[a-z0-9_]+(?:[a-z0-9_.-]*[a-z0-9_])?(?:@[a-z0-9.-]+\.[a-z]{2,})?)|(?:^|[^/\w&])(#\w*[\p{L}_]\w*)",
+    )
+    .unwrap()
+});
+
+// Mentions that can't be resolved stay plain text.
+async fn resolve_mentions(text: &str, host: &str) -> Mentions {
+    let mut mentions = Mentions::new();
+    for c in MENTION.captures_iter(text) {
+        let user = c.get(2).map(|m| m.as_str()).unwrap_or_default();
+        let domain = c.get(3).map(|m| m.as_str());
+        let handle = match domain {
+            Some(d) => format!("{user}@{d}"),
+            None => user.to_string(),
+        };
+        if mentions.contains_key(&handle) {
+            continue;
+        }
+        let actor = match domain {
+            Some(d) if !d.eq_ignore_ascii_case(host) => {
+                match crate::utils::get_actor_url_from_id(handle.clone()).await
+                {
+                    Ok(a) => Some(a),
+                    Err(e) => {
+                        debug!("mention {handle}: {e}");
+                        None
+                    }
+                }
+            }
+            _ => local_user(user).await,
+        };
+        if let Some(a) = actor {
+            let acct = format!("{user}@{}", domain.unwrap_or(host));
+            mentions.insert(handle, (a, acct));
+        }
+    }
+    mentions
+}
+
+async fn local_user(name: &str) -> Option<String> {
+    let qr = crate::db::Connection::builder()
+        .await
+        .execute(
+            "SELECT federationId FROM user WHERE name = ?",
+            &[SV::Text(name.to_string())],
+        )
+        .await;
+    let id = qr
+        .rows()
+        .next()
+        .and_then(|r| r.get::<&str>("federationId"))
+        .map(|f| f.to_string());
+    id
+}
+
+struct Linkifier<'a> {
+    base: &'a str,
+    mentions: &'a Mentions,
+    hashtags: Vec<String>,
+    used: Vec<String>,
+}
+
+impl Linkifier<'_> {
+    // Escaped `text` with urls, mentions and hashtags turned into links.
+    fn linkify(&mut self, text: &str) -> String {
+        let mut html = String::new();
+        let mut last = 0;
+        for c in LINKS.captures_iter(text) {
+            let (m, link) = if let Some(u) = c.get(1) {
+                (u, Some(url_link(u.as_str())))
+            } else if let Some(m) = c.get(2) {
+                (m, self.mention(&m.as_str()[1..]))
+            } else if let Some(h) = c.get(3) {
+                (h, Some(self.hashtag(&h.as_str()[1..])))
+            } else {
+                continue;
+            };
+            if let Some(link) = link {
+                html.push_str(&escape(&text[last..m.start()]));
+                html.push_str(&link);
+                last = m.end();
+            }
+        }
+        html.push_str(&escape(&text[last..]));
+        html
+    }
+
+    fn mention(&mut self, handle: &str) -> Option<String> {
+        let (href, acct) = self.mentions.get(handle)?;
+        if !self.used.iter().any(|u| u == handle) {
+            self.used.push(handle.to_string());
+        }
+        let user = acct.split('@').next().unwrap_or_default();
+        Some(format!(
+            r#"<span class="h-card" translate="no"><a href="{}" class="u-url mention">@<span>{}</span></a></span>"#,
+            escape(href),
+            escape(user)
+        ))
+    }
+
+    fn hashtag(&mut self, name: &str) -> String {
+        if !self.hashtags.iter().any(|h| h.eq_ignore_ascii_case(name)) {
+            self.hashtags.push(name.to_string());
+        }
+        format!(
+            r#"<a href="{}/tags/{}" class="mention hashtag" rel="tag">#<span>{}</span></a>"#,
+            self.base,
+            escape(&name.to_lowercase()),
+            escape(name)
+        )
+    }
+}
+
+fn url_link(url: &str) -> String {
+    let shown = url.split_once("://").map(|(_, r)| r).unwrap_or(url);
+    format!(
+        r#"<a href="{0}" rel="nofollow noopener" target="_blank">{1}</a>"#,
+        escape(url),
+        escape(shown)
+    )
+}
+
+// Paragraphs at blank lines, <br> at line breaks.
+fn plain(text: &str, linkifier: &mut Linkifier) -> String {
+    let text = text.replace("\r\n", "\n");
+    text.split("\n\n")
+        .map(str::trim)
+        .filter(|p| !p.is_empty())
+        .map(|p| {
+            let lines: Vec<String> =
+                p.lines().map(|l| linkifier.linkify(l)).collect();
+            format!("<p>{}</p>", lines.join("<br>"))
+        })
+        .collect()
+}
+
+fn markdown(text: &str, linkifier: &mut Linkifier) -> String {
+    let parser = Parser::new_ext(text, Options::ENABLE_STRIKETHROUGH);
+    let mut events: Vec<Event> = Vec::new();
+    // Inside code or a link, text is left alone.
+    let mut verbatim = 0;
+
+    for event in TextMergeStream::new(parser) {
+        match event {
+            Event::Start(MdTag::Heading { .. }) => {
+                events.push(Event::Start(MdTag::Paragraph));
+                events.push(Event::Start(MdTag::Strong));
+            }
+            Event::End(TagEnd::Heading(_)) => {
+                events.push(Event::End(TagEnd::Strong));
+                events.push(Event::End(TagEnd::Paragraph));
+            }
+            Event::Start(MdTag::Link {
+                link_type,
+                dest_url,
+                title,
+                id,
+            }) => {
+                verbatim += 1;
+                let dest_url = safe_url(dest_url);
+                events.push(Event::Start(MdTag::Link {
+                    link_type,
+                    dest_url,
+                    title,
+                    id,
+                }));
+            }
+            Event::End(TagEnd::Link) => {
+                verbatim -= 1;
+                events.push(Event::End(TagEnd::Link));
+            }
+            // An image is a link to it, its alt text the link text.
+            Event::Start(MdTag::Image {
+                link_type,
+                dest_url,
+                title,
+                id,
+            }) => {
+                verbatim += 1;
+                events.push(Event::Start(MdTag::Link {
+                    link_type,
+                    dest_url: safe_url(dest_url),
+                    title,
+                    id,
+                }));
+            }
+            Event::End(TagEnd::Image) => {
+                verbatim -= 1;
+                events.push(Event::End(TagEnd::Link));
+            }
+            Event::Start(MdTag::CodeBlock(_)) => {
+                verbatim += 1;
+                events.push(Event::Html("<pre><code>".into()));
+            }
+            Event::End(TagEnd::CodeBlock) => {
+                verbatim -= 1;
+                events.push(Event::Html("</code></pre>".into()));
+            }
+            Event::Text(t) if verbatim == 0 => {
+                events.push(Event::Html(linkifier.linkify(&t).into()));
+            }
+            Event::Html(h) | Event::InlineHtml(h) => {
+                events.push(Event::Text(h));
+            }
+            Event::SoftBreak => events.push(Event::Html("<br>".into())),
+            Event::Rule => {}
+            e => events.push(e),
+        }
+    }
+
+    let mut html = String::new();
+    pulldown_cmark::html::push_html(&mut html, events.into_iter());
+    html
+}
+
+// Links to http(s) only, others lead nowhere.
+fn safe_url(url: CowStr) -> CowStr {
+    let lower = url.to_ascii_lowercase();
+    if lower.starts_with("http://") || lower.starts_with("https://") {
+        url
+    } else {
+        CowStr::Borrowed("")
+    }
+}
+
+pub(crate) fn escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn linkifier(mentions: &Mentions) -> Linkifier<'_> {
+        Linkifier {
+            base: "https://sparrow.example",
+            mentions,
+            hashtags: Vec::new(),
+            used: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn plain_text() {
+        let mut mentions = Mentions::new();
+        mentions.insert(
+            "alice@remote.example".to_string(),
+            (
+                "https://remote.example/users/alice".to_string(),
+                "alice@remote.example".to_string(),
+            ),
+        );
+        let mut l = linkifier(&mentions);
+        let html = plain(
+            "hi @alice@remote.example & @nobody, see https://a.example/x.\n\n#Rust <3",
+            &mut l,
+        );
+        assert_eq!(
+            html,
+            r#"<p>hi <span class="h-card" translate="no"><a href="https://remote.example/users/alice" class="u-url mention">@<span>alice</span></a></span> &amp; @nobody, see <a href="https://a.example/x" rel="nofollow noopener" target="_blank">a.example/x</a>.</p><p><a href="https://sparrow.example/tags/rust" class="mention hashtag" rel="tag">#<span>Rust</span></a> &lt;3</p>"#
+        );
+        assert_eq!(l.used, vec!["alice@remote.example"]);
+        assert_eq!(l.hashtags, vec!["Rust"]);
+    }
+
+    #[test]
+    fn markdown_subset() {
+        let mentions = Mentions::new();
+        let mut l = linkifier(&mentions);
+        let html = markdown(
+            "# Title\n\n**bold** `#notag` <script>x</script> [a](javascript:x) ~~s~~ #tag",
+            &mut l,
+        );
+        assert!(html.contains("<p><strong>Title</strong></p>"));
+        assert!(html.contains("<code>#notag</code>"));
+        assert!(html.contains("&lt;script&gt;"));
+        assert!(!html.contains("javascript"));
+        assert!(html.contains("<del>s</del>"));
+        assert_eq!(l.hashtags, vec!["tag"]);
+    }
+}
@@ -0,0 +1,284 @@
+// Groups
+// https://codeberg.org/fediverse/fep/src/branch/main/fep/1b12/fep-1b12.md
+//
+// A Group (Lemmy community, friendica forum, ...) is followed like any other
+// actor. Members address their posts to the group, and the group wraps every
+// activity it accepts in an Announce for its followers:
+//
+//   Announce{actor: group, object: Create{actor: member, object: Page}}
+//
+// Inbound, `unwrap` hands back the member's activity so it is handled like
+// one delivered directly. Threading follows the wrapped object: inReplyTo for
+// the post or comment it answers, `audience` for the group it belongs to.
+//
+// Local groups relay their members' activities the same way. A local user
+// becomes a group with `create`.
+//
+// CREATE TABLE group_actor (
+//     userId INTEGER PRIMARY KEY,   -- user the group federates as
+//     createdAt TEXT NOT NULL
+// );
+
+use anyhow::{anyhow, Result};
+use serde_json::Value;
+use spin_sdk::sqlite::Value as SV;
+use tracing::debug;
+use url::Url;
+use uuid::Uuid;
+
+use crate::apo::context;
+use crate::apo::{
+    same_origin, AcceptedActivity, AcceptedTypes, Activity, ActivityType,
+    OriginError, PUBLIC,
+};
+use crate::utils::get_current_time_in_iso_8601;
+
+// Activities a group relays. Plain boosts are not among them.
+const RELAYED: [&str; 6] =
+    ["Create", "Update", "Delete", "Like", "Dislike", "Undo"];
+
+// The member's activity inside a group's Announce. None for anything else,
+// boosts of a post included.
+pub async fn unwrap(
+    activity: &AcceptedActivity,
+) -> Result<Option<AcceptedActivity>> {
+    if !matches!(activity.kind(), Some(AcceptedTypes::Announce)) {
+        return Ok(None);
+    }
+    // Groups embed the activity. An id only is a boost.
+    let embedded = match &activity.object {
+        Value::Object(o) if o.contains_key("actor") => {
+            context::normalize(activity.object.clone())
+        }
+        _ => return Ok(None),
+    };
+    match embedded.get("type").and_then(|t| t.as_str()) {
+        Some(t) if RELAYED.contains(&t) => {}
+        _ => return Ok(None),
+    }
+    let id = match embedded.get("id").and_then(|i| i.as_str()) {
+        Some(id) => id.to_string(),
+        None => return Err(OriginError::ActivityId(String::new()).into()),
+    };
+
+    // The group's copy is trusted for activities from its own server only.
+    let inner = if same_origin(&id, &activity.actor) {
+        embedded
+    } else {
+        let fetched =
+            context::normalize(crate::utils::fetch_object(&id).await?);
+        if fetched.get("id").and_then(|i| i.as_str()) != Some(id.as_str()) {
+            return Err(OriginError::ActivityId(id).into());
+        }
+        fetched
+    };
+
+    let mut inner: AcceptedActivity = serde_json::from_value(inner)?;
+    if !same_origin(&inner.id, &inner.actor) {
+        return Err(OriginError::ActivityId(inner.id).into());
+    }
+
+    // Not every server sets audience. The object belongs to this group.
+    if let Value::Object(o) = &mut inner.object {
+        if !o.contains_key("audience") {
+            o.insert(
+                "audience".to_string(),
+                Value::from(activity.actor.clone()),
+            );
+        }
+    }
+
+    debug!(
+        "{} announced {} by {}",
+        activity.actor, inner.id, inner.actor
+    );
+    Ok(Some(inner))
+}
+
+// A member's activity addressed to local group `group`. It goes out to the
+// group's followers in an Announce, embedded as received (`raw`) so its LD
+// signature stays valid. Returns the number of inboxes it was queued for.
+pub async fn relay(
+    group: &str,
+    activity: &AcceptedActivity,
+    raw: &str,
+) -> Result<usize> {
+    if !RELAYED.contains(&activity.kind.as_str()) {
+        return Ok(0);
+    }
+    let user_id = match local_group_id(group).await {
+        Some(id) => id,
+        None => return Ok(0),
+    };
+
+    let object: Value = context::normalize(serde_json::from_str(raw)?);
+    let mut recipients = crate::inbox::addressed(&object);
+    if let Some(o) = object.get("object") {
+        recipients.extend(crate::inbox::addressed(o));
+    }
+    if !recipients.iter().any(|r| r == group) {
+        return Ok(0);
+    }
+
+    // Members are the group's followers.
+    let qr = crate::db::Connection::builder()
+        .await
+        .execute(
+            "SELECT 1 FROM follower WHERE userId = ? AND federationId = ?",
+            &[SV::Integer(user_id), SV::Text(activity.actor.clone())],
+        )
+        .await;
+    if qr.rows.is_empty() {
+        debug!("{} is not a member of {group}", activity.actor);
+        return Ok(0);
+    }
+
+    let group_url = Url::parse(group)?;
+    let host = match group_url.host_str() {
+        Some(h) => h,
+        None => return Err(anyhow!("no host in {group}")),
+    };
+    let id = format!("{}://{host}/{}", group_url.scheme(), Uuid::now_v7());
+    let raw_object: Value = serde_json::from_str(raw)?;
+    let mut announce = Activity::new(
+        ActivityType::Announce,
+        id,
+        group.to_string(),
+        raw_object,
+    );
+    announce.to = format!("{group}/followers").into();
+    announce.cc = PUBLIC.to_string().into();
+    let body = serde_json::to_string(&announce)?;
+    debug!("group announce -> {body}");
+
+    let inboxes = crate::inbox::follower_inboxes(user_id, None).await?;
+    crate::delivery::enqueue(group, &inboxes, &body).await;
+    if let Err(e) = crate::delivery::run(inboxes.len()).await {
+        tracing::error!("relaying {}: {e}", activity.id);
+    }
+    Ok(inboxes.len())
+}
+
+// Make local user `federation_id` a group: served as a Group actor, relaying
+// what its members address to it.
+pub async fn create(federation_id: &str) -> Result<()> {
+    let qr = crate::db::Connection::builder()
+        .await
+        .execute(
+            "SELECT id FROM user WHERE federationId = ?",
+            &[SV::Text(federation_id.to_string())],
+        )
+        .await;
+    let user_id = match qr.rows().next().and_then(|r| r.get::<i64>("id")) {
+        Some(id) => id,
+        None => return Err(anyhow!("{federation_id} is not a local user")),
+    };
+    crate::db::Connection::builder()
+        .await
+        .execute(
+            "INSERT OR IGNORE INTO group_actor(userId, createdAt) VALUES(?,?)",
+            &[
+                SV::Integer(user_id),
+                SV::Text(get_current_time_in_iso_8601().await),
+            ],
+        )
+        .await;
+    Ok(())
+}
+
+pub async fn is_local_group(federation_id: &str) -> bool {
+    local_group_id(federation_id).await.is_some()
+}
+
+async fn local_group_id(federation_id: &str) -> Option<i64> {
+    let qr = crate::db::Connection::builder()
+        .await
+        .execute(
+            "SELECT user.id AS id FROM user JOIN group_actor ON group_actor.userId = user.id WHERE user.federationId = ?",
+            &[SV::Text(federation_id.to_string())],
+        )
+        .await;
+    let id = qr.rows().next().and_then(|r| r.get::<i64>("id"));
+    id
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    const GROUP: &str = "https://lemmy.example/c/birds";
+
+    fn announce(object: Value) -> AcceptedActivity {
+        AcceptedActivity {
+            id: "https://lemmy.example/activities/announce/1".to_string(),
+            kind: "Announce".to_string(),
+            actor: GROUP.to_string(),
+            object,
+            ..Default::default()
+        }
+    }
+
+    fn create(actor: &str) -> Value {
+        json!({
+            "id": "https://lemmy.example/activities/create/1",
+            "type": "Create",
+            "actor": actor,
+            "object": {
+                "id": "https://lemmy.example/post/1",
+                "type": "Page",
+                "attributedTo": actor,
+                "name": "Sparrows"
+            }
+        })
+    }
+
+    #[tokio::test]
+    async fn unwraps_member_activities() {
+        let member = "https://lemmy.example/u/alice";
+        let inner = unwrap(&announce(create(member))).await.unwrap().unwrap();
+        assert_eq!(inner.actor, member);
+        assert_eq!(inner.id, "https://lemmy.example/activities/create/1");
+        assert!(matches!(inner.kind(), Some(AcceptedTypes::Create)));
+        // Set from the announcing group when missing.
+        assert_eq!(inner.object["audience"], GROUP);
+
+        let mut with_audience = create(member);
+        with_audience["object"]["audience"] =
+            json!("https://lemmy.example/c/other");
+        let inner = unwrap(&announce(with_audience)).await.unwrap().unwrap();
+        assert_eq!(inner.object["audience"], "https://lemmy.example/c/other");
+    }
+
+    #[tokio::test]
+    async fn leaves_the_rest() {
+        // A boost.
+        let boost = announce(json!("https://lemmy.example/post/1"));
+        assert!(unwrap(&boost).await.unwrap().is_none());
+
+        // Not something groups relay.
+        let follow = announce(json!({
+            "id": "https://lemmy.example/activities/follow/1",
+            "type": "Follow",
+            "actor": "https://lemmy.example/u/alice",
+            "object": GROUP
+        }));
+        assert!(unwrap(&follow).await.unwrap().is_none());
+
+        let mut not_announce =
+            announce(create("https://lemmy.example/u/alice"));
+        not_announce.kind = "Create".to_string();
+        assert!(unwrap(&not_announce).await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn refuses_forged_activities() {
+        // The group's server speaking for an actor elsewhere.
+        let forged = announce(create("https://other.example/users/eve"));
+        assert!(unwrap(&forged).await.is_err());
+
+        let mut no_id = create("https://lemmy.example/u/alice");
+        no_id.as_object_mut().unwrap().remove("id");
+        assert!(unwrap(&announce(no_id)).await.is_err());
+    }
+}
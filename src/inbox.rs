@@ -0,0 +1,451 @@
+// Inbox processing
+//
+// Remote servers retry deliveries and relays duplicate them, so every inbound
+// activity is journaled by its `id` before it is processed. A row left in
+// `processing` for longer than STALE_AFTER belongs to a request that died on
+// the way, the next delivery of the activity takes it over.
+//
+// CREATE TABLE inbox_journal (
+//     id TEXT PRIMARY KEY,   -- activity id
+//     body TEXT NOT NULL,    -- raw json as received
+//     keyId TEXT NOT NULL,   -- keyId the http signature was verified with
+//     outcome TEXT NOT NULL,
+//     receivedAt TEXT NOT NULL,
+//     updatedAt TEXT NOT NULL
+// );
+
+use anyhow::Result;
+use chrono::{DateTime, Duration, Utc};
+use serde_json::Value;
+use spin_sdk::sqlite::Value as SV;
+use std::str::FromStr;
+use thiserror::Error;
+use tracing::debug;
+use url::Url;
+
+use crate::apo::AcceptedActivity;
+use crate::thread::Resolver;
+use crate::utils::get_current_time_in_iso_8601;
+use crate::utils::get_shared_inbox_from_actor;
+
+const STALE_AFTER: Duration = Duration::minutes(10);
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Outcome {
+    Processing,
+    Accepted,
+    Ignored,
+    Rejected,
+    Failed,
+}
+
+impl Outcome {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Outcome::Processing => "processing",
+            Outcome::Accepted => "accepted",
+            Outcome::Ignored => "ignored",
+            Outcome::Rejected => "rejected",
+            Outcome::Failed => "failed",
+        }
+    }
+}
+
+#[derive(Error, Debug, PartialEq, Eq)]
+#[error("not an outcome: {0}")]
+pub struct ParseError(String);
+
+impl FromStr for Outcome {
+    type Err = ParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "processing" => Ok(Outcome::Processing),
+            "accepted" => Ok(Outcome::Accepted),
+            "ignored" => Ok(Outcome::Ignored),
+            "rejected" => Ok(Outcome::Rejected),
+            "failed" => Ok(Outcome::Failed),
+            _ => Err(ParseError(s.to_string())),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Journal {
+    // First time we see this id (or the last attempt failed). Process it.
+    New,
+    // Already handled. Acknowledge without processing.
+    Duplicate(Outcome),
+}
+
+// Record the activity before processing it.
+// A delivery whose previous attempt ended in `Outcome::Failed`, or got stuck
+// in `Outcome::Processing`, is handed out again as `Journal::New` so the
+// sender's retry can succeed.
+pub async fn journal(
+    activity: &AcceptedActivity,
+    raw: &str,
+    key_id: &str,
+) -> Result<Journal> {
+    let now = get_current_time_in_iso_8601().await;
+
+    // RETURNING gives a row only when the insert actually happened.
+    let qr = crate::db::Connection::builder()
+        .await
+        .execute(
+            "INSERT INTO inbox_journal(id, body, keyId, outcome, receivedAt, updatedAt) VALUES(?,?,?,?,?,?) ON CONFLICT(id) DO NOTHING RETURNING id",
+            &[
+                SV::Text(activity.id.clone()),
+                SV::Text(raw.to_string()),
+                SV::Text(key_id.to_string()),
+                SV::Text(Outcome::Processing.as_str().to_string()),
+                SV::Text(now.clone()),
+                SV::Text(now.clone()),
+            ],
+        )
+        .await;
+    if qr.rows.len() == 1 {
+        return Ok(Journal::New);
+    }
+
+    let qr = crate::db::Connection::builder()
+        .await
+        .execute(
+            "SELECT outcome, updatedAt FROM inbox_journal WHERE id = ?",
+            &[SV::Text(activity.id.clone())],
+        )
+        .await;
+    let row = qr.rows().next().and_then(|r| {
+        Some((
+            r.get::<&str>("outcome")?.to_string(),
+            r.get::<&str>("updatedAt")?.to_string(),
+        ))
+    });
+    let journal = journaled(
+        row.as_ref().map(|(o, u)| (o.as_str(), u.as_str())),
+        Utc::now(),
+    )?;
+    let (outcome, updated_at) = match (journal, row) {
+        (Journal::New, Some(row)) => row,
+        // Gone in between, take it.
+        (Journal::New, None) => return Ok(Journal::New),
+        (duplicate, _) => {
+            debug!("duplicate activity {} ({duplicate:?})", activity.id);
+            return Ok(duplicate);
+        }
+    };
+
+    // Only one of concurrent retries gets the row.
+    let qr = crate::db::Connection::builder()
+        .await
+        .execute(
+            "UPDATE inbox_journal SET outcome = ?, keyId = ?, updatedAt = ? WHERE id = ? AND outcome = ? AND updatedAt = ? RETURNING id",
+            &[
+                SV::Text(Outcome::Processing.as_str().to_string()),
+                SV::Text(key_id.to_string()),
+                SV::Text(now),
+                SV::Text(activity.id.clone()),
+                SV::Text(outcome),
+                SV::Text(updated_at),
+            ],
+        )
+        .await;
+    if qr.rows.len() == 1 {
+        debug!("retrying activity {}", activity.id);
+        return Ok(Journal::New);
+    }
+    Ok(Journal::Duplicate(Outcome::Processing))
+}
+
+// What becomes of an activity given its journal row, (outcome, updatedAt).
+fn journaled(row: Option<(&str, &str)>, now: DateTime<Utc>) -> Result<Journal> {
+    let (outcome, updated_at) = match row {
+        Some(r) => r,
+        None => return Ok(Journal::New),
+    };
+    let outcome: Outcome = outcome.parse()?;
+    let stale = match DateTime::parse_from_rfc3339(updated_at) {
+        Ok(t) => t < now - STALE_AFTER,
+        Err(_) => true,
+    };
+    match outcome {
+        Outcome::Failed => Ok(Journal::New),
+        Outcome::Processing if stale => Ok(Journal::New),
+        outcome => Ok(Journal::Duplicate(outcome)),
+    }
+}
+
+pub async fn finish(activity_id: &str, outcome: Outcome) -> Result<()> {
+    let _ = crate::db::Connection::builder()
+        .await
+        .execute(
+            "UPDATE inbox_journal SET outcome = ?, updatedAt = ? WHERE id = ?",
+            &[
+                SV::Text(outcome.as_str().to_string()),
+                SV::Text(get_current_time_in_iso_8601().await),
+                SV::Text(activity_id.to_string()),
+            ],
+        )
+        .await;
+    Ok(())
+}
+
+pub async fn outcome(activity_id: &str) -> Result<Option<Outcome>> {
+    let qr = crate::db::Connection::builder()
+        .await
+        .execute(
+            "SELECT outcome FROM inbox_journal WHERE id = ?",
+            &[SV::Text(activity_id.to_string())],
+        )
+        .await;
+    let outcome = qr
+        .rows()
+        .next()
+        .and_then(|r| r.get::<&str>("outcome").map(|o| o.to_string()));
+    match outcome {
+        Some(o) => Ok(Some(o.parse()?)),
+        None => Ok(None),
+    }
+}
+
+// Inbox forwarding
+// https://www.w3.org/TR/activitypub/#inbox-forwarding
+// https://docs.joinmastodon.org/spec/activitypub/#inbox-forwarding
+//
+// When a remote reply to one of our posts is addressed to the followers
+// collection of the post's author, forward the activity to those followers.
+// The original body goes out byte for byte so its LD signature stays valid.
+// Call this only for activities `journal` reported as `Journal::New`.
+pub async fn forward(raw: &str) -> Result<usize> {
+    let activity = crate::apo::context::normalize(serde_json::from_str(raw)?);
+    let (sender, in_reply_to, owners) = match forwardable(&activity) {
+        Some(f) => f,
+        None => return Ok(0),
+    };
+    // The reply has to be to a post we hold, by the owner of the collection.
+    let parent = match Resolver::new().stored_only().load(&in_reply_to).await {
+        Ok(p) => p,
+        Err(_) => return Ok(0),
+    };
+
+    let mut delivered = 0;
+    for owner in owners {
+        if parent.attributed_to_id() != Some(owner.as_str()) {
+            continue;
+        }
+        // The collection has to be one of ours.
+        let qr = crate::db::Connection::builder()
+            .await
+            .execute(
+                "SELECT id FROM user WHERE federationId = ?",
+                &[SV::Text(owner.clone())],
+            )
+            .await;
+        let user_id = match qr.rows().next().and_then(|r| r.get::<i64>("id")) {
+            Some(id) => id,
+            None => continue,
+        };
+
+        let sender_host =
+            Url::parse(&sender)?.host_str().map(|h| h.to_string());
+        for inbox in follower_inboxes(user_id, sender_host).await? {
+            match crate::send::deliver(&owner, &inbox, raw).await {
+                Ok(status) => {
+                    debug!("forwarded to {inbox} --> {status}");
+                    delivered += 1;
+                }
+                Err(e) => tracing::error!("forwarding to {inbox} failed: {e}"),
+            }
+        }
+    }
+
+    Ok(delivered)
+}
+
+// (sender, inReplyTo, owners of the followers collections it is addressed
+// to) for a reply worth forwarding. None for anything else.
+fn forwardable(activity: &Value) -> Option<(String, String, Vec<String>)> {
+    match activity.get("type").and_then(|t| t.as_str()) {
+        Some("Create") | Some("Update") | Some("Delete") => {}
+        _ => return None,
+    }
+    let sender = activity.get("actor").and_then(|a| a.as_str())?;
+    let object = activity.get("object").filter(|o| o.is_object())?;
+    let in_reply_to = object.get("inReplyTo").and_then(|r| r.as_str())?;
+
+    let mut owners: Vec<String> = addressed(activity)
+        .into_iter()
+        .chain(addressed(object))
+        .filter_map(|r| r.strip_suffix("/followers").map(|o| o.to_string()))
+        .collect();
+    owners.sort();
+    owners.dedup();
+    if owners.is_empty() {
+        return None;
+    }
+    Some((sender.to_string(), in_reply_to.to_string(), owners))
+}
+
+// Unique inboxes of the user's followers, minus `skip_host` (the sender's own
+// server when it already has the activity).
+pub(crate) async fn follower_inboxes(
+    user_id: i64,
+    skip_host: Option<String>,
+) -> Result<Vec<String>> {
+    let qr = crate::db::Connection::builder()
+        .await
+        .execute(
+            "SELECT federationId FROM follower WHERE userId = ?",
+            &[SV::Integer(user_id)],
+        )
+        .await;
+
+    let mut inboxes: Vec<String> = Vec::new();
+    for row in qr.rows() {
+        let follower = row.get::<&str>("federationId").unwrap().to_string();
+        let inbox = match get_shared_inbox_from_actor(follower).await {
+            Ok(i) => i,
+            Err(_) => continue,
+        };
+        let host = Url::parse(&inbox)?.host_str().map(|h| h.to_string());
+        if (skip_host.is_some() && host == skip_host)
+            || inboxes.contains(&inbox)
+        {
+            continue;
+        }
+        inboxes.push(inbox);
+    }
+    Ok(inboxes)
+}
+
+pub(crate) fn addressed(v: &Value) -> Vec<String> {
+    let mut a = Vec::new();
+    for field in ["to", "cc", "audience"] {
+        match v.get(field) {
+            Some(Value::String(s)) => a.push(s.to_owned()),
+            Some(Value::Array(l)) => a.extend(
+                l.iter().filter_map(|s| s.as_str()).map(|s| s.to_string()),
+            ),
+            _ => {}
+        }
+    }
+    a
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn outcomes() {
+        for o in [
+            Outcome::Processing,
+            Outcome::Accepted,
+            Outcome::Ignored,
+            Outcome::Rejected,
+            Outcome::Failed,
+        ] {
+            assert_eq!(o.as_str().parse(), Ok(o));
+        }
+        assert!("done".parse::<Outcome>().is_err());
+        assert!("".parse::<Outcome>().is_err());
+    }
+
+    #[test]
+    fn journal_rows() {
+        let now = DateTime::parse_from_rfc3339("2024-03-01T12:00:00Z")
+            .unwrap()
+            .with_timezone(&Utc);
+        let recent = "2024-03-01T11:58:00Z";
+        let stale = "2024-03-01T11:00:00Z";
+
+        // Never seen.
+        assert_eq!(journaled(None, now).unwrap(), Journal::New);
+        // Handled already.
+        assert_eq!(
+            journaled(Some(("accepted", stale)), now).unwrap(),
+            Journal::Duplicate(Outcome::Accepted)
+        );
+        assert_eq!(
+            journaled(Some(("ignored", recent)), now).unwrap(),
+            Journal::Duplicate(Outcome::Ignored)
+        );
+        // Being processed right now.
+        assert_eq!(
+            journaled(Some(("processing", recent)), now).unwrap(),
+            Journal::Duplicate(Outcome::Processing)
+        );
+        // Retry after a failure.
+        assert_eq!(
+            journaled(Some(("failed", recent)), now).unwrap(),
+            Journal::New
+        );
+        // Whoever was processing it is long gone.
+        assert_eq!(
+            journaled(Some(("processing", stale)), now).unwrap(),
+            Journal::New
+        );
+        assert!(journaled(Some(("done", recent)), now).is_err());
+    }
+
+    #[test]
+    fn forwarding() {
+        let reply = |to: Value, cc: Value| {
+            serde_json::json!({
+                "id": "https://remote.example/notes/2/activity",
+                "type": "Create",
+                "actor": "https://remote.example/users/bob",
+                "to": to,
+                "cc": cc,
+                "object": {
+                    "id": "https://remote.example/notes/2",
+                    "type": "Note",
+                    "inReplyTo": "https://sparrow.example/users/alice/1",
+                    "to": to,
+                    "cc": cc
+                }
+            })
+        };
+
+        let (sender, in_reply_to, owners) = forwardable(&reply(
+            serde_json::json!("https://www.w3.org/ns/activitystreams#Public"),
+            serde_json::json!([
+                "https://sparrow.example/users/alice/followers",
+                "https://remote.example/users/bob/followers"
+            ]),
+        ))
+        .unwrap();
+        assert_eq!(sender, "https://remote.example/users/bob");
+        assert_eq!(in_reply_to, "https://sparrow.example/users/alice/1");
+        assert_eq!(
+            owners,
+            vec![
+                "https://remote.example/users/bob",
+                "https://sparrow.example/users/alice"
+            ]
+        );
+
+        // No followers collection addressed.
+        assert!(forwardable(&reply(
+            serde_json::json!("https://sparrow.example/users/alice"),
+            serde_json::json!([])
+        ))
+        .is_none());
+
+        // Not a reply.
+        let mut post = reply(
+            serde_json::json!("https://sparrow.example/users/alice/followers"),
+            serde_json::json!([]),
+        );
+        post["object"].as_object_mut().unwrap().remove("inReplyTo");
+        assert!(forwardable(&post).is_none());
+
+        // Nothing to forward in a Like.
+        let mut like = reply(
+            serde_json::json!("https://sparrow.example/users/alice/followers"),
+            serde_json::json!([]),
+        );
+        like["type"] = serde_json::json!("Like");
+        assert!(forwardable(&like).is_none());
+    }
+}
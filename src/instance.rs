@@ -0,0 +1,119 @@
+// Instance actor
+// https://docs.joinmastodon.org/spec/activitypub/#as
+//
+// The server's own actor, an Application at https://host/actor. It signs
+// what shouldn't be tied to one of our users: reports go out under it, so
+// the reporter stays anonymous to the remote admins, as with Mastodon. Its
+// key is made on first use and kept in
+//
+// CREATE TABLE instance_key (
+//     host TEXT PRIMARY KEY,
+//     privateKey TEXT NOT NULL,      -- PKCS#8 PEM
+//     createdAt TEXT NOT NULL
+// );
+
+use anyhow::{anyhow, Result};
+use rsa::pkcs8::{EncodePrivateKey, LineEnding};
+use rsa::RsaPrivateKey;
+use spin_sdk::http::{IntoResponse, Params, Request, Response};
+use spin_sdk::sqlite::Value as SV;
+use url::Url;
+
+use crate::actor::ActorBuilder;
+use crate::apo::{Actor, ActorType};
+use crate::utils::get_current_time_in_iso_8601;
+
+const PATH: &str = "/actor";
+
+// Instance actor of the server at `base` (scheme://host).
+pub fn actor_id(base: &str) -> String {
+    format!("{}{PATH}", base.trim_end_matches('/'))
+}
+
+// Host `id` is the instance actor of. None for any other url.
+fn host_of(id: &str) -> Option<String> {
+    let url = Url::parse(id).ok()?;
+    if url.path() != PATH || url.query().is_some() {
+        return None;
+    }
+    url.host_str().map(|h| h.to_string())
+}
+
+pub fn is_instance_actor(id: &str) -> bool {
+    host_of(id).is_some()
+}
+
+// Private key PEM of instance actor `id`, made if there is none yet.
+pub async fn private_key(id: &str) -> Result<String> {
+    let host =
+        host_of(id).ok_or_else(|| anyhow!("{id} is no instance actor"))?;
+    if let Some(key) = stored_key(&host).await {
+        return Ok(key);
+    }
+
+    let key = RsaPrivateKey::new(&mut rand::thread_rng(), 2048)?
+        .to_pkcs8_pem(LineEnding::LF)?
+        .to_string();
+    let _ = crate::db::Connection::builder()
+        .await
+        .execute(
+            "INSERT OR IGNORE INTO instance_key(host, privateKey, createdAt) VALUES(?,?,?)",
+            &[
+                SV::Text(host.clone()),
+                SV::Text(key),
+                SV::Text(get_current_time_in_iso_8601().await),
+            ],
+        )
+        .await;
+    // Another request may have won the race.
+    stored_key(&host)
+        .await
+        .ok_or_else(|| anyhow!("no instance key for {host}"))
+}
+
+pub async fn actor(base: &str) -> Result<Actor> {
+    let id = actor_id(base);
+    let host = host_of(&id).ok_or_else(|| anyhow!("bad base url {base}"))?;
+    let public_key = crate::actor::public_key_pem(&private_key(&id).await?)?;
+
+    let mut actor = ActorBuilder::new(&id, &host)?
+        .kind(ActorType::Application)
+        .manually_approves_followers(true)
+        .discoverable(false)
+        .public_key(&public_key)
+        .build();
+    actor.url = Default::default();
+    actor.followers = None;
+    actor.following = None;
+    actor.featured = None;
+    Ok(actor)
+}
+
+// GET /actor
+pub async fn request(
+    req: Request,
+    _params: Params,
+) -> Result<impl IntoResponse> {
+    let base = Url::parse(req.uri())?.origin().ascii_serialization();
+    Ok(Response::builder()
+        .status(200)
+        .header("Content-Type", "application/activity+json; charset=utf-8")
+        .body(serde_json::to_string(&actor(&base).await?)?)
+        .build())
+}
+
+async fn stored_key(host: &str) -> Option<String> {
+    let qr = crate::db::Connection::builder()
+        .await
+        .execute(
+            "SELECT privateKey FROM instance_key WHERE host = ?",
+            &[SV::Text(host.to_string())],
+        )
+        .await;
+    let key = qr
+        .rows()
+        .next()
+        .and_then(|r| r.get::<&str>("privateKey"))
+        .map(|k| k.to_string());
+    key
+}
@@ -1,11 +1,31 @@
+pub mod actor;
 pub mod apo;
 pub mod auth;
+pub mod block;
+pub mod compose;
 pub mod db;
+pub mod delivery;
+pub mod flag;
+pub mod format;
+pub mod featured;
+pub mod fed_id;
 pub mod follow_request;
+pub mod group;
+pub mod inbox;
+pub mod instance;
 pub mod keys;
 pub mod mastodon;
+pub mod nodeinfo;
+pub mod pagination;
+pub mod poll;
 pub mod postbox;
+pub mod quote;
+pub mod remote_actor;
+pub mod sanitize;
 pub mod send;
+pub mod thread;
 pub mod utils;
+pub mod walker;
+pub mod webfinger;
 
 pub mod storage;
@@ -1,4 +1,10 @@
 pub mod account;
 pub mod application;
+pub mod custom_emoji;
 pub mod media;
+pub mod mention;
+pub mod poll;
+pub mod preview_card;
+pub mod quote;
 pub mod status;
+pub mod tag;
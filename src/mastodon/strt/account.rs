@@ -1,5 +1,14 @@
+use anyhow::Result;
 use serde::{Deserialize, Serialize};
+use spin_sdk::sqlite::Value as SV;
 use std::collections::HashMap;
+use url::Url;
+
+use crate::actor::ActorBuilder;
+use crate::apo::{self, Actor, ActorType};
+use crate::db::placeholders;
+
+use super::custom_emoji::CustomEmoji;
 
 #[derive(Serialize, Deserialize, Default, Debug, PartialEq, Eq, Clone)]
 #[serde(rename_all = "camelCase")]
@@ -23,6 +32,175 @@ pub struct Account {
     pub following_count: u32,
     pub statuses_count: u32,
     pub last_status_at: String,
-    pub emojis: Vec<String>,
+    pub emojis: Vec<CustomEmoji>,
     pub fields: Vec<String>,
 }
+
+impl Account {
+    // Account for an actor document. Accounts are identified by their
+    // federationId, `base` is this server (https://host). Counters are left
+    // to the loaders.
+    pub fn from_actor(actor: &Actor, base: &str) -> Self {
+        let username = actor.preferred_username.clone().unwrap_or_default();
+        let host = Url::parse(&actor.id)
+            .ok()
+            .and_then(|u| u.host_str().map(|h| h.to_string()))
+            .unwrap_or_default();
+        let local = Url::parse(base)
+            .ok()
+            .is_some_and(|b| b.host_str() == Some(host.as_str()));
+        let image = |o: &Option<apo::Object>| {
+            o.as_ref()
+                .and_then(|i| i.url_any())
+                .unwrap_or_default()
+                .to_string()
+        };
+        let url = actor
+            .url
+            .iter()
+            .find_map(|u| u.id())
+            .unwrap_or(&actor.id)
+            .to_string();
+
+        let mut account = Self {
+            id: actor.id.clone(),
+            acct: match local {
+                true => username.clone(),
+                false => format!("{username}@{host}"),
+            },
+            username,
+            display_name: actor.name.clone().unwrap_or_default(),
+            locked: actor.manually_approves_followers.unwrap_or_default(),
+            bot: matches!(
+                actor.kind,
+                ActorType::Service | ActorType::Application
+            ),
+            discoverable: actor.discoverable.unwrap_or_default(),
+            group: actor.is_group(),
+            created_at: actor.published.clone().unwrap_or_default(),
+            note: actor.summary.clone().unwrap_or_default(),
+            url,
+            avatar: image(&actor.icon),
+            avatar_static: image(&actor.icon),
+            header: image(&actor.image),
+            header_static: image(&actor.image),
+            ..Default::default()
+        };
+        account.set_emojis(actor);
+        account
+    }
+
+    // Account `id` (a federationId), local or as stored in `remote_actor`.
+    // Nothing is fetched.
+    pub async fn load(id: &str, base: &str) -> Result<Option<Self>> {
+        Ok(Self::load_many(&[id.to_string()], base).await?.pop())
+    }
+
+    // Accounts for `ids` in their order, unknown ones left out. A handful of
+    // queries whatever the number of ids.
+    pub async fn load_many(ids: &[String], base: &str) -> Result<Vec<Self>> {
+        let base_host = Url::parse(base)?.host_str().map(|h| h.to_string());
+        let is_local = |id: &str| {
+            Url::parse(id)
+                .ok()
+                .and_then(|u| u.host_str().map(|h| h.to_string()))
+                == base_host
+        };
+
+        let mut local: Vec<String> =
+            ids.iter().filter(|i| is_local(i)).cloned().collect();
+        local.sort();
+        local.dedup();
+        let mut accounts: HashMap<String, Self> = HashMap::new();
+        for builder in ActorBuilder::from_db_many(&local).await? {
+            let account = Self::from_actor(&builder.build(), base);
+            accounts.insert(account.id.clone(), account);
+        }
+        for (id, count) in user_counts("follower", &local).await {
+            if let Some(a) = accounts.get_mut(&id) {
+                a.followers_count = count;
+            }
+        }
+        for (id, count) in user_counts("following", &local).await {
+            if let Some(a) = accounts.get_mut(&id) {
+                a.following_count = count;
+            }
+        }
+
+        let remote: Vec<String> =
+            ids.iter().filter(|i| !is_local(i)).cloned().collect();
+        for r in crate::remote_actor::stored_many(&remote).await {
+            let mut account = Self::from_actor(&r.actor, base);
+            account.followers_count =
+                r.followers_count.unwrap_or_default() as u32;
+            account.following_count =
+                r.following_count.unwrap_or_default() as u32;
+            account.statuses_count =
+                r.statuses_count.unwrap_or_default() as u32;
+            accounts.insert(r.actor.id.clone(), account);
+        }
+
+        // What we have seen them post.
+        let known: Vec<String> = accounts.keys().cloned().collect();
+        if !known.is_empty() {
+            let params: Vec<SV> =
+                known.iter().map(|k| SV::Text(k.clone())).collect();
+            let qr = crate::db::Connection::builder()
+                .await
+                .execute(
+                    &format!("SELECT json_extract(object, '$.attributedTo') AS author, COUNT(*) AS count, MAX(json_extract(object, '$.published')) AS last FROM thread_object WHERE json_extract(object, '$.attributedTo') IN ({}) GROUP BY author", placeholders(known.len())),
+                    &params,
+                )
+                .await;
+            for row in qr.rows() {
+                let author = row.get::<&str>("author").unwrap_or_default();
+                if let Some(a) = accounts.get_mut(author) {
+                    let count =
+                        row.get::<i64>("count").unwrap_or_default() as u32;
+                    a.statuses_count = a.statuses_count.max(count);
+                    a.last_status_at = row
+                        .get::<&str>("last")
+                        .map(|l| l.chars().take(10).collect())
+                        .unwrap_or_default();
+                }
+            }
+        }
+
+        Ok(ids
+            .iter()
+            .filter_map(|i| accounts.get(i).cloned())
+            .collect())
+    }
+
+    // Custom emoji used in the actor's name, bio and profile fields.
+    pub fn set_emojis(&mut self, actor: &Actor) {
+        self.emojis = actor
+            .tag
+            .iter()
+            .filter_map(apo::Tag::as_emoji)
+            .filter_map(CustomEmoji::from_emoji)
+            .collect();
+    }
+}
+
+// (federationId, rows in `table`) for local users, follower or following.
+async fn user_counts(table: &str, users: &[String]) -> Vec<(String, u32)> {
+    if users.is_empty() {
+        return Vec::new();
+    }
+    let qr = crate::db::Connection::builder()
+        .await
+        .execute(
+            &format!("SELECT user.federationId AS id, COUNT({table}.userId) AS count FROM user LEFT JOIN {table} ON {table}.userId = user.id WHERE user.federationId IN ({}) GROUP BY user.id", placeholders(users.len())),
+            &users.iter().map(|u| SV::Text(u.clone())).collect::<Vec<_>>(),
+        )
+        .await;
+    let counts = qr
+        .rows()
+        .filter_map(|r| {
+            let count = r.get::<i64>("count").unwrap_or_default() as u32;
+            Some((r.get::<&str>("id")?.to_string(), count))
+        })
+        .collect();
+    counts
+}
@@ -0,0 +1,29 @@
+use serde::{Deserialize, Serialize};
+
+use crate::apo::Emoji;
+
+// https://docs.joinmastodon.org/entities/CustomEmoji/
+#[derive(Serialize, Deserialize, Default, Debug, PartialEq, Eq, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct CustomEmoji {
+    pub shortcode: String,
+    pub url: String,
+    pub static_url: String,
+    pub visible_in_picker: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub category: Option<String>,
+}
+
+impl CustomEmoji {
+    // Remote emoji only come with one image and never show in our picker.
+    pub fn from_emoji(emoji: &Emoji) -> Option<Self> {
+        let url = emoji.icon.url_any()?.to_string();
+        Some(Self {
+            shortcode: emoji.name.trim_matches(':').to_string(),
+            static_url: url.clone(),
+            url,
+            visible_in_picker: false,
+            category: None,
+        })
+    }
+}
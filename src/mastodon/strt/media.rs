@@ -1,3 +1,4 @@
+use crate::apo::Object;
 use crate::db::Connection as DbCon;
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
@@ -31,7 +32,8 @@ pub struct MediaAttachment {
 }
 
 impl MediaAttachment {
-    pub async fn create<'b>(id: &str) -> Self {
+    // None when there is no such row.
+    pub async fn create(id: &str) -> Option<Self> {
         let qr: QueryResult = DbCon::builder()
             .await
             .execute(
@@ -39,37 +41,97 @@ impl MediaAttachment {
                 &[SV::Text(id.to_string())],
             )
             .await;
-        let row = &qr.rows().next().unwrap();
+        let media = qr.rows().next().map(|row| Self::from_row(&row));
+        media
+    }
 
-        pub async fn foo(a: Option<&str>) -> Option<String> {
-            match a {
-                None => return None,
-                Some(a) => return Some(a.to_string()),
-            }
+    // Upload `id` if local user `uploader` made it. None otherwise.
+    pub async fn uploaded_by(id: &str, uploader: &str) -> Option<Self> {
+        let qr: QueryResult = DbCon::builder()
+            .await
+            .execute(
+                "SELECT * FROM media_attachement WHERE id = ? AND userId = (SELECT id FROM user WHERE federationId = ?)",
+                &[SV::Text(id.to_string()), SV::Text(uploader.to_string())],
+            )
+            .await;
+        let media = qr.rows().next().map(|row| Self::from_row(&row));
+        media
+    }
+
+    // Our uploads among `urls`, keyed by url.
+    pub async fn by_urls(urls: &[String]) -> HashMap<String, Self> {
+        if urls.is_empty() {
+            return HashMap::new();
         }
+        let qr: QueryResult = DbCon::builder()
+            .await
+            .execute(
+                &format!(
+                    "SELECT * FROM media_attachement WHERE url IN ({})",
+                    crate::db::placeholders(urls.len())
+                ),
+                &urls.iter().map(|u| SV::Text(u.clone())).collect::<Vec<_>>(),
+            )
+            .await;
+        let media = qr
+            .rows()
+            .map(|row| Self::from_row(&row))
+            .filter_map(|m| Some((m.url.clone()?, m)))
+            .collect();
+        media
+    }
 
-        pub async fn foo2(a: Option<&str>) -> Option<Value> {
-            match a {
-                None => return None,
-                Some(a) => return Some(serde_json::from_str(a).unwrap()),
-            }
+    fn from_row(row: &Row) -> Self {
+        let text = |c: &str| row.get::<&str>(c).map(|s| s.to_string());
+        Self {
+            id: text("id").unwrap_or_default(),
+            kind: MediaType::set(row.get::<&str>("type").unwrap_or_default()),
+            url: text("url"),
+            preview_url: text("preview_url"),
+            remote_url: text("remote_url"),
+            text_url: text("text_url"),
+            meta: row
+                .get::<&str>("meta")
+                .and_then(|m| serde_json::from_str(m).ok()),
+            description: text("description"),
+            blurhash: text("blurhash"),
+            created_at: text("created_at"),
+            updated_at: text("updated_at"),
         }
+    }
 
-        let ma = Self {
-            id: row.get::<&str>("id").unwrap().to_string(),
-            kind: MediaType::set(row.get::<&str>("type").unwrap()),
-            url: row.get::<&str>("url").map(|s| s.to_string()),
-            preview_url: row.get::<&str>("preview_url").map(|s| s.to_string()),
-            remote_url: row.get::<&str>("remote_url").map(|s| s.to_string()),
-            text_url: row.get::<&str>("text_url").map(|s| s.to_string()),
-            meta: foo2(row.get::<&str>("meta")).await,
-            description: foo(row.get::<&str>("description")).await,
-            blurhash: row.get::<&str>("blurhash").map(|s| s.to_string()),
-            created_at: row.get::<&str>("created_at").map(|s| s.to_string()),
-            updated_at: row.get::<&str>("updated_at").map(|s| s.to_string()),
+    // A remote note's attachment. Its url doubles as id.
+    pub fn from_object(object: &Object) -> Option<Self> {
+        let url = object.url_any()?.to_string();
+        let kind = match object
+            .media_type
+            .as_deref()
+            .and_then(|m| m.split('/').next())
+        {
+            Some("image") => MediaType::Image,
+            Some("video") => MediaType::Video,
+            Some("audio") => MediaType::Audio,
+            _ => MediaType::Unknown,
         };
-
-        return ma;
+        let meta = match (object.width, object.height) {
+            (Some(width), Some(height)) => Some(serde_json::json!({
+                "original": { "width": width, "height": height }
+            })),
+            _ => None,
+        };
+        Some(Self {
+            id: url.clone(),
+            kind,
+            url: Some(url.clone()),
+            preview_url: Some(url.clone()),
+            remote_url: Some(url),
+            text_url: None,
+            meta,
+            description: object.name.clone(),
+            blurhash: object.blurhash.clone(),
+            created_at: None,
+            updated_at: None,
+        })
     }
 }
 
@@ -0,0 +1,59 @@
+use serde::{Deserialize, Serialize};
+use url::Url;
+
+use crate::apo;
+
+// https://docs.joinmastodon.org/entities/Status/#Mention
+#[derive(Serialize, Deserialize, Default, Debug, PartialEq, Eq, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct Mention {
+    pub id: String,
+    pub username: String,
+    pub url: String,
+    pub acct: String,
+}
+
+impl Mention {
+    // `id` is the mentioned account's id, `host` is this server. Local
+    // accounts get a bare username as acct, like Mastodon does.
+    pub fn from_mention(mention: &apo::Mention, id: String, host: &str) -> Self {
+        let actor_host = Url::parse(&mention.href)
+            .ok()
+            .and_then(|u| u.host_str().map(|h| h.to_string()))
+            .unwrap_or_default();
+
+        let handle = mention
+            .name
+            .as_deref()
+            .unwrap_or_default()
+            .trim_start_matches('@');
+        let (username, domain) = match handle.split_once('@') {
+            Some((u, d)) => (u.to_string(), d.to_string()),
+            // Some servers send "@user". Otherwise the last path segment is
+            // the best guess we have.
+            None if !handle.is_empty() => (handle.to_string(), actor_host),
+            None => (
+                mention
+                    .href
+                    .rsplit('/')
+                    .next()
+                    .unwrap_or_default()
+                    .to_string(),
+                actor_host,
+            ),
+        };
+
+        let acct = if domain == host {
+            username.clone()
+        } else {
+            format!("{username}@{domain}")
+        };
+
+        Self {
+            id,
+            username,
+            url: mention.href.clone(),
+            acct,
+        }
+    }
+}
@@ -0,0 +1,160 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use spin_sdk::sqlite::{QueryResult, Value as SV};
+use std::collections::HashMap;
+
+use crate::apo::{Question, Tag};
+use crate::db::{placeholders, Connection as DbCon};
+
+use super::custom_emoji::CustomEmoji;
+
+// https://docs.joinmastodon.org/entities/Poll/
+#[derive(Serialize, Deserialize, Default, Debug, PartialEq, Eq, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct Poll {
+    pub id: String,
+    pub expires_at: Option<String>,
+    pub expired: bool,
+    pub multiple: bool,
+    pub votes_count: u64,
+    pub voters_count: Option<u64>,
+    pub options: Vec<PollOption>,
+    pub emojis: Vec<CustomEmoji>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub voted: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub own_votes: Option<Vec<usize>>,
+}
+
+#[derive(Serialize, Deserialize, Default, Debug, PartialEq, Eq, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct PollOption {
+    pub title: String,
+    pub votes_count: Option<u64>,
+}
+
+impl Poll {
+    // `viewer` is the federationId of the local user looking at the poll.
+    pub async fn create(federation_id: &str, viewer: Option<&str>) -> Option<Self> {
+        Self::load_many(&[federation_id.to_string()], viewer)
+            .await
+            .remove(federation_id)
+    }
+
+    // Polls of the Questions `federation_ids`, keyed by federationId. Two
+    // queries whatever their number.
+    pub async fn load_many(
+        federation_ids: &[String],
+        viewer: Option<&str>,
+    ) -> HashMap<String, Self> {
+        if federation_ids.is_empty() {
+            return HashMap::new();
+        }
+        let qr: QueryResult = DbCon::builder()
+            .await
+            .execute(
+                &format!(
+                    "SELECT id, federationId, object FROM poll WHERE federationId IN ({})",
+                    placeholders(federation_ids.len())
+                ),
+                &federation_ids
+                    .iter()
+                    .map(|i| SV::Text(i.clone()))
+                    .collect::<Vec<_>>(),
+            )
+            .await;
+        let mut polls: HashMap<String, Self> = HashMap::new();
+        let mut ids: HashMap<i64, String> = HashMap::new();
+        for row in qr.rows() {
+            let id = row.get::<i64>("id").unwrap_or_default();
+            let federation_id = row.get::<&str>("federationId").unwrap_or_default();
+            let question: Question =
+                match serde_json::from_str(row.get::<&str>("object").unwrap_or_default()) {
+                    Ok(q) => q,
+                    Err(_) => continue,
+                };
+            polls.insert(
+                federation_id.to_string(),
+                Self::from_question(id.to_string(), &question),
+            );
+            ids.insert(id, federation_id.to_string());
+        }
+
+        if let Some(viewer) = viewer.filter(|_| !ids.is_empty()) {
+            let mut params = vec![SV::Text(viewer.to_string())];
+            params.extend(ids.keys().map(|i| SV::Integer(*i)));
+            let qr: QueryResult = DbCon::builder()
+                .await
+                .execute(
+                    &format!(
+                        "SELECT pollId, choice FROM poll_vote WHERE federationId = ? AND pollId IN ({}) ORDER BY choice",
+                        placeholders(ids.len())
+                    ),
+                    &params,
+                )
+                .await;
+            let mut own_votes: HashMap<i64, Vec<usize>> = HashMap::new();
+            for r in qr.rows() {
+                if let (Some(p), Some(c)) = (r.get::<i64>("pollId"), r.get::<i64>("choice")) {
+                    own_votes.entry(p).or_default().push(c as usize);
+                }
+            }
+            for (id, federation_id) in &ids {
+                if let Some(poll) = polls.get_mut(federation_id) {
+                    let votes = own_votes.remove(id).unwrap_or_default();
+                    poll.voted = Some(!votes.is_empty());
+                    poll.own_votes = Some(votes);
+                }
+            }
+        }
+
+        polls
+    }
+
+    pub fn from_question(id: String, question: &Question) -> Self {
+        let (options, multiple) = question.poll_options();
+        let options: Vec<PollOption> = options
+            .iter()
+            .map(|o| PollOption {
+                title: o.name.clone().unwrap_or_default(),
+                votes_count: o.replies_count(),
+            })
+            .collect();
+        let votes_count = options.iter().filter_map(|o| o.votes_count).sum();
+
+        // `closed` is set once the poll ended, `endTime` before that.
+        let expires_at = match question.closed.as_ref().and_then(|c| c.as_str()) {
+            Some(closed) => Some(closed.to_string()),
+            None => question.end_time.clone(),
+        };
+        let closed = question
+            .closed
+            .as_ref()
+            .is_some_and(|c| c.as_bool() != Some(false));
+        let expired = closed
+            || expires_at
+                .as_deref()
+                .and_then(|t| DateTime::parse_from_rfc3339(t).ok())
+                .is_some_and(|t| t < Utc::now());
+
+        Self {
+            id,
+            expires_at,
+            expired,
+            multiple,
+            votes_count,
+            voters_count: question
+                .voters_count
+                .or(if multiple { None } else { Some(votes_count) }),
+            options,
+            emojis: question
+                .tag
+                .iter()
+                .filter_map(Tag::as_emoji)
+                .filter_map(CustomEmoji::from_emoji)
+                .collect(),
+            voted: None,
+            own_votes: None,
+        }
+    }
+}
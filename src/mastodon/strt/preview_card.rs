@@ -0,0 +1,92 @@
+use serde::{Deserialize, Serialize};
+use url::Url;
+
+use crate::apo::{Object, ObjectType};
+
+// https://docs.joinmastodon.org/entities/PreviewCard/
+#[derive(Serialize, Deserialize, Default, Debug, PartialEq, Eq, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct PreviewCard {
+    pub url: String,
+    pub title: String,
+    pub description: String,
+    #[serde(rename = "type")]
+    pub kind: String,
+    pub author_name: String,
+    pub author_url: String,
+    pub provider_name: String,
+    pub provider_url: String,
+    pub html: String,
+    pub width: u32,
+    pub height: u32,
+    pub image: Option<String>,
+    pub embed_url: String,
+    pub blurhash: Option<String>,
+}
+
+impl PreviewCard {
+    // Card for a PeerTube video or a Mobilizon event. None for other types.
+    pub fn from_object(object: &Object) -> Option<Self> {
+        let kind = match object.kind {
+            ObjectType::Video => "video",
+            ObjectType::Event => "link",
+            _ => return None,
+        };
+        let url = object.url_href().or(object.id.as_deref())?.to_string();
+        let provider_url = Url::parse(&url)
+            .map(|u| u.origin().ascii_serialization())
+            .unwrap_or_default();
+        let provider_name = provider_url
+            .split("://")
+            .last()
+            .unwrap_or_default()
+            .to_string();
+
+        // PeerTube attributes videos to the account and its channel.
+        let author = object.attributed_to.iter().find_map(|a| a.object());
+        let author_name = author.and_then(|a| a.name.clone()).unwrap_or_default();
+        let author_url = object.attributed_to_id().unwrap_or_default().to_string();
+
+        // Biggest thumbnail.
+        let thumbnail = object
+            .icon
+            .iter()
+            .chain(object.image.iter())
+            .flat_map(|i| i.iter())
+            .max_by_key(|i| i.width.unwrap_or_default());
+        let image = thumbnail.and_then(|i| i.url_any()).map(|u| u.to_string());
+
+        let description = match object.kind {
+            ObjectType::Event => {
+                let place = object
+                    .location
+                    .as_ref()
+                    .and_then(|l| l.object())
+                    .and_then(|l| l.name.clone());
+                [object.start_time.clone(), place]
+                    .into_iter()
+                    .flatten()
+                    .collect::<Vec<String>>()
+                    .join(" · ")
+            }
+            _ => object.summary.clone().unwrap_or_default(),
+        };
+
+        Some(Self {
+            url,
+            title: object.name.clone().unwrap_or_default(),
+            description,
+            kind: kind.to_string(),
+            author_name,
+            author_url,
+            provider_name,
+            provider_url,
+            html: String::new(),
+            width: thumbnail.and_then(|i| i.width).unwrap_or_default(),
+            height: thumbnail.and_then(|i| i.height).unwrap_or_default(),
+            image,
+            embed_url: String::new(),
+            blurhash: thumbnail.and_then(|i| i.blurhash.clone()),
+        })
+    }
+}
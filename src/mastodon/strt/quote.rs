@@ -0,0 +1,31 @@
+use serde::{Deserialize, Serialize};
+
+use crate::apo::Note;
+
+use super::status::Status;
+
+// https://docs.joinmastodon.org/entities/Quote/
+#[derive(Serialize, Deserialize, Default, Debug, PartialEq, Eq, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct Quote {
+    // pending, accepted, rejected, revoked, deleted or unauthorized
+    pub state: String,
+    pub quoted_status: Option<Box<Status>>,
+}
+
+impl Quote {
+    pub fn from_note(quoted: &Note, base: &str) -> Self {
+        Self {
+            state: "accepted".to_string(),
+            quoted_status: Some(Box::new(Status::from_note(quoted, base))),
+        }
+    }
+
+    // The quoted object couldn't be fetched.
+    pub fn deleted() -> Self {
+        Self {
+            state: "deleted".to_string(),
+            quoted_status: None,
+        }
+    }
+}
@@ -1,10 +1,58 @@
+// Statuses are loaded from `thread_object`, their accounts from `user` or
+// `remote_actor`. Interactions are kept per status:
+//
+// CREATE TABLE favourite (
+//     statusId TEXT NOT NULL,       -- status federationId
+//     federationId TEXT NOT NULL,   -- actor who liked it
+//     createdAt TEXT NOT NULL,
+//     UNIQUE(statusId, federationId)
+// );
+// CREATE TABLE reblog (
+//     statusId TEXT NOT NULL,
+//     federationId TEXT NOT NULL,   -- actor who announced it
+//     createdAt TEXT NOT NULL,
+//     UNIQUE(statusId, federationId)
+// );
+// CREATE TABLE bookmark (
+//     userId INTEGER NOT NULL,
+//     statusId TEXT NOT NULL,
+//     createdAt TEXT NOT NULL,
+//     UNIQUE(userId, statusId)
+// );
+// CREATE TABLE muted_conversation (
+//     userId INTEGER NOT NULL,
+//     context TEXT NOT NULL,        -- thread_object.context
+//     createdAt TEXT NOT NULL,
+//     UNIQUE(userId, context)
+// );
+
+use anyhow::Result;
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use spin_sdk::sqlite::Value as SV;
+use std::collections::{HashMap, HashSet};
+
+use url::Url;
 
+use crate::apo::{self, Note, Object, ObjectType};
+use crate::db::placeholders;
+use crate::format::escape;
 use crate::mastodon::strt::account::Account;
 use crate::mastodon::strt::application::Application;
 
+use super::custom_emoji::CustomEmoji;
 use super::media::MediaAttachment;
+use super::mention::Mention;
+use super::poll::Poll;
+use super::preview_card::PreviewCard;
+use super::quote::Quote;
+use super::tag::Tag;
+
+// The viewer's flags, `{}` standing for the status ids.
+const FAVOURITED: &str = "SELECT statusId FROM favourite WHERE federationId = ? AND statusId IN ({})";
+const REBLOGGED: &str =
+    "SELECT statusId FROM reblog WHERE federationId = ? AND statusId IN ({})";
+const BOOKMARKED: &str = "SELECT statusId FROM bookmark WHERE userId = (SELECT id FROM user WHERE federationId = ?) AND statusId IN ({})";
+const MUTED: &str = "SELECT t.federationId AS statusId FROM thread_object t JOIN muted_conversation m ON m.context = t.context WHERE m.userId = (SELECT id FROM user WHERE federationId = ?) AND t.federationId IN ({})";
 
 #[derive(Serialize, Deserialize, Default, Debug, PartialEq, Eq, Clone)]
 #[serde(rename_all = "camelCase")]
@@ -17,7 +65,7 @@ pub struct Status {
     #[serde(default)]
     pub spoiler_text: Option<String>,
     pub visibility: String,
-    pub language: String,
+    pub language: Option<String>,
     pub uri: String,
     pub url: String,
     pub replies_count: u32,
@@ -33,9 +81,500 @@ pub struct Status {
     pub application: Application,
     pub account: Account,
     pub media_attachments: Vec<MediaAttachment>,
-    pub mentions: Vec<String>,
-    pub tags: Vec<String>,
-    pub emojis: Vec<String>,
-    pub card: Option<String>,
-    pub poll: Option<String>,
+    pub mentions: Vec<Mention>,
+    pub tags: Vec<Tag>,
+    pub emojis: Vec<CustomEmoji>,
+    pub card: Option<PreviewCard>,
+    pub poll: Option<Poll>,
+    pub quote: Option<Quote>,
+}
+
+impl Status {
+    // Status for a note as far as the note alone tells. Statuses and accounts
+    // are identified by their federationId. Counters the note lacks stay 0.
+    pub fn from_note(note: &Note, base: &str) -> Self {
+        let id = note.id.clone().unwrap_or_default();
+        let author = note.attributed_to_id().unwrap_or_default().to_string();
+
+        let mut status = Status {
+            id: id.clone(),
+            created_at: note.published.clone().unwrap_or_default(),
+            in_reply_to_id: note.in_reply_to_id().map(|i| i.to_string()),
+            visibility: visibility(note, &author).to_string(),
+            uri: id.clone(),
+            url: note.url_href().unwrap_or(&id).to_string(),
+            replies_count: note.replies_count().unwrap_or_default() as u32,
+            account: Account {
+                id: author.clone(),
+                url: author,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        status.set_content(note);
+        status.set_tags(note, base);
+        status
+    }
+
+    // Status `id` (a federationId) as seen by `viewer`, the local user asking.
+    pub async fn load(
+        id: &str,
+        viewer: Option<&str>,
+        base: &str,
+    ) -> Result<Option<Self>> {
+        Ok(Self::load_many(&[id.to_string()], viewer, base)
+            .await?
+            .pop())
+    }
+
+    // Statuses for `ids` in their order, unknown ones left out. Accounts,
+    // media, counts and the viewer's flags are loaded for all of them at
+    // once, timelines don't pay a query per status.
+    pub async fn load_many(
+        ids: &[String],
+        viewer: Option<&str>,
+        base: &str,
+    ) -> Result<Vec<Self>> {
+        if ids.is_empty() {
+            return Ok(Vec::new());
+        }
+        let notes = stored_notes(ids).await;
+        let found: Vec<String> = notes.keys().cloned().collect();
+
+        // Parents for in_reply_to_account_id, quoted posts for quote.
+        let related: Vec<String> = notes
+            .values()
+            .flat_map(|n| [n.in_reply_to_id(), n.quote_id()])
+            .flatten()
+            .map(|i| i.to_string())
+            .collect();
+        let related = stored_notes(&related).await;
+
+        let mut authors: Vec<String> = notes
+            .values()
+            .chain(related.values())
+            .filter_map(|n| n.attributed_to_id())
+            .map(|a| a.to_string())
+            .collect();
+        authors.sort();
+        authors.dedup();
+        let accounts: HashMap<String, Account> =
+            Account::load_many(&authors, base)
+                .await?
+                .into_iter()
+                .map(|a| (a.id.clone(), a))
+                .collect();
+
+        let urls: Vec<String> = notes
+            .values()
+            .flat_map(|n| n.attachment.iter())
+            .filter_map(|a| a.url_any())
+            .map(|u| u.to_string())
+            .collect();
+        let uploads = MediaAttachment::by_urls(&urls).await;
+
+        let replies = counts(
+            "SELECT inReplyTo AS statusId, COUNT(*) AS count FROM thread_object WHERE inReplyTo IN ({}) GROUP BY inReplyTo",
+            &found,
+        )
+        .await;
+        let favourites = counts(
+            "SELECT statusId, COUNT(*) AS count FROM favourite WHERE statusId IN ({}) GROUP BY statusId",
+            &found,
+        )
+        .await;
+        let reblogs = counts(
+            "SELECT statusId, COUNT(*) AS count FROM reblog WHERE statusId IN ({}) GROUP BY statusId",
+            &found,
+        )
+        .await;
+
+        let questions: Vec<String> = notes
+            .values()
+            .filter(|n| n.kind == ObjectType::Question)
+            .filter_map(|n| n.id.clone())
+            .collect();
+        let mut polls = Poll::load_many(&questions, viewer).await;
+
+        let (favourited, reblogged, bookmarked, muted) = match viewer {
+            Some(v) => (
+                flagged(FAVOURITED, v, &found).await,
+                flagged(REBLOGGED, v, &found).await,
+                flagged(BOOKMARKED, v, &found).await,
+                flagged(MUTED, v, &found).await,
+            ),
+            None => Default::default(),
+        };
+
+        let mut statuses = Vec::new();
+        for id in ids {
+            let note = match notes.get(id) {
+                Some(n) => n,
+                None => continue,
+            };
+            let mut status = Status::from_note(note, base);
+            if let Some(a) =
+                note.attributed_to_id().and_then(|a| accounts.get(a))
+            {
+                status.account = a.clone();
+            }
+            status.in_reply_to_account_id = note
+                .in_reply_to_id()
+                .and_then(|p| related.get(p))
+                .and_then(|p| p.attributed_to_id())
+                .map(|a| a.to_string());
+            status.media_attachments = note
+                .attachment
+                .iter()
+                .filter_map(|a| {
+                    match a.url_any().and_then(|u| uploads.get(u)) {
+                        Some(upload) => Some(upload.clone()),
+                        None => MediaAttachment::from_object(a),
+                    }
+                })
+                .collect();
+            if let Some(quoted) = note.quote_id() {
+                status.quote = Some(match related.get(quoted) {
+                    Some(q) => {
+                        let mut quote = Quote::from_note(q, base);
+                        if let (Some(s), Some(a)) = (
+                            quote.quoted_status.as_mut(),
+                            q.attributed_to_id().and_then(|a| accounts.get(a)),
+                        ) {
+                            s.account = a.clone();
+                        }
+                        quote
+                    }
+                    None => Quote::deleted(),
+                });
+            }
+            if note.kind == ObjectType::Question {
+                status.poll = polls.remove(id);
+            }
+
+            let count = |c: &HashMap<String, u32>| {
+                c.get(id).copied().unwrap_or_default()
+            };
+            status.replies_count = status.replies_count.max(count(&replies));
+            status.favourites_count = count(&favourites);
+            status.reblogs_count = count(&reblogs);
+            status.favourited = favourited.contains(id);
+            status.reblogged = reblogged.contains(id);
+            status.bookmarked = bookmarked.contains(id);
+            status.muted = muted.contains(id);
+            statuses.push(status);
+        }
+        Ok(statuses)
+    }
+
+    // Notes and Questions are shown as they are. Articles, Pages, Events and
+    // Videos get their title and a link instead, like Mastodon shows them,
+    // and a preview card for videos and events.
+    pub fn set_content(&mut self, object: &Object) {
+        self.sensitive = object.sensitive.unwrap_or_default();
+        self.card = PreviewCard::from_object(object);
+        self.language = object.language();
+
+        if !object.kind.is_converted() {
+            self.content =
+                object.content_text().unwrap_or_default().to_string();
+            self.spoiler_text = object.summary_text().map(|s| s.to_string());
+            return;
+        }
+
+        let url = object
+            .url_href()
+            .or(object.id.as_deref())
+            .unwrap_or_default();
+        let title = object
+            .name_text()
+            .map(|n| format!("<h2>{}</h2>", escape(n)));
+        // Already HTML, from a server we don't vouch for.
+        let summary = object.summary_text().map(crate::sanitize::html);
+        let link = format!(r#"<p><a href="{0}">{0}</a></p>"#, escape(url));
+
+        self.content = [title, summary, Some(link)]
+            .into_iter()
+            .flatten()
+            .collect::<Vec<String>>()
+            .join("");
+        self.spoiler_text = None;
+    }
+
+    // Mentions, hashtags and custom emoji from the note's `tag`. `base` is this
+    // server (https://host). Mentioned accounts are identified by their
+    // federationId.
+    pub fn set_tags(&mut self, note: &Note, base: &str) {
+        let host = Url::parse(base)
+            .ok()
+            .and_then(|u| u.host_str().map(|h| h.to_string()))
+            .unwrap_or_default();
+
+        self.mentions = note
+            .tag
+            .iter()
+            .filter_map(apo::Tag::as_mention)
+            .map(|m| Mention::from_mention(m, m.href.clone(), &host))
+            .collect();
+        self.tags = note
+            .tag
+            .iter()
+            .filter_map(apo::Tag::as_hashtag)
+            .map(|h| Tag::from_hashtag(h, base))
+            .collect();
+        self.emojis = note
+            .tag
+            .iter()
+            .filter_map(apo::Tag::as_emoji)
+            .filter_map(CustomEmoji::from_emoji)
+            .collect();
+    }
+}
+
+// Stored objects for `ids`, keyed by federationId.
+async fn stored_notes(ids: &[String]) -> HashMap<String, Note> {
+    if ids.is_empty() {
+        return HashMap::new();
+    }
+    let qr = crate::db::Connection::builder()
+        .await
+        .execute(
+            &format!(
+                "SELECT federationId, object FROM thread_object WHERE federationId IN ({})",
+                placeholders(ids.len())
+            ),
+            &texts(ids),
+        )
+        .await;
+    let notes = qr
+        .rows()
+        .filter_map(|r| {
+            let note = serde_json::from_str(r.get::<&str>("object")?).ok()?;
+            Some((r.get::<&str>("federationId")?.to_string(), note))
+        })
+        .collect();
+    notes
+}
+
+// statusId -> count, `sql` having `{}` for the ids.
+async fn counts(sql: &str, ids: &[String]) -> HashMap<String, u32> {
+    if ids.is_empty() {
+        return HashMap::new();
+    }
+    let qr = crate::db::Connection::builder()
+        .await
+        .execute(&sql.replace("{}", &placeholders(ids.len())), &texts(ids))
+        .await;
+    let counts = qr
+        .rows()
+        .filter_map(|r| {
+            let count = r.get::<i64>("count").unwrap_or_default() as u32;
+            Some((r.get::<&str>("statusId")?.to_string(), count))
+        })
+        .collect();
+    counts
+}
+
+// statusIds among `ids` that `viewer` has a row for.
+async fn flagged(sql: &str, viewer: &str, ids: &[String]) -> HashSet<String> {
+    if ids.is_empty() {
+        return HashSet::new();
+    }
+    let mut params = vec![SV::Text(viewer.to_string())];
+    params.extend(texts(ids));
+    let qr = crate::db::Connection::builder()
+        .await
+        .execute(&sql.replace("{}", &placeholders(ids.len())), &params)
+        .await;
+    let flagged = qr
+        .rows()
+        .filter_map(|r| r.get::<&str>("statusId").map(|s| s.to_string()))
+        .collect();
+    flagged
+}
+
+fn texts(values: &[String]) -> Vec<SV> {
+    values.iter().map(|v| SV::Text(v.clone())).collect()
+}
+
+// Mastodon's reading of the addressing.
+fn visibility(note: &Note, author: &str) -> &'static str {
+    let followers = format!("{author}/followers");
+    let mut addressed = note.to.iter().chain(note.cc.iter());
+    let to_followers = addressed.any(|a| *a == followers);
+    if note.to.iter().any(|t| t == apo::PUBLIC) {
+        "public"
+    } else if note.cc.iter().any(|c| c == apo::PUBLIC) {
+        "unlisted"
+    } else if to_followers {
+        "private"
+    } else {
+        "direct"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn object(v: serde_json::Value) -> Object {
+        serde_json::from_value(v).unwrap()
+    }
+
+    #[test]
+    fn content() {
+        let mut status = Status::default();
+        status.set_content(&object(json!({
+            "id": "https://remote.example/notes/1",
+            "type": "Note",
+            "content": "<p>Hi</p>",
+            "summary": "cw",
+            "sensitive": true
+        })));
+        assert_eq!(status.content, "<p>Hi</p>");
+        assert_eq!(status.spoiler_text.as_deref(), Some("cw"));
+        assert!(status.sensitive);
+        assert_eq!(status.card, None);
+
+        let mut status = Status::default();
+        status.set_content(&object(json!({
+            "id": "https://blog.example/a/1",
+            "type": "Article",
+            "name": "Fish & <Chips>",
+            "summary": "<p>Tasty <script>alert(1)</script><b>food</b></p>",
+            "url": "https://blog.example/fish-and-chips?a=1&b=2",
+            "content": "<p>The whole article</p>"
+        })));
+        assert_eq!(
+            status.content,
+            concat!(
+                "<h2>Fish &amp; &lt;Chips&gt;</h2>",
+                "<p>Tasty <b>food</b></p>",
+                r#"<p><a href="https://blog.example/fish-and-chips?a=1&amp;b=2">"#,
+                "https://blog.example/fish-and-chips?a=1&amp;b=2</a></p>"
+            )
+        );
+        assert_eq!(status.spoiler_text, None);
+    }
+
+    #[test]
+    fn tags() {
+        let note = object(json!({
+            "id": "https://remote.example/notes/1",
+            "type": "Note",
+            "tag": [
+                {
+                    "type": "Mention",
+                    "href": "https://sparrow.example/users/alice",
+                    "name": "@alice@sparrow.example"
+                },
+                {
+                    "type": "Mention",
+                    "href": "https://remote.example/users/bob",
+                    "name": "@bob"
+                },
+                {
+                    "type": "Hashtag",
+                    "href": "https://remote.example/tags/Birds",
+                    "name": "#Birds"
+                },
+                {
+                    "type": "Emoji",
+                    "name": ":blobcat:",
+                    "icon": {
+                        "type": "Image",
+                        "mediaType": "image/png",
+                        "url": "https://remote.example/emoji/blobcat.png"
+                    }
+                },
+                {
+                    "type": "Emoji",
+                    "name": ":sparrow:",
+                    "icon": {
+                        "type": "Image",
+                        "url": {
+                            "type": "Link",
+                            "mediaType": "image/webp",
+                            "href": "https://remote.example/emoji/sparrow.webp"
+                        }
+                    }
+                }
+            ]
+        }));
+        let mut status = Status::default();
+        status.set_tags(&note, "https://sparrow.example");
+
+        let accts: Vec<&str> =
+            status.mentions.iter().map(|m| m.acct.as_str()).collect();
+        assert_eq!(accts, vec!["alice", "bob@remote.example"]);
+        assert_eq!(status.mentions[1].username, "bob");
+        assert_eq!(status.mentions[1].url, "https://remote.example/users/bob");
+
+        assert_eq!(status.tags.len(), 1);
+        assert_eq!(status.tags[0].name, "birds");
+        assert_eq!(status.tags[0].url, "https://sparrow.example/tags/birds");
+
+        let emojis: Vec<(&str, &str)> = status
+            .emojis
+            .iter()
+            .map(|e| (e.shortcode.as_str(), e.url.as_str()))
+            .collect();
+        assert_eq!(
+            emojis,
+            vec![
+                ("blobcat", "https://remote.example/emoji/blobcat.png"),
+                ("sparrow", "https://remote.example/emoji/sparrow.webp")
+            ]
+        );
+        assert!(status.emojis.iter().all(|e| e.url == e.static_url));
+    }
+
+    #[test]
+    fn cards() {
+        assert_eq!(
+            PreviewCard::from_object(&object(json!({
+                "id": "https://blog.example/a/1",
+                "type": "Article"
+            }))),
+            None
+        );
+
+        let card = PreviewCard::from_object(&object(json!({
+            "id": "https://video.example/videos/watch/1",
+            "type": "Video",
+            "name": "A sparrow",
+            "summary": "Birds",
+            "url": "https://video.example/w/1",
+            "attributedTo": [
+                { "type": "Person", "id": "https://video.example/accounts/bob", "name": "Bob" }
+            ],
+            "icon": [
+                { "type": "Image", "url": "https://video.example/s.jpg", "width": 280, "height": 157 },
+                { "type": "Image", "url": "https://video.example/l.jpg", "width": 560, "height": 315 }
+            ]
+        })))
+        .unwrap();
+        assert_eq!(card.kind, "video");
+        assert_eq!(card.url, "https://video.example/w/1");
+        assert_eq!(card.title, "A sparrow");
+        assert_eq!(card.description, "Birds");
+        assert_eq!(card.author_name, "Bob");
+        assert_eq!(card.author_url, "https://video.example/accounts/bob");
+        assert_eq!(card.provider_name, "video.example");
+        assert_eq!(card.provider_url, "https://video.example");
+        assert_eq!(card.image.as_deref(), Some("https://video.example/l.jpg"));
+        assert_eq!((card.width, card.height), (560, 315));
+
+        let card = PreviewCard::from_object(&object(json!({
+            "id": "https://events.example/events/1",
+            "type": "Event",
+            "name": "Birdwatching",
+            "startTime": "2024-05-01T09:00:00Z",
+            "location": { "type": "Place", "name": "The park" }
+        })))
+        .unwrap();
+        assert_eq!(card.kind, "link");
+        assert_eq!(card.description, "2024-05-01T09:00:00Z · The park");
+    }
 }
@@ -0,0 +1,22 @@
+use serde::{Deserialize, Serialize};
+
+use crate::apo::Hashtag;
+
+// https://docs.joinmastodon.org/entities/Status/#Tag
+#[derive(Serialize, Deserialize, Default, Debug, PartialEq, Eq, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct Tag {
+    pub name: String,
+    pub url: String,
+}
+
+impl Tag {
+    // `base` is this server (https://host), the url points to our tag page.
+    pub fn from_hashtag(hashtag: &Hashtag, base: &str) -> Self {
+        let name = hashtag.name.trim_start_matches('#').to_lowercase();
+        Self {
+            url: format!("{base}/tags/{name}"),
+            name,
+        }
+    }
+}
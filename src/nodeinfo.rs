@@ -0,0 +1,361 @@
+// NodeInfo
+// https://github.com/jhass/nodeinfo/blob/main/PROTOCOL.md
+// https://nodeinfo.diaspora.software/schema.html
+//
+// GET /.well-known/nodeinfo         links to the documents below
+// GET /nodeinfo/:version            2.0 or 2.1
+//
+// Our document is built from local data: users, the posts they authored and
+// the `open_registrations` variable. Remote documents are fetched the same
+// way, newest schema first, and kept per host so code can tell what software
+// it talks to (Misskey quotes, Pleroma emoji reactions, ...).
+//
+// CREATE TABLE nodeinfo (
+//     host TEXT PRIMARY KEY,
+//     software TEXT NOT NULL,   -- software.name, lowercase
+//     version TEXT NOT NULL,
+//     document TEXT NOT NULL,   -- NodeInfo json
+//     fetchedAt TEXT NOT NULL
+// );
+
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use spin_sdk::http::{IntoResponse, Params, Request, Response};
+use spin_sdk::sqlite::Value as SV;
+use spin_sdk::variables;
+use tracing::debug;
+use url::Url;
+
+use crate::utils::get_current_time_in_iso_8601;
+use crate::webfinger::{Jrd, JrdLink};
+
+pub const SCHEMA_2_0: &str = "http://nodeinfo.diaspora.software/ns/schema/2.0";
+pub const SCHEMA_2_1: &str = "http://nodeinfo.diaspora.software/ns/schema/2.1";
+
+const CACHE_DAYS: i64 = 1;
+
+#[derive(Serialize, Deserialize, Default, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct NodeInfo {
+    pub version: String,
+    pub software: Software,
+    #[serde(default)]
+    pub protocols: Vec<String>,
+    #[serde(default)]
+    pub services: Services,
+    #[serde(default)]
+    pub open_registrations: bool,
+    #[serde(default)]
+    pub usage: Usage,
+    #[serde(default)]
+    pub metadata: Value,
+}
+
+#[derive(Serialize, Deserialize, Default, Debug, Clone)]
+pub struct Software {
+    pub name: String,
+    #[serde(default)]
+    pub version: String,
+    // 2.1
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub repository: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub homepage: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Default, Debug, Clone)]
+pub struct Services {
+    #[serde(default)]
+    pub inbound: Vec<String>,
+    #[serde(default)]
+    pub outbound: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, Default, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct Usage {
+    #[serde(default)]
+    pub users: Users,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub local_posts: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub local_comments: Option<u64>,
+}
+
+#[derive(Serialize, Deserialize, Default, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct Users {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub total: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub active_month: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub active_halfyear: Option<u64>,
+}
+
+// Software families that federate alike.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Family {
+    Mastodon,
+    Misskey,
+    Pleroma,
+    Lemmy,
+    Other,
+}
+
+impl Family {
+    pub fn of(software: &str) -> Self {
+        match software.to_lowercase().as_str() {
+            "mastodon" | "hometown" | "glitchsoc" | "sparrow" => {
+                Family::Mastodon
+            }
+            "misskey" | "sharkey" | "firefish" | "calckey" | "foundkey"
+            | "iceshrimp" | "cherrypick" | "meisskey" => Family::Misskey,
+            "pleroma" | "akkoma" => Family::Pleroma,
+            "lemmy" | "mbin" | "kbin" => Family::Lemmy,
+            _ => Family::Other,
+        }
+    }
+}
+
+impl NodeInfo {
+    // Our own document, `version` being "2.0" or "2.1".
+    pub async fn local(version: &str) -> Self {
+        let mut software = Software {
+            name: env!("CARGO_PKG_NAME").to_string(),
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            repository: None,
+            homepage: None,
+        };
+        if version == "2.1" {
+            let some = |s: &str| Some(s.to_string()).filter(|s| !s.is_empty());
+            software.repository = some(env!("CARGO_PKG_REPOSITORY"));
+            software.homepage = some(env!("CARGO_PKG_HOMEPAGE"));
+        }
+
+        // Active is having posted, there is no record of logins.
+        let active = |days: i64| async move {
+            let since = (Utc::now() - Duration::days(days)).to_rfc3339();
+            count(
+                "SELECT COUNT(DISTINCT json_extract(object, '$.attributedTo')) AS count FROM thread_object WHERE json_extract(object, '$.attributedTo') IN (SELECT federationId FROM user) AND json_extract(object, '$.published') > ?",
+                &[SV::Text(since)],
+            )
+            .await
+        };
+
+        let local_posts = count(
+            "SELECT COUNT(*) AS count FROM thread_object WHERE json_extract(object, '$.attributedTo') IN (SELECT federationId FROM user)",
+            &[],
+        )
+        .await;
+
+        Self {
+            version: version.to_string(),
+            software,
+            protocols: vec!["activitypub".to_string()],
+            services: Services::default(),
+            open_registrations: variables::get("open_registrations")
+                .map(|v| v == "true")
+                .unwrap_or(false),
+            usage: Usage {
+                users: Users {
+                    total: Some(
+                        count("SELECT COUNT(*) AS count FROM user", &[]).await,
+                    ),
+                    active_month: Some(active(30).await),
+                    active_halfyear: Some(active(180).await),
+                },
+                local_posts: Some(local_posts),
+                local_comments: None,
+            },
+            metadata: serde_json::json!({}),
+        }
+    }
+
+    pub fn family(&self) -> Family {
+        Family::of(&self.software.name)
+    }
+}
+
+// GET /.well-known/nodeinfo
+pub async fn discovery(
+    req: Request,
+    _params: Params,
+) -> Result<impl IntoResponse> {
+    let base = Url::parse(req.uri())
+        .map(|u| u.origin().ascii_serialization())
+        .unwrap_or_default();
+    let links = [(SCHEMA_2_1, "2.1"), (SCHEMA_2_0, "2.0")]
+        .into_iter()
+        .map(|(rel, version)| JrdLink {
+            rel: rel.to_string(),
+            kind: None,
+            href: Some(format!("{base}/nodeinfo/{version}")),
+            template: None,
+        })
+        .collect::<Vec<_>>();
+    Ok(Response::builder()
+        .status(200)
+        .header("Content-Type", "application/json")
+        .header("Access-Control-Allow-Origin", "*")
+        .body(serde_json::json!({ "links": links }).to_string())
+        .build())
+}
+
+// GET /nodeinfo/:version
+pub async fn request(
+    _req: Request,
+    params: Params,
+) -> Result<impl IntoResponse> {
+    let version = params.get("version").unwrap_or_default();
+    let schema = match version {
+        "2.0" => SCHEMA_2_0,
+        "2.1" => SCHEMA_2_1,
+        _ => return Ok(Response::builder().status(404).build()),
+    };
+    let nodeinfo = NodeInfo::local(version).await;
+    Ok(Response::builder()
+        .status(200)
+        .header(
+            "Content-Type",
+            format!("application/json; profile=\"{schema}#\""),
+        )
+        .header("Access-Control-Allow-Origin", "*")
+        .body(serde_json::to_string(&nodeinfo)?)
+        .build())
+}
+
+// NodeInfo of `host`, from the cache when recent enough.
+pub async fn get(host: &str) -> Result<NodeInfo> {
+    let host = host.to_lowercase();
+    if let Some(nodeinfo) = cached(&host).await {
+        return Ok(nodeinfo);
+    }
+    let nodeinfo = fetch(&host).await?;
+    cache(&host, &nodeinfo).await;
+    Ok(nodeinfo)
+}
+
+// Software family of `host`, Other when it can't be told.
+pub async fn family(host: &str) -> Family {
+    match get(host).await {
+        Ok(n) => n.family(),
+        Err(e) => {
+            debug!("nodeinfo of {host}: {e}");
+            Family::Other
+        }
+    }
+}
+
+pub async fn fetch(host: &str) -> Result<NodeInfo> {
+    let url = format!("https://{host}/.well-known/nodeinfo");
+    let (status, _, body) =
+        crate::fed_id::get(&url, "application/json").await?;
+    if status != 200 {
+        return Err(anyhow!("{url}: {status}"));
+    }
+    let jrd: Jrd = serde_json::from_str(&body)?;
+    let href = document_link(&jrd, host)
+        .ok_or_else(|| anyhow!("{host}: no nodeinfo link"))?;
+
+    let (status, _, body) =
+        crate::fed_id::get(&href, "application/json").await?;
+    if status != 200 {
+        return Err(anyhow!("{href}: {status}"));
+    }
+    Ok(serde_json::from_str(&body)?)
+}
+
+// Newest schema we know of, served from `host` itself.
+fn document_link(jrd: &Jrd, host: &str) -> Option<String> {
+    [SCHEMA_2_1, SCHEMA_2_0].into_iter().find_map(|schema| {
+        jrd.links
+            .iter()
+            .filter(|l| l.rel.trim_end_matches('#') == schema)
+            .filter_map(|l| l.href.as_deref())
+            .find(|href| {
+                Url::parse(href).is_ok_and(|u| {
+                    u.scheme() == "https"
+                        && u.host_str()
+                            .is_some_and(|h| h.eq_ignore_ascii_case(host))
+                })
+            })
+            .map(|href| href.to_string())
+    })
+}
+
+async fn count(sql: &str, params: &[SV]) -> u64 {
+    let qr = crate::db::Connection::builder()
+        .await
+        .execute(sql, params)
+        .await;
+    let count = qr
+        .rows()
+        .next()
+        .and_then(|r| r.get::<i64>("count"))
+        .unwrap_or_default();
+    count as u64
+}
+
+async fn cached(host: &str) -> Option<NodeInfo> {
+    let qr = crate::db::Connection::builder()
+        .await
+        .execute(
+            "SELECT document, fetchedAt FROM nodeinfo WHERE host = ?",
+            &[SV::Text(host.to_string())],
+        )
+        .await;
+    let row = qr.rows().next()?;
+    let fetched_at: DateTime<Utc> =
+        row.get::<&str>("fetchedAt")?.parse().ok()?;
+    if Utc::now() - fetched_at > Duration::days(CACHE_DAYS) {
+        return None;
+    }
+    serde_json::from_str(row.get::<&str>("document")?).ok()
+}
+
+async fn cache(host: &str, nodeinfo: &NodeInfo) {
+    let document = match serde_json::to_string(nodeinfo) {
+        Ok(d) => d,
+        Err(_) => return,
+    };
+    let _ = crate::db::Connection::builder()
+        .await
+        .execute(
+            "INSERT INTO nodeinfo(host, software, version, document, fetchedAt) VALUES(?,?,?,?,?) ON CONFLICT(host) DO UPDATE SET software = excluded.software, version = excluded.version, document = excluded.document, fetchedAt = excluded.fetchedAt",
+            &[
+                SV::Text(host.to_string()),
+                SV::Text(nodeinfo.software.name.to_lowercase()),
+                SV::Text(nodeinfo.software.version.clone()),
+                SV::Text(document),
+                SV::Text(get_current_time_in_iso_8601().await),
+            ],
+        )
+        .await;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn discovery_links() {
+        let jrd: Jrd = serde_json::from_str(r#"{"links":[
+            {"rel":"http://nodeinfo.diaspora.software/ns/schema/2.0","href":"https://misskey.example/nodeinfo/2.0"},
+            {"rel":"http://nodeinfo.diaspora.software/ns/schema/2.1","href":"https://elsewhere.example/nodeinfo/2.1"}
+        ]}"#).unwrap();
+        assert_eq!(
+            document_link(&jrd, "misskey.example").as_deref(),
+            Some("https://misskey.example/nodeinfo/2.0")
+        );
+
+        let nodeinfo: NodeInfo = serde_json::from_str(
+            r#"{"version":"2.0","software":{"name":"Sharkey","version":"2024.3.1"},"protocols":["activitypub"],"openRegistrations":false,"usage":{"users":{"total":3}}}"#,
+        )
+        .unwrap();
+        assert_eq!(nodeinfo.family(), Family::Misskey);
+        assert_eq!(nodeinfo.usage.users.total, Some(3));
+    }
+}
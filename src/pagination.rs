@@ -0,0 +1,530 @@
+// Paginated OrderedCollections
+// https://www.w3.org/TR/activitypub/#collections
+// https://www.w3.org/TR/activitystreams-core/#paging
+//
+// Collections we serve (outbox, followers, following, liked, featured) are an
+// OrderedCollection root pointing at cursor based pages, newest first, the
+// way Mastodon pages them:
+//
+//   {id}                          root: totalItems, first, last
+//   {id}?page=true                newest items
+//   {id}?page=true&max_id=X       items older than X (next)
+//   {id}?page=true&min_id=X       items newer than X (prev)
+//
+// What is in a collection comes from a `Source`. Outbox, followers, following
+// and liked are here, featured lives next to its table.
+
+use anyhow::Result;
+use async_trait::async_trait;
+use serde::Serialize;
+use serde_json::Value;
+use spin_sdk::sqlite::{Row, Value as SV};
+
+use crate::apo::context;
+use crate::apo::{
+    Activity, ActivityType, CollectionType, IdOrObject, Note,
+    OrderedCollection, OrderedCollectionPage, PUBLIC,
+};
+
+#[async_trait(?Send)]
+pub trait Source {
+    type Item: Serialize;
+
+    async fn count(&self) -> Result<u64>;
+
+    // At most `limit` items, newest first, each with its cursor. With
+    // `max_id` the items right below it, with `min_id` the ones right above.
+    async fn fetch(
+        &self,
+        cursor: &Cursor,
+        limit: usize,
+    ) -> Result<Vec<(String, Self::Item)>>;
+}
+
+// max_id or min_id that is none of ours. Answered with 400.
+#[derive(thiserror::Error, Debug, PartialEq, Eq)]
+#[error("invalid cursor: {0}")]
+pub struct BadCursor(pub String);
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Cursor {
+    pub max_id: Option<String>,
+    pub min_id: Option<String>,
+}
+
+impl Cursor {
+    // None when the query asks for the collection root.
+    pub fn from_query(query: &str) -> Option<Self> {
+        let mut page = false;
+        let mut cursor = Cursor::default();
+        for (k, v) in url::form_urlencoded::parse(query.as_bytes()) {
+            match k.as_ref() {
+                "page" => page = v == "true",
+                "max_id" => cursor.max_id = Some(v.to_string()),
+                "min_id" => cursor.min_id = Some(v.to_string()),
+                _ => {}
+            }
+        }
+        if page {
+            Some(cursor)
+        } else {
+            None
+        }
+    }
+}
+
+pub struct Paginator<S: Source> {
+    id: String,
+    source: S,
+    limit: usize,
+    hide_counts: bool,
+    hide_items: bool,
+}
+
+impl<S: Source> Paginator<S> {
+    pub fn new(id: &str, source: S) -> Self {
+        Self {
+            id: id.to_string(),
+            source,
+            limit: 20,
+            hide_counts: false,
+            hide_items: false,
+        }
+    }
+
+    pub fn limit(mut self, limit: usize) -> Self {
+        self.limit = limit.max(1);
+        self
+    }
+
+    // Leave totalItems out.
+    pub fn hide_counts(mut self, hide: bool) -> Self {
+        self.hide_counts = hide;
+        self
+    }
+
+    // Serve the root only, without pages. Users hiding their network.
+    pub fn hide_items(mut self, hide: bool) -> Self {
+        self.hide_items = hide;
+        self
+    }
+
+    pub async fn collection(&self) -> Result<OrderedCollection<S::Item>> {
+        let (first, last) = if self.hide_items {
+            (None, None)
+        } else {
+            (
+                Some(IdOrObject::Id(self.page_url("", ""))),
+                Some(IdOrObject::Id(self.page_url("min_id", "0"))),
+            )
+        };
+        Ok(OrderedCollection {
+            context: Some(context::outbound()),
+            id: Some(self.id.clone()),
+            kind: CollectionType::OrderedCollection,
+            total_items: self.total_items().await?,
+            first,
+            last,
+            current: None,
+            ordered_items: Vec::new(),
+        })
+    }
+
+    pub async fn page(
+        &self,
+        cursor: &Cursor,
+    ) -> Result<OrderedCollectionPage<S::Item>> {
+        // One more than a page tells whether there is more that way.
+        let mut items = self.source.fetch(cursor, self.limit + 1).await?;
+        let more = items.len() > self.limit;
+        let upwards = cursor.max_id.is_none() && cursor.min_id.is_some();
+        if more && upwards {
+            // Read up from min_id, the extra one is the newest.
+            items.remove(0);
+        } else {
+            items.truncate(self.limit);
+        }
+
+        // Only when there is something older than this page. Going up from
+        // min_id, what is below is not known, so always.
+        let next = match items.last() {
+            Some((c, _)) if more || upwards => Some(self.page_url("max_id", c)),
+            _ => None,
+        };
+        let prev = items.first().map(|(c, _)| self.page_url("min_id", c));
+
+        let id = match (&cursor.max_id, &cursor.min_id) {
+            (Some(c), _) => self.page_url("max_id", c),
+            (None, Some(c)) => self.page_url("min_id", c),
+            (None, None) => self.page_url("", ""),
+        };
+
+        Ok(OrderedCollectionPage {
+            context: Some(context::outbound()),
+            id: Some(id),
+            kind: CollectionType::OrderedCollectionPage,
+            total_items: self.total_items().await?,
+            part_of: Some(self.id.clone()),
+            next,
+            prev,
+            start_index: None,
+            ordered_items: items.into_iter().map(|(_, i)| i).collect(),
+        })
+    }
+
+    // Root or page, depending on the request's query string.
+    pub async fn respond(&self, query: &str) -> Result<Value> {
+        match Cursor::from_query(query) {
+            Some(cursor) if !self.hide_items => {
+                Ok(serde_json::to_value(self.page(&cursor).await?)?)
+            }
+            _ => Ok(serde_json::to_value(self.collection().await?)?),
+        }
+    }
+
+    async fn total_items(&self) -> Result<Option<u64>> {
+        if self.hide_counts {
+            return Ok(None);
+        }
+        Ok(Some(self.source.count().await?))
+    }
+
+    fn page_url(&self, key: &str, cursor: &str) -> String {
+        if key.is_empty() {
+            return format!("{}?page=true", self.id);
+        }
+        format!(
+            "{}?page=true&{key}={}",
+            self.id,
+            urlencoding::encode(cursor)
+        )
+    }
+}
+
+// Actors following `user` (a local federationId).
+pub struct Followers {
+    pub user: String,
+}
+
+// Actors `user` follows.
+pub struct Following {
+    pub user: String,
+}
+
+#[async_trait(?Send)]
+impl Source for Followers {
+    type Item = String;
+
+    async fn count(&self) -> Result<u64> {
+        count_relations("follower", &self.user).await
+    }
+
+    async fn fetch(
+        &self,
+        cursor: &Cursor,
+        limit: usize,
+    ) -> Result<Vec<(String, String)>> {
+        fetch_relations("follower", &self.user, cursor, limit).await
+    }
+}
+
+#[async_trait(?Send)]
+impl Source for Following {
+    type Item = String;
+
+    async fn count(&self) -> Result<u64> {
+        count_relations("following", &self.user).await
+    }
+
+    async fn fetch(
+        &self,
+        cursor: &Cursor,
+        limit: usize,
+    ) -> Result<Vec<(String, String)>> {
+        fetch_relations("following", &self.user, cursor, limit).await
+    }
+}
+
+// Public and unlisted statuses of `user`, as the Creates that posted them.
+pub struct Outbox {
+    pub user: String,
+}
+
+// Statuses `user` liked.
+pub struct Liked {
+    pub user: String,
+}
+
+// Statuses addressed to Public, in to or cc.
+const OUTBOX: &str = "json_extract(object, '$.attributedTo') = ? AND ? IN (SELECT value FROM json_each(object, '$.to') UNION ALL SELECT value FROM json_each(object, '$.cc'))";
+
+#[async_trait(?Send)]
+impl Source for Outbox {
+    type Item = Value;
+
+    async fn count(&self) -> Result<u64> {
+        count_rows("thread_object", OUTBOX, self.params()).await
+    }
+
+    async fn fetch(
+        &self,
+        cursor: &Cursor,
+        limit: usize,
+    ) -> Result<Vec<(String, Value)>> {
+        let params = self.params();
+        fetch_rows("thread_object", OUTBOX, params, cursor, limit, |r| {
+            let note: Note = serde_json::from_str(
+                r.get::<&str>("object").unwrap_or_default(),
+            )?;
+            let id = note.id.clone().unwrap_or_default();
+            let mut activity = Activity::new(
+                ActivityType::Create,
+                format!("{id}/activity"),
+                self.user.clone(),
+                note.clone(),
+            );
+            activity.context = None;
+            activity.to = note.to.clone();
+            activity.cc = note.cc.clone();
+            activity.published = note.published.clone();
+            Ok(serde_json::to_value(activity)?)
+        })
+        .await
+    }
+}
+
+impl Outbox {
+    fn params(&self) -> Vec<SV> {
+        vec![SV::Text(self.user.clone()), SV::Text(PUBLIC.to_string())]
+    }
+}
+
+#[async_trait(?Send)]
+impl Source for Liked {
+    type Item = String;
+
+    async fn count(&self) -> Result<u64> {
+        let params = vec![SV::Text(self.user.clone())];
+        count_rows("favourite", "federationId = ?", params).await
+    }
+
+    async fn fetch(
+        &self,
+        cursor: &Cursor,
+        limit: usize,
+    ) -> Result<Vec<(String, String)>> {
+        let params = vec![SV::Text(self.user.clone())];
+        fetch_rows(
+            "favourite",
+            "federationId = ?",
+            params,
+            cursor,
+            limit,
+            |r| Ok(r.get::<&str>("statusId").unwrap_or_default().to_string()),
+        )
+        .await
+    }
+}
+
+async fn count_relations(table: &str, user: &str) -> Result<u64> {
+    count_rows(
+        table,
+        "userId = (SELECT id FROM user WHERE federationId = ?)",
+        vec![SV::Text(user.to_string())],
+    )
+    .await
+}
+
+async fn count_rows(table: &str, filter: &str, params: Vec<SV>) -> Result<u64> {
+    let qr = crate::db::Connection::builder()
+        .await
+        .execute(
+            format!("SELECT COUNT(*) AS count FROM {table} WHERE {filter}")
+                .as_str(),
+            params.as_slice(),
+        )
+        .await;
+    let count = qr
+        .rows()
+        .next()
+        .and_then(|r| r.get::<i64>("count"))
+        .unwrap_or_default();
+    Ok(count as u64)
+}
+
+// Pages over rowid, the order relations were recorded in.
+async fn fetch_relations(
+    table: &str,
+    user: &str,
+    cursor: &Cursor,
+    limit: usize,
+) -> Result<Vec<(String, String)>> {
+    fetch_rows(
+        table,
+        "userId = (SELECT id FROM user WHERE federationId = ?)",
+        vec![SV::Text(user.to_string())],
+        cursor,
+        limit,
+        |r| {
+            Ok(r.get::<&str>("federationId")
+                .unwrap_or_default()
+                .to_string())
+        },
+    )
+    .await
+}
+
+// A page of the rows of `table` matching `filter` (with `params`), ordered
+// by rowid, newest first. The rowid is the cursor, `item` makes the rest.
+pub(crate) async fn fetch_rows<T>(
+    table: &str,
+    filter: &str,
+    mut params: Vec<SV>,
+    cursor: &Cursor,
+    limit: usize,
+    item: impl Fn(&Row) -> Result<T>,
+) -> Result<Vec<(String, T)>> {
+    let max_id = rowid(&cursor.max_id)?;
+    let min_id = rowid(&cursor.min_id)?;
+
+    let (condition, order, bound) = match (max_id, min_id) {
+        (Some(max), _) => ("AND rowid < ?", "DESC", Some(max)),
+        (None, Some(min)) => ("AND rowid > ?", "ASC", Some(min)),
+        (None, None) => ("", "DESC", None),
+    };
+    let sql = format!(
+        "SELECT rowid AS cursor, * FROM {table} WHERE {filter} {condition} ORDER BY rowid {order} LIMIT ?"
+    );
+    if let Some(b) = bound {
+        params.push(SV::Integer(b));
+    }
+    params.push(SV::Integer(limit as i64));
+
+    let qr = crate::db::Connection::builder()
+        .await
+        .execute(sql.as_str(), params.as_slice())
+        .await;
+    let mut items = Vec::new();
+    for r in qr.rows() {
+        let cursor = r.get::<i64>("cursor").unwrap_or_default().to_string();
+        items.push((cursor, item(&r)?));
+    }
+    // min_id pages are read upwards but served newest first.
+    if order == "ASC" {
+        items.reverse();
+    }
+    Ok(items)
+}
+
+fn rowid(cursor: &Option<String>) -> Result<Option<i64>, BadCursor> {
+    match cursor {
+        Some(c) => match c.parse::<i64>() {
+            Ok(c) => Ok(Some(c)),
+            Err(_) => Err(BadCursor(c.clone())),
+        },
+        None => Ok(None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ID: &str = "https://sparrow.example/users/alice/outbox";
+
+    // Rows 1..=n, rowids as cursors, paged like fetch_rows does.
+    struct Rows(i64);
+
+    #[async_trait(?Send)]
+    impl Source for Rows {
+        type Item = i64;
+
+        async fn count(&self) -> Result<u64> {
+            Ok(self.0 as u64)
+        }
+
+        async fn fetch(
+            &self,
+            cursor: &Cursor,
+            limit: usize,
+        ) -> Result<Vec<(String, i64)>> {
+            let max_id = rowid(&cursor.max_id)?;
+            let min_id = rowid(&cursor.min_id)?;
+            let mut rows: Vec<i64> = match (max_id, min_id) {
+                (Some(max), _) => (1..max).rev().take(limit).collect(),
+                (None, Some(min)) => ((min + 1)..=self.0).take(limit).collect(),
+                (None, None) => (1..=self.0).rev().take(limit).collect(),
+            };
+            rows.sort_by(|a, b| b.cmp(a));
+            Ok(rows.into_iter().map(|r| (r.to_string(), r)).collect())
+        }
+    }
+
+    fn cursor(max_id: Option<&str>, min_id: Option<&str>) -> Cursor {
+        Cursor {
+            max_id: max_id.map(|c| c.to_string()),
+            min_id: min_id.map(|c| c.to_string()),
+        }
+    }
+
+    #[test]
+    fn cursors() {
+        assert_eq!(Cursor::from_query(""), None);
+        assert_eq!(Cursor::from_query("max_id=5"), None);
+        assert_eq!(Cursor::from_query("page=false&max_id=5"), None);
+        assert_eq!(Cursor::from_query("page=true"), Some(Cursor::default()));
+        assert_eq!(
+            Cursor::from_query("page=true&max_id=5"),
+            Some(cursor(Some("5"), None))
+        );
+        assert_eq!(
+            Cursor::from_query("min_id=a%20b&page=true"),
+            Some(cursor(None, Some("a b")))
+        );
+    }
+
+    #[tokio::test]
+    async fn pages() {
+        let paginator = Paginator::new(ID, Rows(5)).limit(2);
+        let url = |q: &str| format!("{ID}?page=true{q}");
+
+        let root = paginator.collection().await.unwrap();
+        assert_eq!(root.total_items, Some(5));
+        assert_eq!(root.first, Some(IdOrObject::Id(url(""))));
+        assert_eq!(root.last, Some(IdOrObject::Id(url("&min_id=0"))));
+
+        let first = paginator.page(&Cursor::default()).await.unwrap();
+        assert_eq!(first.id, Some(url("")));
+        assert_eq!(first.ordered_items, vec![5, 4]);
+        assert_eq!(first.next, Some(url("&max_id=4")));
+        assert_eq!(first.prev, Some(url("&min_id=5")));
+
+        let older = paginator.page(&cursor(Some("4"), None)).await.unwrap();
+        assert_eq!(older.id, Some(url("&max_id=4")));
+        assert_eq!(older.ordered_items, vec![3, 2]);
+        assert_eq!(older.next, Some(url("&max_id=2")));
+
+        // Exactly a page left: no next.
+        let last = paginator.page(&cursor(Some("3"), None)).await.unwrap();
+        assert_eq!(last.ordered_items, vec![2, 1]);
+        assert_eq!(last.next, None);
+
+        let newer = paginator.page(&cursor(None, Some("1"))).await.unwrap();
+        assert_eq!(newer.id, Some(url("&min_id=1")));
+        assert_eq!(newer.ordered_items, vec![3, 2]);
+        assert_eq!(newer.next, Some(url("&max_id=2")));
+        assert_eq!(newer.prev, Some(url("&min_id=3")));
+
+        let empty = paginator.page(&cursor(None, Some("5"))).await.unwrap();
+        assert!(empty.ordered_items.is_empty());
+        assert_eq!((empty.next, empty.prev), (None, None));
+    }
+
+    #[tokio::test]
+    async fn bad_cursor() {
+        let paginator = Paginator::new(ID, Rows(5));
+        let e = paginator.respond("page=true&max_id=x").await.unwrap_err();
+        assert_eq!(e.downcast_ref(), Some(&BadCursor("x".to_string())));
+        assert!(paginator.respond("max_id=x").await.is_ok());
+    }
+}
@@ -0,0 +1,549 @@
+// Polls
+// https://www.w3.org/TR/activitystreams-vocabulary/#dfn-question
+// https://docs.joinmastodon.org/spec/activitypub/#Question
+//
+// A poll is a Question with its options in `oneOf` (single choice) or
+// `anyOf` (multiple choice). Remote polls come in a Create and their tallies
+// are refreshed with Update. Ours send that Update to whoever got the poll
+// and to the remote voters, at most once per TALLY_DELAY however many votes
+// come in. A vote is a Note replying to the Question with the chosen option
+// in `name` and no content, one Note per choice.
+//
+// CREATE TABLE poll (
+//     id INTEGER PRIMARY KEY AUTOINCREMENT,
+//     federationId TEXT UNIQUE NOT NULL, -- Question id
+//     userId INTEGER,                    -- author, NULL for remote polls
+//     object TEXT NOT NULL,              -- latest Question json
+//     expiresAt TEXT,
+//     createdAt TEXT NOT NULL,
+//     updatedAt TEXT NOT NULL
+// );
+//
+// CREATE TABLE poll_vote (
+//     pollId INTEGER NOT NULL,
+//     federationId TEXT NOT NULL,        -- voter
+//     choice INTEGER NOT NULL,
+//     object TEXT NOT NULL,              -- vote Note json
+//     createdAt TEXT NOT NULL,
+//     UNIQUE(pollId, federationId, choice)
+// );
+
+use anyhow::Result;
+use chrono::{DateTime, Duration, Utc};
+use spin_sdk::sqlite::Value as SV;
+use tracing::debug;
+use url::Url;
+use uuid::Uuid;
+
+use crate::apo::context;
+use crate::apo::{
+    AcceptedActivity, AcceptedTypes, Activity, ActivityType, Collection,
+    CollectionType, Create, IdOrObject, Note, Object, ObjectType, Question,
+};
+use crate::utils::get_current_time_in_iso_8601;
+
+// Tally updates of our polls are batched over this long.
+const TALLY_DELAY: Duration = Duration::seconds(60);
+
+// Question for a new local status. Addressing is up to the caller.
+pub fn question(
+    id: String,
+    actor: &str,
+    content: String,
+    options: &[String],
+    multiple: bool,
+    expires_in: i64,
+) -> Question {
+    let now = Utc::now();
+    let options: Vec<Object> = options
+        .iter()
+        .map(|title| {
+            let mut option = Note::new(ObjectType::Note);
+            option.name = Some(title.to_owned());
+            set_votes(&mut option, 0);
+            option
+        })
+        .collect();
+
+    let mut question = Question::new(ObjectType::Question);
+    question.id = Some(id);
+    question.attributed_to = IdOrObject::from(actor.to_string()).into();
+    question.content = Some(content);
+    question.published = Some(now.format("%Y-%m-%dT%H:%M:%SZ").to_string());
+    question.end_time = Some(
+        (now + Duration::seconds(expires_in))
+            .format("%Y-%m-%dT%H:%M:%SZ")
+            .to_string(),
+    );
+    question.voters_count = Some(0);
+    if multiple {
+        question.any_of = options;
+    } else {
+        question.one_of = options;
+    }
+    question
+}
+
+// Insert or refresh a poll. Local when its author is one of our users.
+pub async fn save(question: &Question) -> Result<()> {
+    let id = match &question.id {
+        Some(id) => id.to_owned(),
+        None => return Err(anyhow::Error::msg("Question without id")),
+    };
+    let author = question.attributed_to_id().unwrap_or_default().to_string();
+    let now = get_current_time_in_iso_8601().await;
+
+    let _ = crate::db::Connection::builder()
+        .await
+        .execute(
+            "INSERT INTO poll(federationId, userId, object, expiresAt, createdAt, updatedAt) VALUES(?,(SELECT id FROM user WHERE federationId = ?),json(?),?,?,?) ON CONFLICT(federationId) DO UPDATE SET object = excluded.object, expiresAt = excluded.expiresAt, updatedAt = excluded.updatedAt",
+            &[
+                SV::Text(id),
+                SV::Text(author),
+                SV::Text(serde_json::to_string(question)?),
+                match &question.end_time {
+                    Some(t) => SV::Text(t.to_owned()),
+                    None => SV::Null,
+                },
+                SV::Text(now.clone()),
+                SV::Text(now),
+            ],
+        )
+        .await;
+    Ok(())
+}
+
+// Inbound Create{Question} or Update{Question}. Returns false when the
+// object isn't a Question.
+pub async fn received(activity: &AcceptedActivity) -> Result<bool> {
+    match activity.kind() {
+        Some(AcceptedTypes::Create) | Some(AcceptedTypes::Update) => {}
+        _ => return Ok(false),
+    }
+
+    let object = activity.authoritative_object().await?;
+    let mut question: Question =
+        serde_json::from_value(context::normalize(object))?;
+    if question.kind != ObjectType::Question {
+        return Ok(false);
+    }
+    crate::sanitize::object(&mut question);
+
+    // Our own polls are tallied here, not by whoever sends an Update.
+    if is_local(question.id.as_deref().unwrap_or_default()).await {
+        return Ok(true);
+    }
+
+    debug!("poll {:?} from {}", question.id, activity.actor);
+    save(&question).await?;
+    Ok(true)
+}
+
+// Inbound Create{Note} answering one of our polls. Returns false when the
+// note isn't a vote.
+pub async fn vote_received(activity: &AcceptedActivity) -> Result<bool> {
+    if !matches!(activity.kind(), Some(AcceptedTypes::Create)) {
+        return Ok(false);
+    }
+    let note: Note =
+        serde_json::from_value(context::normalize(activity.object.clone()))?;
+    let (name, poll_id) = match (&note.name, note.in_reply_to_id()) {
+        (Some(name), Some(poll_id)) if note.content.is_none() => {
+            (name.clone(), poll_id.to_string())
+        }
+        _ => return Ok(false),
+    };
+    if note.attributed_to_id() != Some(activity.actor.as_str()) {
+        return Ok(false);
+    }
+
+    let (row_id, question) = match load(&poll_id).await? {
+        Some((row_id, question, true)) => (row_id, question),
+        _ => return Ok(false),
+    };
+    if expired(&question) {
+        debug!("vote on closed poll {poll_id} from {}", activity.actor);
+        return Ok(true);
+    }
+
+    let (options, multiple) = question.poll_options();
+    let choice =
+        match options.iter().position(|o| o.name.as_ref() == Some(&name)) {
+            Some(c) => c,
+            None => return Ok(true),
+        };
+    if !multiple && has_voted(row_id, &activity.actor).await {
+        return Ok(true);
+    }
+
+    record(
+        row_id,
+        &activity.actor,
+        choice,
+        &serde_json::to_string(&note)?,
+    )
+    .await;
+    recount(row_id, question).await?;
+    Ok(true)
+}
+
+// Vote on a poll as `my_actor`. `choices` are option indexes.
+pub async fn vote(
+    my_actor: Url,
+    poll_id: &str,
+    choices: Vec<usize>,
+) -> Result<()> {
+    let (row_id, question, local) = match load(poll_id).await? {
+        Some(p) => p,
+        None => {
+            return Err(anyhow::Error::msg(format!("unknown poll {poll_id}")))
+        }
+    };
+    if expired(&question) {
+        return Err(anyhow::Error::msg("poll is closed"));
+    }
+    let (options, multiple) = question.poll_options();
+    if choices.is_empty()
+        || (!multiple && choices.len() > 1)
+        || choices.iter().any(|c| *c >= options.len())
+    {
+        return Err(anyhow::Error::msg("invalid choices"));
+    }
+    if has_voted(row_id, my_actor.as_str()).await {
+        return Err(anyhow::Error::msg("already voted"));
+    }
+    let author = question.attributed_to_id().unwrap_or_default().to_string();
+    let host = match my_actor.host_str() {
+        Some(h) => h,
+        None => return Err(anyhow::Error::msg("actor without host")),
+    };
+    let inbox = match local {
+        true => None,
+        false => Some(crate::remote_actor::get(&author).await?.actor.inbox),
+    };
+
+    for choice in &choices {
+        let id = format!("{}://{host}/{}", my_actor.scheme(), Uuid::now_v7());
+
+        let mut note = Note::new(ObjectType::Note);
+        note.id = Some(format!("{id}#vote"));
+        note.name = options[*choice].name.clone();
+        note.attributed_to = IdOrObject::from(my_actor.to_string()).into();
+        note.in_reply_to = Some(poll_id.into());
+        note.to = author.clone().into();
+        let object = serde_json::to_string(&note)?;
+
+        if let Some(inbox) = &inbox {
+            let mut create: Create<Note> = Create::new(
+                ActivityType::Create,
+                id,
+                my_actor.to_string(),
+                note,
+            );
+            create.to = author.clone().into();
+            let body = serde_json::to_string(&create)?;
+            debug!("vote -> {body}");
+
+            crate::delivery::enqueue(
+                my_actor.as_str(),
+                std::slice::from_ref(inbox),
+                &body,
+            )
+            .await;
+        }
+
+        record(row_id, my_actor.as_str(), *choice, &object).await;
+    }
+
+    if local {
+        recount(row_id, question).await?;
+    } else if let Err(e) = crate::delivery::run(choices.len()).await {
+        tracing::error!("delivering votes on {poll_id}: {e}");
+    }
+    Ok(())
+}
+
+pub fn expired(question: &Question) -> bool {
+    if let Some(closed) = &question.closed {
+        if closed.as_bool() != Some(false) {
+            return true;
+        }
+    }
+    question
+        .end_time
+        .as_deref()
+        .and_then(|t| DateTime::parse_from_rfc3339(t).ok())
+        .is_some_and(|t| t < Utc::now())
+}
+
+// (poll.id, Question, local)
+async fn load(poll_id: &str) -> Result<Option<(i64, Question, bool)>> {
+    let qr = crate::db::Connection::builder()
+        .await
+        .execute(
+            "SELECT id, userId, object FROM poll WHERE federationId = ?",
+            &[SV::Text(poll_id.to_string())],
+        )
+        .await;
+    let row = match qr.rows().next() {
+        Some(r) => r,
+        None => return Ok(None),
+    };
+    let question: Question =
+        serde_json::from_str(row.get::<&str>("object").unwrap())?;
+    Ok(Some((
+        row.get::<i64>("id").unwrap(),
+        question,
+        row.get::<i64>("userId").is_some(),
+    )))
+}
+
+async fn is_local(poll_id: &str) -> bool {
+    let qr = crate::db::Connection::builder()
+        .await
+        .execute(
+            "SELECT 1 FROM poll WHERE federationId = ? AND userId IS NOT NULL",
+            &[SV::Text(poll_id.to_string())],
+        )
+        .await;
+    !qr.rows.is_empty()
+}
+
+async fn has_voted(row_id: i64, voter: &str) -> bool {
+    let qr = crate::db::Connection::builder()
+        .await
+        .execute(
+            "SELECT 1 FROM poll_vote WHERE pollId = ? AND federationId = ?",
+            &[SV::Integer(row_id), SV::Text(voter.to_string())],
+        )
+        .await;
+    !qr.rows.is_empty()
+}
+
+async fn record(row_id: i64, voter: &str, choice: usize, object: &str) {
+    let _ = crate::db::Connection::builder()
+        .await
+        .execute(
+            "INSERT OR IGNORE INTO poll_vote(pollId, federationId, choice, object, createdAt) VALUES(?,?,?,json(?),?)",
+            &[
+                SV::Integer(row_id),
+                SV::Text(voter.to_string()),
+                SV::Integer(choice as i64),
+                SV::Text(object.to_string()),
+                SV::Text(get_current_time_in_iso_8601().await),
+            ],
+        )
+        .await;
+}
+
+// Write the tallies of a local poll back into its Question.
+async fn recount(row_id: i64, mut question: Question) -> Result<()> {
+    let qr = crate::db::Connection::builder()
+        .await
+        .execute(
+            "SELECT choice, COUNT(*) AS votes FROM poll_vote WHERE pollId = ? GROUP BY choice",
+            &[SV::Integer(row_id)],
+        )
+        .await;
+    let votes: Vec<(usize, u64)> = qr
+        .rows()
+        .map(|r| {
+            (
+                r.get::<i64>("choice").unwrap_or_default() as usize,
+                r.get::<i64>("votes").unwrap_or_default() as u64,
+            )
+        })
+        .collect();
+
+    let qr = crate::db::Connection::builder()
+        .await
+        .execute(
+            "SELECT COUNT(DISTINCT federationId) AS voters FROM poll_vote WHERE pollId = ?",
+            &[SV::Integer(row_id)],
+        )
+        .await;
+    let voters = qr
+        .rows()
+        .next()
+        .and_then(|r| r.get::<i64>("voters"))
+        .unwrap_or_default();
+
+    tally(&mut question, &votes, voters as u64);
+    question.updated = Some(get_current_time_in_iso_8601().await);
+
+    save(&question).await?;
+    publish(row_id, &question).await
+}
+
+// Update{Question} with the new tallies of a local poll.
+async fn publish(row_id: i64, question: &Question) -> Result<()> {
+    let author = question.attributed_to_id().unwrap_or_default().to_string();
+    let mut inboxes = crate::compose::inboxes(&author, question).await?;
+
+    let qr = crate::db::Connection::builder()
+        .await
+        .execute(
+            "SELECT DISTINCT federationId FROM poll_vote WHERE pollId = ? AND federationId NOT IN (SELECT federationId FROM user)",
+            &[SV::Integer(row_id)],
+        )
+        .await;
+    let voters: Vec<String> = qr
+        .rows()
+        .filter_map(|r| r.get::<&str>("federationId").map(|v| v.to_string()))
+        .collect();
+    for voter in voters {
+        match crate::remote_actor::get(&voter).await {
+            Ok(a) if !inboxes.iter().any(|i| i == a.delivery_inbox()) => {
+                inboxes.push(a.delivery_inbox().to_string())
+            }
+            Ok(_) => {}
+            Err(e) => debug!("no inbox for voter {voter}: {e}"),
+        }
+    }
+
+    let author_url = Url::parse(&author)?;
+    let id = format!(
+        "{}://{}/{}",
+        author_url.scheme(),
+        author_url.host_str().unwrap_or_default(),
+        Uuid::now_v7()
+    );
+    let mut update =
+        Activity::new(ActivityType::Update, id, author.clone(), question);
+    update.to = question.to.clone();
+    update.cc = question.cc.clone();
+    let body = serde_json::to_string(&update)?;
+    debug!("poll update -> {body}");
+
+    let key = format!("poll:{row_id}");
+    crate::delivery::enqueue_collapsed(
+        &author,
+        &inboxes,
+        &body,
+        &key,
+        TALLY_DELAY,
+    )
+    .await;
+    Ok(())
+}
+
+// Set the option counts from (choice, votes) pairs, and the voters.
+fn tally(question: &mut Question, votes: &[(usize, u64)], voters: u64) {
+    for (i, option) in question.poll_options_mut().iter_mut().enumerate() {
+        let count = votes.iter().find(|(c, _)| *c == i).map(|(_, v)| *v);
+        set_votes(option, count.unwrap_or_default());
+    }
+    question.voters_count = Some(voters);
+}
+
+fn set_votes(option: &mut Object, votes: u64) {
+    option.replies = Some(IdOrObject::Object(Box::new(Collection {
+        context: None,
+        id: None,
+        kind: CollectionType::Collection,
+        total_items: Some(votes),
+        first: None,
+        last: None,
+        current: None,
+        items: Vec::new(),
+    })));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mastodon::strt::poll::Poll;
+
+    fn options() -> Vec<String> {
+        vec!["tea".to_string(), "coffee".to_string()]
+    }
+
+    #[test]
+    fn new_question() {
+        let q = question(
+            "https://example.com/statuses/1".to_string(),
+            "https://example.com/users/alice",
+            "<p>tea or coffee?</p>".to_string(),
+            &options(),
+            false,
+            3600,
+        );
+        let (opts, multiple) = q.poll_options();
+        assert!(!multiple);
+        assert_eq!(opts.len(), 2);
+        assert_eq!(opts[1].name.as_deref(), Some("coffee"));
+        assert!(opts.iter().all(|o| o.replies_count() == Some(0)));
+        assert!(q.end_time > q.published);
+        assert!(!expired(&q));
+
+        let v = serde_json::to_value(&q).unwrap();
+        assert_eq!(v["type"], "Question");
+        assert_eq!(v["oneOf"][0]["replies"]["totalItems"], 0);
+
+        let q = question(
+            "https://example.com/statuses/2".to_string(),
+            "https://example.com/users/alice",
+            String::new(),
+            &options(),
+            true,
+            3600,
+        );
+        assert!(q.poll_options().1);
+        assert!(q.one_of.is_empty());
+    }
+
+    #[test]
+    fn expiry() {
+        let mut q = Question::new(ObjectType::Question);
+        assert!(!expired(&q));
+        q.end_time = Some("2001-01-01T00:00:00Z".to_string());
+        assert!(expired(&q));
+
+        q.end_time = Some("2999-01-01T00:00:00Z".to_string());
+        assert!(!expired(&q));
+        q.closed = Some(serde_json::Value::Bool(false));
+        assert!(!expired(&q));
+        q.closed = Some(serde_json::Value::from("2001-01-01T00:00:00Z"));
+        assert!(expired(&q));
+    }
+
+    #[test]
+    fn tallies() {
+        let mut q = question(
+            "https://example.com/statuses/1".to_string(),
+            "https://example.com/users/alice",
+            String::new(),
+            &options(),
+            true,
+            3600,
+        );
+        tally(&mut q, &[(1, 3), (0, 1)], 3);
+        let counts: Vec<Option<u64>> = q
+            .poll_options()
+            .0
+            .iter()
+            .map(|o| o.replies_count())
+            .collect();
+        assert_eq!(counts, vec![Some(1), Some(3)]);
+        assert_eq!(q.voters_count, Some(3));
+
+        // Options nobody chose any more go back to zero.
+        tally(&mut q, &[(1, 2)], 2);
+        assert_eq!(q.poll_options().0[0].replies_count(), Some(0));
+
+        let poll = Poll::from_question("7".to_string(), &q);
+        assert_eq!(poll.id, "7");
+        assert!(poll.multiple);
+        assert!(!poll.expired);
+        assert_eq!(poll.votes_count, 2);
+        assert_eq!(poll.voters_count, Some(2));
+        assert_eq!(poll.options[1].title, "coffee");
+        assert_eq!(poll.options[1].votes_count, Some(2));
+        assert_eq!(poll.expires_at, q.end_time);
+
+        q.closed = Some(serde_json::Value::from("2001-01-01T00:00:00Z"));
+        let poll = Poll::from_question("7".to_string(), &q);
+        assert!(poll.expired);
+        assert_eq!(poll.expires_at.as_deref(), Some("2001-01-01T00:00:00Z"));
+    }
+}
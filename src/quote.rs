@@ -0,0 +1,123 @@
+// Quote posts
+// https://codeberg.org/fediverse/fep/src/branch/main/fep/044f/fep-044f.md
+// https://codeberg.org/fediverse/fep/src/branch/main/fep/e232/fep-e232.md
+// https://misskey-hub.net/ns#_misskey_quote
+//
+// Every server names the quoted object differently: `quote` (Mastodon),
+// `quoteUrl` (Akkoma, Misskey), `quoteUri` (Fedibird), `_misskey_quote`, or a
+// FEP-e232 Link in `tag`. We read any of them and write all of them, with an
+// "RE: url" line in the content for servers that know none.
+
+use anyhow::{anyhow, Result};
+use tracing::debug;
+
+use crate::apo::{Link, Note, Tag};
+use crate::mastodon::strt::quote::Quote;
+use crate::mastodon::strt::status::Status;
+use crate::thread::Resolver;
+
+// The object `note` quotes, fetched with `viewer`'s key if set. Ok(None) when
+// it quotes nothing.
+pub async fn quoted(note: &Note, viewer: Option<&str>) -> Result<Option<Note>> {
+    let id = match note.quote_id() {
+        Some(id) => id,
+        None => return Ok(None),
+    };
+    // A note quoting itself would loop in clients.
+    if note.id.as_deref() == Some(id) {
+        return Ok(None);
+    }
+    let resolver = match viewer {
+        Some(v) => Resolver::new().signed_by(v),
+        None => Resolver::new(),
+    };
+    let quoted = resolver.load(id).await?;
+    if !crate::thread::authored_on_origin(&quoted) {
+        return Err(anyhow!("{id} is attributed to another origin"));
+    }
+    Ok(Some(quoted))
+}
+
+// Status for `note` with its quote, if any.
+pub async fn status(
+    note: &Note,
+    base: &str,
+    viewer: Option<&str>,
+) -> Result<Status> {
+    let mut status = Status::from_note(note, base);
+    status.quote = match quoted(note, viewer).await {
+        Ok(Some(q)) => Some(Quote::from_note(&q, base)),
+        Ok(None) => None,
+        Err(e) => {
+            debug!("quote of {:?}: {e}", note.id);
+            Some(Quote::deleted())
+        }
+    };
+    Ok(status)
+}
+
+// Make local `note` quote `quoted` (an object id).
+pub fn link(note: &mut Note, quoted: &str) {
+    note.quote = Some(quoted.to_string());
+    note.quote_url = Some(quoted.to_string());
+    note.quote_uri = Some(quoted.to_string());
+    note.misskey_quote = Some(quoted.to_string());
+    note.tag.push(Tag::Link(Link::quote(quoted)));
+
+    let href = crate::format::escape(quoted);
+    let content = note.content.get_or_insert_with(String::new);
+    if !content.contains(&href) {
+        content.push_str(&format!(
+            r#"<p class="quote-inline">RE: <a href="{0}">{0}</a></p>"#,
+            href
+        ));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::apo::{context, ObjectType};
+    use serde_json::json;
+
+    #[test]
+    fn quotes() {
+        let misskey: Note = context::from_str(
+            r#"{
+              "@context": [
+                "https://www.w3.org/ns/activitystreams",
+                { "misskey": "https://misskey-hub.net/ns#" }
+              ],
+              "id": "https://misskey.io/notes/9x",
+              "type": "Note",
+              "misskey:_misskey_quote": "https://misskey.io/notes/9w"
+            }"#,
+        )
+        .unwrap();
+        assert_eq!(misskey.quote_id(), Some("https://misskey.io/notes/9w"));
+
+        let linked: Note = serde_json::from_value(json!({
+            "id": "https://example.com/notes/2",
+            "type": "Note",
+            "tag": [{
+                "type": "Link",
+                "mediaType": "application/ld+json; profile=\"https://www.w3.org/ns/activitystreams\"",
+                "href": "https://example.com/notes/1"
+            }]
+        }))
+        .unwrap();
+        assert_eq!(linked.quote_id(), Some("https://example.com/notes/1"));
+
+        let mut local = Note::new(ObjectType::Note);
+        link(&mut local, "https://example.com/notes/1?a=1&b=2");
+        let v = serde_json::to_value(&local).unwrap();
+        for key in ["quote", "quoteUrl", "quoteUri", "_misskey_quote"] {
+            assert_eq!(v[key], "https://example.com/notes/1?a=1&b=2");
+        }
+        assert_eq!(local.tag.iter().filter_map(Tag::as_quote).count(), 1);
+        assert!(local
+            .content
+            .unwrap()
+            .contains(r#"href="https://example.com/notes/1?a=1&amp;b=2""#));
+    }
+}
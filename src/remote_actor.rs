@@ -0,0 +1,333 @@
+// Remote actors
+// https://www.w3.org/TR/activitypub/#actor-objects
+// https://docs.joinmastodon.org/spec/activitypub/#profile
+//
+// Every remote account sparrow deals with, for signatures, delivery or
+// display, is looked up here. Documents are fetched once, sanitized, and
+// kept with what is asked of them most: key, inboxes, profile and the sizes
+// of their collections. They are fetched again after TTL_HOURS or when the
+// actor sends an Update of itself; `refresh` is for a signature that no
+// longer verifies with the key we have. A stale copy is still served when
+// the origin is down. Delete of the actor drops it.
+//
+// CREATE TABLE remote_actor (
+//     federationId TEXT PRIMARY KEY,
+//     object TEXT NOT NULL,          -- Actor json, sanitized
+//     publicKeyId TEXT,
+//     publicKeyPem TEXT,
+//     inbox TEXT NOT NULL,
+//     sharedInbox TEXT,
+//     username TEXT,                 -- preferredUsername
+//     host TEXT NOT NULL,
+//     displayName TEXT,
+//     avatar TEXT,
+//     header TEXT,
+//     followersCount INTEGER,        -- NULL when hidden
+//     followingCount INTEGER,
+//     statusesCount INTEGER,
+//     fetchedAt TEXT NOT NULL
+// );
+// CREATE INDEX remote_actor_public_key_id ON remote_actor(publicKeyId);
+
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, Duration, Utc};
+use serde_json::Value;
+use spin_sdk::sqlite::Value as SV;
+use tracing::debug;
+use url::Url;
+
+use crate::apo::context;
+use crate::apo::{object_id, same_origin, AcceptedActivity, AcceptedTypes};
+use crate::apo::{Actor, OriginError};
+use crate::utils::get_current_time_in_iso_8601;
+
+const TTL_HOURS: i64 = 24;
+
+#[derive(Clone, Debug)]
+pub struct RemoteActor {
+    pub actor: Actor,
+    pub followers_count: Option<u64>,
+    pub following_count: Option<u64>,
+    pub statuses_count: Option<u64>,
+    pub fetched_at: String,
+}
+
+impl RemoteActor {
+    pub fn id(&self) -> &str {
+        &self.actor.id
+    }
+
+    pub fn public_key_pem(&self) -> Option<&str> {
+        self.actor
+            .public_key
+            .as_ref()
+            .map(|k| k.public_key_pem.as_str())
+    }
+
+    // sharedInbox when there is one, inbox otherwise.
+    pub fn delivery_inbox(&self) -> &str {
+        self.actor.delivery_inbox()
+    }
+
+    // user@host
+    pub fn acct(&self) -> Option<String> {
+        let host = Url::parse(&self.actor.id).ok()?.host_str()?.to_string();
+        let name = self.actor.preferred_username.as_deref()?;
+        Some(format!("{name}@{host}"))
+    }
+
+    pub fn avatar(&self) -> Option<&str> {
+        self.actor.icon.as_ref().and_then(|i| i.url_any())
+    }
+
+    pub fn header(&self) -> Option<&str> {
+        self.actor.image.as_ref().and_then(|i| i.url_any())
+    }
+
+    fn is_stale(&self) -> bool {
+        match self.fetched_at.parse::<DateTime<Utc>>() {
+            Ok(t) => Utc::now() - t > Duration::hours(TTL_HOURS),
+            Err(_) => true,
+        }
+    }
+}
+
+// Actor `id`, from the store when fresh enough.
+pub async fn get(id: &str) -> Result<RemoteActor> {
+    let stored = stored(id).await;
+    match stored {
+        Some(a) if !a.is_stale() => Ok(a),
+        Some(a) => match refresh(id).await {
+            Ok(fresh) => Ok(fresh),
+            Err(e) => {
+                debug!("refreshing {id}: {e}, serving the stored copy");
+                Ok(a)
+            }
+        },
+        None => refresh(id).await,
+    }
+}
+
+// Fetch `id` again and store it.
+pub async fn refresh(id: &str) -> Result<RemoteActor> {
+    let object = crate::utils::fetch_object(id).await?;
+    if object_id(&object).as_deref() != Some(id) {
+        return Err(OriginError::Object(id.to_string()).into());
+    }
+    save(object).await
+}
+
+// Actor at `url`: its id, or a profile page (…/@name) answering with an
+// actor of the same origin.
+pub async fn lookup(url: &str) -> Result<RemoteActor> {
+    if stored(url).await.is_some() {
+        return get(url).await;
+    }
+    let object = crate::utils::fetch_object(url).await?;
+    let id = object_id(&object).ok_or_else(|| anyhow!("{url}: no id"))?;
+    if !same_origin(&id, url) {
+        return Err(OriginError::Object(id).into());
+    }
+    match stored(&id).await {
+        Some(a) if !a.is_stale() => Ok(a),
+        _ => save(object).await,
+    }
+}
+
+// Public key PEM a signature with `key_id` verifies against. Keys are
+// usually a fragment of the actor (…/users/name#main-key), some servers give
+// them their own document pointing at its owner.
+pub async fn public_key(key_id: &str) -> Result<String> {
+    if let Some(a) = by_key_id(key_id).await {
+        if !a.is_stale() {
+            return key_of(&a, key_id);
+        }
+    }
+
+    let owner = match key_id.split_once('#') {
+        Some((actor, _)) => actor.to_string(),
+        None => {
+            let key = crate::utils::fetch_object(key_id).await?;
+            match key.get("owner").and_then(|o| o.as_str()) {
+                Some(owner) => owner.to_string(),
+                None => key_id.to_string(),
+            }
+        }
+    };
+    if !same_origin(&owner, key_id) {
+        return Err(OriginError::KeyId(key_id.to_string()).into());
+    }
+    key_of(&refresh(&owner).await?, key_id)
+}
+
+fn key_of(actor: &RemoteActor, key_id: &str) -> Result<String> {
+    match &actor.actor.public_key {
+        Some(k) if k.id == key_id && k.owner == actor.actor.id => {
+            Ok(k.public_key_pem.clone())
+        }
+        _ => Err(anyhow!("{} has no key {key_id}", actor.actor.id)),
+    }
+}
+
+// Inbound Update or Delete of an actor. Returns false when the activity is
+// about something else.
+pub async fn received(activity: &AcceptedActivity) -> Result<bool> {
+    let id = object_id(&activity.object).unwrap_or_default();
+    if id != activity.actor {
+        return Ok(false);
+    }
+    match activity.kind() {
+        Some(AcceptedTypes::Update) => {
+            let object = activity.authoritative_object().await?;
+            if object.get("inbox").is_none() {
+                return Ok(false);
+            }
+            debug!("actor {id} updated");
+            save(object).await?;
+            Ok(true)
+        }
+        Some(AcceptedTypes::Delete) => {
+            debug!("actor {id} deleted");
+            delete(&id).await;
+            Ok(true)
+        }
+        _ => Ok(false),
+    }
+}
+
+pub async fn delete(id: &str) {
+    let _ = crate::db::Connection::builder()
+        .await
+        .execute(
+            "DELETE FROM remote_actor WHERE federationId = ?",
+            &[SV::Text(id.to_string())],
+        )
+        .await;
+}
+
+async fn save(object: Value) -> Result<RemoteActor> {
+    let mut actor: Actor = serde_json::from_value(context::normalize(object))?;
+    if actor.inbox.is_empty() {
+        return Err(anyhow!("{} has no inbox", actor.id));
+    }
+    if let Some(k) = &actor.public_key {
+        if !same_origin(&k.id, &actor.id) {
+            return Err(OriginError::KeyId(k.id.clone()).into());
+        }
+    }
+    crate::sanitize::actor(&mut actor);
+
+    let followers_count = total_items(actor.followers.as_deref()).await;
+    let following_count = total_items(actor.following.as_deref()).await;
+    let statuses_count = total_items(Some(actor.outbox.as_str())).await;
+    let remote = RemoteActor {
+        actor,
+        followers_count,
+        following_count,
+        statuses_count,
+        fetched_at: get_current_time_in_iso_8601().await,
+    };
+
+    let text = |s: Option<&str>| match s {
+        Some(s) => SV::Text(s.to_string()),
+        None => SV::Null,
+    };
+    let count = |c: Option<u64>| match c {
+        Some(c) => SV::Integer(c as i64),
+        None => SV::Null,
+    };
+    let a = &remote.actor;
+    let host = Url::parse(&a.id)?
+        .host_str()
+        .unwrap_or_default()
+        .to_string();
+    let shared_inbox =
+        a.endpoints.as_ref().and_then(|e| e.shared_inbox.as_deref());
+    crate::db::Connection::builder()
+        .await
+        .execute(
+            "INSERT INTO remote_actor(federationId, object, publicKeyId, publicKeyPem, inbox, sharedInbox, username, host, displayName, avatar, header, followersCount, followingCount, statusesCount, fetchedAt) VALUES(?,json(?),?,?,?,?,?,?,?,?,?,?,?,?,?) ON CONFLICT(federationId) DO UPDATE SET object = excluded.object, publicKeyId = excluded.publicKeyId, publicKeyPem = excluded.publicKeyPem, inbox = excluded.inbox, sharedInbox = excluded.sharedInbox, username = excluded.username, host = excluded.host, displayName = excluded.displayName, avatar = excluded.avatar, header = excluded.header, followersCount = excluded.followersCount, followingCount = excluded.followingCount, statusesCount = excluded.statusesCount, fetchedAt = excluded.fetchedAt",
+            &[
+                SV::Text(a.id.clone()),
+                SV::Text(serde_json::to_string(a)?),
+                text(a.public_key.as_ref().map(|k| k.id.as_str())),
+                text(remote.public_key_pem()),
+                SV::Text(a.inbox.clone()),
+                text(shared_inbox),
+                text(a.preferred_username.as_deref()),
+                SV::Text(host),
+                text(a.name.as_deref()),
+                text(remote.avatar()),
+                text(remote.header()),
+                count(remote.followers_count),
+                count(remote.following_count),
+                count(remote.statuses_count),
+                SV::Text(remote.fetched_at.clone()),
+            ],
+        )
+        .await;
+    Ok(remote)
+}
+
+// totalItems of a collection, None when it can't be had.
+async fn total_items(collection: Option<&str>) -> Option<u64> {
+    let collection = collection.filter(|c| !c.is_empty())?;
+    match crate::utils::fetch_object(collection).await {
+        Ok(c) => c.get("totalItems").and_then(|t| t.as_u64()),
+        Err(e) => {
+            debug!("{e}");
+            None
+        }
+    }
+}
+
+// What we have of `id`, without going to its origin.
+pub async fn stored(id: &str) -> Option<RemoteActor> {
+    select("federationId = ?", vec![SV::Text(id.to_string())])
+        .await
+        .pop()
+}
+
+// Batch of `stored`, in no particular order. Unknown ids are left out.
+pub async fn stored_many(ids: &[String]) -> Vec<RemoteActor> {
+    if ids.is_empty() {
+        return Vec::new();
+    }
+    let condition =
+        format!("federationId IN ({})", crate::db::placeholders(ids.len()));
+    select(
+        &condition,
+        ids.iter().map(|i| SV::Text(i.clone())).collect(),
+    )
+    .await
+}
+
+async fn by_key_id(key_id: &str) -> Option<RemoteActor> {
+    select("publicKeyId = ?", vec![SV::Text(key_id.to_string())])
+        .await
+        .pop()
+}
+
+async fn select(condition: &str, params: Vec<SV>) -> Vec<RemoteActor> {
+    let qr = crate::db::Connection::builder()
+        .await
+        .execute(
+            &format!("SELECT object, followersCount, followingCount, statusesCount, fetchedAt FROM remote_actor WHERE {condition}"),
+            &params,
+        )
+        .await;
+    let actors = qr
+        .rows()
+        .filter_map(|row| {
+            let count = |c: &str| row.get::<i64>(c).map(|c| c as u64);
+            Some(RemoteActor {
+                actor: serde_json::from_str(row.get::<&str>("object")?).ok()?,
+                followers_count: count("followersCount"),
+                following_count: count("followingCount"),
+                statuses_count: count("statusesCount"),
+                fetched_at: row.get::<&str>("fetchedAt")?.to_string(),
+            })
+        })
+        .collect();
+    actors
+}
@@ -0,0 +1,119 @@
+// HTML sanitization
+// https://docs.joinmastodon.org/spec/activitypub/#sanitization
+//
+// Remote HTML is cleaned when it comes in, before it is stored or handed to
+// clients. Same allowlist as Mastodon: a few text-level tags, microformat and
+// mention classes, links to known schemes only. Links get
+// rel="nofollow noopener", scripts and styles are dropped with their content,
+// style attributes and everything else not listed are dropped too.
+
+use ammonia::Builder;
+use std::collections::{HashMap, HashSet};
+
+use crate::apo::{Actor, Object};
+
+const TAGS: [&str; 21] = [
+    "p",
+    "br",
+    "span",
+    "a",
+    "abbr",
+    "del",
+    "s",
+    "pre",
+    "blockquote",
+    "code",
+    "b",
+    "strong",
+    "u",
+    "i",
+    "em",
+    "ul",
+    "ol",
+    "li",
+    "ruby",
+    "rt",
+    "rp",
+];
+
+const SCHEMES: [&str; 10] = [
+    "http", "https", "dat", "dweb", "ipfs", "ipns", "ssb", "gopher", "xmpp",
+    "magnet",
+];
+
+// Mentions, hashtags, h-card links, shortened urls and quote fallbacks.
+const CLASSES: [&str; 9] = [
+    "h-card",
+    "u-url",
+    "p-name",
+    "mention",
+    "hashtag",
+    "ellipsis",
+    "invisible",
+    "quote-inline",
+    "status-link",
+];
+
+pub fn html(dirty: &str) -> String {
+    let classes: HashSet<&str> = CLASSES.into_iter().collect();
+    Builder::default()
+        .tags(TAGS.into_iter().collect())
+        .generic_attributes(HashSet::new())
+        .tag_attributes(HashMap::from([
+            ("a", HashSet::from(["href", "translate"])),
+            ("span", HashSet::from(["translate"])),
+            ("ol", HashSet::from(["start", "reversed"])),
+            ("li", HashSet::from(["value"])),
+        ]))
+        .allowed_classes(HashMap::from([
+            ("a", classes.clone()),
+            ("span", classes.clone()),
+            ("p", classes),
+        ]))
+        .url_schemes(SCHEMES.into_iter().collect())
+        .url_relative(ammonia::UrlRelative::Deny)
+        .link_rel(Some("nofollow noopener"))
+        .clean(dirty)
+        .to_string()
+}
+
+// content, summary and their maps of a remote object.
+pub fn object(object: &mut Object) {
+    if let Some(c) = &object.content {
+        object.content = Some(html(c));
+    }
+    if let Some(s) = &object.summary {
+        object.summary = Some(html(s));
+    }
+    for map in [&mut object.content_map, &mut object.summary_map] {
+        for value in map.iter_mut().flat_map(|m| m.values_mut()) {
+            *value = html(value);
+        }
+    }
+}
+
+// Bio and profile field values of a remote actor.
+pub fn actor(actor: &mut Actor) {
+    if let Some(s) = &actor.summary {
+        actor.summary = Some(html(s));
+    }
+    for field in actor.attachment.iter_mut() {
+        if let Some(v) = &field.value {
+            field.value = Some(html(v));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mastodon_allowlist() {
+        let dirty = r#"<p style="color:red" onclick="x()">Hi <span class="h-card evil"><a href="https://a.example/@b" class="u-url mention" target="_top">@<span>b</span></a></span></p><script>alert(1)</script><img src="https://a.example/x.png"><a href="javascript:alert(1)">x</a><h1>t</h1>"#;
+        assert_eq!(
+            html(dirty),
+            r#"<p>Hi <span class="h-card"><a href="https://a.example/@b" class="u-url mention" rel="nofollow noopener">@<span>b</span></a></span></p><a rel="nofollow noopener">x</a>t"#
+        );
+    }
+}
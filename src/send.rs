@@ -28,29 +28,31 @@ pub async fn foo(recipient: String, body: String) -> Result<u16> {
     let me = request_body.get("actor").unwrap().as_str().unwrap();
     tracing::debug!(me);
 
-    //let my_actor = format!("{}", Url::parse(me).unwrap().to_string());
-    let recipient_actor = Url::parse(&recipient).unwrap();
-    let recipient_server: &str = recipient_actor.host_str().unwrap();
+    let inbox = crate::remote_actor::get(&recipient).await?.actor.inbox;
+    deliver(me, &inbox, &request_body.to_string()).await
+}
+
+// POST `body` to `inbox` as is, signed with the key of local actor `signer`.
+// The body is not re-serialized so already signed documents can be forwarded.
+pub async fn deliver(signer: &str, inbox: &str, body: &str) -> Result<u16> {
+    let inbox_url = Url::parse(inbox)?;
+    let recipient_server = match inbox_url.host_str() {
+        Some(h) => h,
+        None => return Err(anyhow::anyhow!("no host in {inbox}")),
+    };
 
     let private_key_pem =
-        get_privatekey_with_actor_url(me.to_string()).await.unwrap();
+        get_privatekey_with_actor_url(signer.to_string()).await?;
     let date = get_current_time_in_rfc_1123().await;
     let content_type = "application/activity+json".to_string();
 
-    tracing::debug!("me -> {me}");
-    //tracing::debug!("my_actor -> {my_actor}");
-    tracing::debug!("recipient_actor -> {recipient_actor}");
-    tracing::debug!("recipient_server -> {recipient_server}");
-    tracing::debug!("private_key_pem -> {private_key_pem}");
+    tracing::debug!("signer -> {signer}");
+    tracing::debug!("inbox -> {inbox}");
     tracing::debug!("date -> {date}");
-    tracing::debug!("content_type -> {content_type}");
-
-    // TODO: This should be created from activity_stream crate not from string literal.
-
-    tracing::debug!("request_body -> {request_body}");
+    tracing::debug!("request_body -> {body}");
 
     let mut hasher = Sha256::new();
-    hasher.update(request_body.to_string());
+    hasher.update(body);
     let digest = format!(
         "SHA-256={}",
         general_purpose::STANDARD.encode(hasher.finalize())
@@ -58,7 +60,7 @@ pub async fn foo(recipient: String, body: String) -> Result<u16> {
     tracing::debug!("digest --> {digest}");
 
     let hostname = recipient_server.to_string();
-    let inbox_path = format!("{}/inbox", recipient_actor.path());
+    let inbox_path = inbox_url.path();
     let signature_string = format!(
         "(request-target): post {}\nhost: {}\ndate: {}\ndigest: {}\ncontent-type: {}",
         inbox_path, hostname, date, digest, content_type
@@ -66,36 +68,23 @@ pub async fn foo(recipient: String, body: String) -> Result<u16> {
     tracing::debug!("signature_string --> \n{signature_string}");
 
     // The signature string is constructed using the values of the HTTP headers defined in headers, joined by newlines. Typically, you will want to include the request target, as well as the host and the date. Mastodon assumes Date: header if none are provided. For the above GET request, to generate a Signature: with headers="(request-target) host date"
-    // https://github.com/RustCrypto/RSA/issues/341
-    let private_key = RsaPrivateKey::from_pkcs8_pem(&private_key_pem)
-        .expect("RsaPrivateKey creation failed");
-    let signing_key: SigningKey<Sha256> = SigningKey::new(private_key);
-    let signature = <SigningKey<Sha256> as Signer<Signature>>::sign(
-        &signing_key,
-        signature_string.as_bytes(),
-    );
-    let encoded_signature =
-        general_purpose::STANDARD.encode(signature.to_bytes().as_ref());
+    let encoded_signature = sign(&private_key_pem, &signature_string)?;
 
     let sig_header = format!(
         r#"keyId="{}#main-key",algorithm="rsa-sha256",headers="(request-target) host date digest content-type",signature="{}""#,
-        me, encoded_signature
+        signer, encoded_signature
     );
 
     tracing::debug!("sig_header --> {sig_header}");
 
-    // FIXME: Need to get INBOX url from actor request.
-    let request = RequestBuilder::new(
-        Method::Post,
-        format!("{}/inbox", recipient_actor),
-    ) // TODO: recipient uri should get from actor.
-    .header("Date", date)
-    .header("Signature", sig_header)
-    .header("Digest", digest)
-    .header("Content-Type", &content_type)
-    .header("Accept", &content_type)
-    .body(request_body.to_string())
-    .build();
+    let request = RequestBuilder::new(Method::Post, inbox)
+        .header("Date", date)
+        .header("Signature", sig_header)
+        .header("Digest", digest)
+        .header("Content-Type", &content_type)
+        .header("Accept", &content_type)
+        .body(body.to_string())
+        .build();
     let response: IncomingResponse = http::send(request).await?;
     let status = response.status();
 
@@ -106,6 +95,53 @@ pub async fn foo(recipient: String, body: String) -> Result<u16> {
     Ok(status)
 }
 
+// GET `url` signed with the key of local actor `signer`, for servers that
+// only serve signed fetches (Mastodon's authorized fetch mode).
+pub async fn signed_get(signer: &str, url: &str) -> Result<(u16, String)> {
+    let target = Url::parse(url)?;
+    let host = target.host_str().unwrap_or_default().to_string();
+    let path = match target.query() {
+        Some(q) => format!("{}?{q}", target.path()),
+        None => target.path().to_string(),
+    };
+
+    let private_key_pem =
+        get_privatekey_with_actor_url(signer.to_string()).await?;
+    let date = get_current_time_in_rfc_1123().await;
+
+    let signature_string =
+        format!("(request-target): get {path}\nhost: {host}\ndate: {date}");
+    let encoded_signature = sign(&private_key_pem, &signature_string)?;
+    let sig_header = format!(
+        r#"keyId="{}#main-key",algorithm="rsa-sha256",headers="(request-target) host date",signature="{}""#,
+        signer, encoded_signature
+    );
+    tracing::debug!("signed get {url} as {signer}");
+
+    let request = RequestBuilder::new(Method::Get, url)
+        .header("Date", date)
+        .header("Signature", sig_header)
+        .header("Accept", "application/activity+json")
+        .build();
+    let response: IncomingResponse = http::send(request).await?;
+    let status = response.status();
+    let body = String::from_utf8(response.into_body().await?)?;
+
+    Ok((status, body))
+}
+
+// rsa-sha256 signature of `signature_string`, base64 encoded.
+// https://github.com/RustCrypto/RSA/issues/341
+fn sign(private_key_pem: &str, signature_string: &str) -> Result<String> {
+    let private_key = RsaPrivateKey::from_pkcs8_pem(private_key_pem)?;
+    let signing_key: SigningKey<Sha256> = SigningKey::new(private_key);
+    let signature = <SigningKey<Sha256> as Signer<Signature>>::sign(
+        &signing_key,
+        signature_string.as_bytes(),
+    );
+    Ok(general_purpose::STANDARD.encode(signature.to_bytes().as_ref()))
+}
+
 async fn is_actor_local(actor: String) -> Result<bool> {
     Ok(true)
 }
@@ -0,0 +1,578 @@
+// Conversation threads
+// https://www.w3.org/TR/activitystreams-vocabulary/#dfn-inreplyto
+// https://www.w3.org/TR/activitystreams-vocabulary/#dfn-context
+//
+// A thread is rebuilt from both ends of a status: its ancestors by following
+// `inReplyTo` up to the root, its descendants from what we stored and from
+// every `replies` collection on the way down. Missing objects are fetched,
+// everything seen is recorded with the thread it belongs to: `context`, the
+// ostatus `conversation`, or else the root's id.
+//
+// CREATE TABLE thread_object (
+//     federationId TEXT PRIMARY KEY,   -- object id
+//     inReplyTo TEXT,
+//     context TEXT NOT NULL,
+//     object TEXT NOT NULL,            -- Note json
+//     createdAt TEXT NOT NULL
+// );
+// CREATE INDEX thread_object_in_reply_to ON thread_object(inReplyTo);
+
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use spin_sdk::http::{IntoResponse, Params, Request, Response};
+use spin_sdk::sqlite::Value as SV;
+use std::collections::HashSet;
+use tracing::debug;
+use url::Url;
+
+use crate::apo::context;
+use crate::apo::{same_origin, IdOrObject, Note, PUBLIC};
+use crate::auth::{check_api_auth, TokenAuth};
+use crate::mastodon::strt::status::Status;
+use crate::utils::get_current_time_in_iso_8601;
+use crate::walker::Walker;
+
+// Same limits as Mastodon.
+const MAX_ANCESTORS: usize = 40;
+const MAX_DESCENDANTS: usize = 60;
+
+pub struct Thread {
+    pub ancestors: Vec<Note>,
+    pub status: Note,
+    pub descendants: Vec<Note>,
+}
+
+// https://docs.joinmastodon.org/entities/Context/
+#[derive(Serialize, Deserialize, Default, Debug, PartialEq, Eq, Clone)]
+pub struct Context {
+    pub ancestors: Vec<Status>,
+    pub descendants: Vec<Status>,
+}
+
+impl Context {
+    // Statuses as timelines show them, loaded in one batch for `viewer`.
+    pub async fn from_thread(
+        thread: &Thread,
+        viewer: Option<&str>,
+        base: &str,
+    ) -> Result<Self> {
+        let ids = |notes: &[Note]| -> Vec<String> {
+            notes.iter().filter_map(|n| n.id.clone()).collect()
+        };
+        let ancestors: HashSet<String> =
+            ids(&thread.ancestors).into_iter().collect();
+        let all = [ids(&thread.ancestors), ids(&thread.descendants)].concat();
+
+        let (ancestors, descendants) = Status::load_many(&all, viewer, base)
+            .await?
+            .into_iter()
+            .partition(|s| ancestors.contains(&s.uri));
+        Ok(Self {
+            ancestors,
+            descendants,
+        })
+    }
+}
+
+// Resolver for one request. Remote fetches are signed by `viewer` if set,
+// and not made at all when `stored_only`.
+pub struct Resolver {
+    viewer: Option<String>,
+    fetch: bool,
+}
+
+impl Resolver {
+    pub fn new() -> Self {
+        Self {
+            viewer: None,
+            fetch: true,
+        }
+    }
+
+    pub fn signed_by(mut self, actor: &str) -> Self {
+        self.viewer = Some(actor.to_string());
+        self
+    }
+
+    pub fn stored_only(mut self) -> Self {
+        self.fetch = false;
+        self
+    }
+
+    pub async fn resolve(&self, id: &str) -> Result<Thread> {
+        let status = self.load(id).await?;
+        let ancestors = self.ancestors(&status).await?;
+        let descendants = self.descendants(&status).await?;
+        Ok(Thread {
+            ancestors,
+            status,
+            descendants,
+        })
+    }
+
+    // Root first. Stops at the first parent that can't be had.
+    pub async fn ancestors(&self, note: &Note) -> Result<Vec<Note>> {
+        let mut ancestors = Vec::new();
+        let mut seen: HashSet<String> = note.id.iter().cloned().collect();
+        let mut parent = note.in_reply_to_id().map(|p| p.to_string());
+
+        while let Some(id) = parent {
+            if ancestors.len() >= MAX_ANCESTORS || !seen.insert(id.clone()) {
+                break;
+            }
+            let note = match self.load(&id).await {
+                Ok(n) => n,
+                Err(e) => {
+                    debug!("ancestor {id}: {e}");
+                    break;
+                }
+            };
+            parent = note.in_reply_to_id().map(|p| p.to_string());
+            ancestors.push(note);
+        }
+        ancestors.reverse();
+        Ok(ancestors)
+    }
+
+    // Depth first, each reply followed by its own replies.
+    pub async fn descendants(&self, note: &Note) -> Result<Vec<Note>> {
+        let mut descendants = Vec::new();
+        let mut seen: HashSet<String> = note.id.iter().cloned().collect();
+        let mut stack = self.replies(note, &mut seen).await?;
+        stack.reverse();
+
+        while let Some(reply) = stack.pop() {
+            if descendants.len() >= MAX_DESCENDANTS {
+                break;
+            }
+            let mut replies = self.replies(&reply, &mut seen).await?;
+            replies.reverse();
+            stack.extend(replies);
+            descendants.push(reply);
+        }
+        Ok(descendants)
+    }
+
+    // Direct replies to `note`, oldest first: stored ones, then the ones its
+    // `replies` collection adds.
+    async fn replies(
+        &self,
+        note: &Note,
+        seen: &mut HashSet<String>,
+    ) -> Result<Vec<Note>> {
+        let id = match &note.id {
+            Some(id) => id.to_owned(),
+            None => return Ok(Vec::new()),
+        };
+
+        let mut replies = Vec::new();
+        for reply in stored_replies(&id).await? {
+            if reply.id.as_ref().is_some_and(|r| seen.insert(r.clone())) {
+                replies.push(reply);
+            }
+        }
+
+        // Embedded replies are only as good as the server that embeds them.
+        let origin = match &note.replies {
+            Some(IdOrObject::Id(url)) => url.to_owned(),
+            Some(IdOrObject::Object(c)) => c.id.clone().unwrap_or(id.clone()),
+            None => id.clone(),
+        };
+        let walker = match &note.replies {
+            Some(IdOrObject::Id(url)) => Walker::new(url),
+            Some(IdOrObject::Object(c)) => {
+                Walker::from_value(serde_json::to_value(c)?)
+            }
+            None => return Ok(replies),
+        };
+        if !self.fetch {
+            return Ok(replies);
+        }
+        let walker = match &self.viewer {
+            Some(v) => walker.signed_by(v),
+            None => walker,
+        };
+        let items = match walker.max_items(MAX_DESCENDANTS).collect().await {
+            Ok(i) => i,
+            Err(e) => {
+                debug!("replies of {id}: {e}");
+                return Ok(replies);
+            }
+        };
+
+        for item in items {
+            let embedded = match item {
+                IdOrObject::Id(r) => Err(r),
+                IdOrObject::Object(o) => {
+                    match serde_json::from_value::<Note>(context::normalize(*o))
+                    {
+                        Ok(n) if trusted(&n, &origin) => Ok(n),
+                        Ok(n) => match n.id {
+                            Some(r) => Err(r),
+                            None => continue,
+                        },
+                        Err(_) => continue,
+                    }
+                }
+            };
+            let reply = match embedded {
+                Ok(mut n) => {
+                    crate::sanitize::object(&mut n);
+                    n
+                }
+                Err(r) if seen.contains(&r) => continue,
+                // Fetched from its own origin.
+                Err(r) => match self.load(&r).await {
+                    Ok(n) => n,
+                    Err(e) => {
+                        debug!("reply {r}: {e}");
+                        continue;
+                    }
+                },
+            };
+            let reply_id = match &reply.id {
+                Some(r) => r.to_owned(),
+                None => continue,
+            };
+            // Some servers list the whole thread, not just direct replies.
+            if reply.in_reply_to_id() != Some(id.as_str())
+                || !seen.insert(reply_id)
+            {
+                continue;
+            }
+            store(&reply).await?;
+            replies.push(reply);
+        }
+        Ok(replies)
+    }
+
+    // Stored, or fetched and stored.
+    pub async fn load(&self, id: &str) -> Result<Note> {
+        if let Some(note) = stored(id).await? {
+            return Ok(note);
+        }
+        if !self.fetch {
+            return Err(anyhow!("{id} is not stored"));
+        }
+        let object = match &self.viewer {
+            Some(v) => {
+                let (status, body) = crate::send::signed_get(v, id).await?;
+                if status != 200u16 {
+                    return Err(anyhow!(
+                        "signed_get not getting 200 ({status}): {id}"
+                    ));
+                }
+                serde_json::from_str(&body)?
+            }
+            None => crate::utils::fetch_object(id).await?,
+        };
+        let mut note: Note =
+            serde_json::from_value(context::normalize(object))?;
+        if note.id.as_deref() != Some(id) {
+            return Err(anyhow!("{id} answered with {:?}", note.id));
+        }
+        if !authored_on_origin(&note) {
+            return Err(anyhow!("{id} is attributed to another origin"));
+        }
+        crate::sanitize::object(&mut note);
+        store(&note).await?;
+        Ok(note)
+    }
+}
+
+// An embedded object served from `origin` that is its own, by an author of
+// the same origin.
+fn trusted(note: &Note, origin: &str) -> bool {
+    let id = note.id.as_deref().unwrap_or_default();
+    same_origin(id, origin) && authored_on_origin(note)
+}
+
+// Whether `note` has an author, on the origin of its id. A server can only
+// speak for its own accounts.
+pub fn authored_on_origin(note: &Note) -> bool {
+    let id = note.id.as_deref().unwrap_or_default();
+    note.attributed_to_id().is_some_and(|a| same_origin(a, id))
+}
+
+impl Default for Resolver {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// Record `note` with its thread. Returns the thread's id. A stored object is
+// only replaced by one with the same author, and never when it is one of our
+// users' statuses.
+pub async fn store(note: &Note) -> Result<String> {
+    let id = match &note.id {
+        Some(id) => id.to_owned(),
+        None => return Err(anyhow::Error::msg("Note without id")),
+    };
+    let in_reply_to = note.in_reply_to_id().map(|p| p.to_string());
+
+    let thread = match note.thread.as_ref().and_then(|t| t.id()) {
+        Some(t) => t.to_string(),
+        None => match &note.conversation {
+            Some(c) => c.to_owned(),
+            None => match &in_reply_to {
+                Some(p) => thread_of(p).await.unwrap_or(p.to_owned()),
+                None => id.clone(),
+            },
+        },
+    };
+    let created_at = match &note.published {
+        Some(p) => p.to_owned(),
+        None => get_current_time_in_iso_8601().await,
+    };
+
+    let _ = crate::db::Connection::builder()
+        .await
+        .execute(
+            "INSERT INTO thread_object(federationId, inReplyTo, context, object, createdAt) VALUES(?,?,?,json(?),?) ON CONFLICT(federationId) DO UPDATE SET object = excluded.object WHERE json_extract(thread_object.object, '$.attributedTo') = json_extract(excluded.object, '$.attributedTo') AND json_extract(thread_object.object, '$.attributedTo') NOT IN (SELECT federationId FROM user)",
+            &[
+                SV::Text(id),
+                match in_reply_to {
+                    Some(p) => SV::Text(p),
+                    None => SV::Null,
+                },
+                SV::Text(thread.clone()),
+                SV::Text(serde_json::to_string(note)?),
+                SV::Text(created_at),
+            ],
+        )
+        .await;
+    Ok(thread)
+}
+
+// Id of the thread object `id` was recorded in.
+pub async fn thread_of(id: &str) -> Option<String> {
+    let qr = crate::db::Connection::builder()
+        .await
+        .execute(
+            "SELECT context FROM thread_object WHERE federationId = ?",
+            &[SV::Text(id.to_string())],
+        )
+        .await;
+    let thread = qr
+        .rows()
+        .next()
+        .and_then(|r| r.get::<&str>("context"))
+        .map(|c| c.to_string());
+    thread
+}
+
+// Whether `viewer` (None for anonymous requests) may see `note`: it is
+// public, addressed to them, theirs, or addressed to `followed`, the
+// followers collection of its author when the viewer is one of them.
+pub fn visible(
+    note: &Note,
+    viewer: Option<&str>,
+    followed: Option<&str>,
+) -> bool {
+    let viewer = match viewer {
+        Some(v) => v,
+        None => {
+            return note.to.iter().chain(note.cc.iter()).any(|a| a == PUBLIC)
+        }
+    };
+    note.attributed_to_id() == Some(viewer)
+        || note
+            .to
+            .iter()
+            .chain(note.cc.iter())
+            .any(|a| a == PUBLIC || a == viewer || Some(a.as_str()) == followed)
+}
+
+// `visible`, with the follow looked up.
+async fn may_see(note: &Note, viewer: Option<&str>) -> bool {
+    let followed = match (viewer, note.attributed_to_id()) {
+        (Some(v), Some(author)) => followers_of(v, author).await,
+        _ => None,
+    };
+    visible(note, viewer, followed.as_deref())
+}
+
+// Followers collection of `author` when local user `viewer` follows them.
+async fn followers_of(viewer: &str, author: &str) -> Option<String> {
+    let qr = crate::db::Connection::builder()
+        .await
+        .execute(
+            "SELECT 1 FROM following WHERE userId = (SELECT id FROM user WHERE federationId = ?) AND federationId = ?",
+            &[SV::Text(viewer.to_string()), SV::Text(author.to_string())],
+        )
+        .await;
+    if qr.rows.is_empty() {
+        return None;
+    }
+    match crate::remote_actor::stored(author).await {
+        Some(a) => a.actor.followers.clone(),
+        None => Some(format!("{author}/followers")),
+    }
+}
+
+// GET /api/v1/statuses/:id/context
+// `id` is the status' federationId, url encoded. Anonymous requests only
+// get what is already stored.
+pub async fn request(
+    req: Request,
+    params: Params,
+) -> Result<impl IntoResponse> {
+    let id = urlencoding::decode(params.get("id").unwrap_or_default())?;
+    let base = Url::parse(req.uri())
+        .map(|u| u.origin().ascii_serialization())
+        .unwrap_or_default();
+
+    let viewer = match req.header("Authorization") {
+        None => None,
+        Some(_) => match check_api_auth(&req).await? {
+            TokenAuth::Valid(user_id) => {
+                Some(crate::utils::get_local_user(user_id as i64).await?.0)
+            }
+            _ => return Ok(Response::builder().status(401).build()),
+        },
+    };
+    let resolver = match &viewer {
+        Some(v) => Resolver::new().signed_by(v),
+        None => Resolver::new().stored_only(),
+    };
+
+    let thread = match resolver.resolve(&id).await {
+        Ok(t) => t,
+        Err(e) => {
+            debug!("context of {id}: {e}");
+            return Ok(Response::builder().status(404).build());
+        }
+    };
+    let viewer = viewer.as_deref();
+    if !may_see(&thread.status, viewer).await {
+        return Ok(Response::builder().status(404).build());
+    }
+    let mut ancestors = Vec::new();
+    for n in thread.ancestors {
+        if may_see(&n, viewer).await {
+            ancestors.push(n);
+        }
+    }
+    let mut descendants = Vec::new();
+    for n in thread.descendants {
+        if may_see(&n, viewer).await {
+            descendants.push(n);
+        }
+    }
+    let thread = Thread {
+        ancestors,
+        descendants,
+        ..thread
+    };
+    Ok(Response::builder()
+        .status(200)
+        .header("Content-Type", "application/json")
+        .body(serde_json::to_string(
+            &Context::from_thread(&thread, viewer, &base).await?,
+        )?)
+        .build())
+}
+
+async fn stored(id: &str) -> Result<Option<Note>> {
+    let qr = crate::db::Connection::builder()
+        .await
+        .execute(
+            "SELECT object FROM thread_object WHERE federationId = ?",
+            &[SV::Text(id.to_string())],
+        )
+        .await;
+    let note = match qr.rows().next().and_then(|r| r.get::<&str>("object")) {
+        Some(o) => Some(serde_json::from_str(o)?),
+        None => None,
+    };
+    Ok(note)
+}
+
+async fn stored_replies(id: &str) -> Result<Vec<Note>> {
+    let qr = crate::db::Connection::builder()
+        .await
+        .execute(
+            "SELECT object FROM thread_object WHERE inReplyTo = ? ORDER BY createdAt",
+            &[SV::Text(id.to_string())],
+        )
+        .await;
+    let mut replies = Vec::new();
+    for r in qr.rows() {
+        replies.push(serde_json::from_str(
+            r.get::<&str>("object").unwrap_or_default(),
+        )?);
+    }
+    Ok(replies)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn note(id: &str, author: Option<&str>) -> Note {
+        let mut v = json!({ "id": id, "type": "Note" });
+        if let Some(a) = author {
+            v["attributedTo"] = json!(a);
+        }
+        serde_json::from_value(v).unwrap()
+    }
+
+    #[test]
+    fn origins() {
+        let own = note(
+            "https://remote.example/notes/1",
+            Some("https://remote.example/users/bob"),
+        );
+        assert!(authored_on_origin(&own));
+        assert!(trusted(&own, "https://remote.example/notes/0/replies"));
+        assert!(!trusted(&own, "https://other.example/notes/0/replies"));
+
+        // A remote server claiming a post by one of our users.
+        let forged = note(
+            "https://remote.example/notes/2",
+            Some("https://sparrow.example/users/alice"),
+        );
+        assert!(!authored_on_origin(&forged));
+        assert!(!trusted(&forged, "https://remote.example/notes/0/replies"));
+
+        assert!(!authored_on_origin(&note(
+            "https://remote.example/notes/3",
+            None
+        )));
+    }
+
+    #[test]
+    fn visibility() {
+        let alice = "https://sparrow.example/users/alice";
+        let bob = "https://remote.example/users/bob";
+        let bobs_followers = "https://remote.example/users/bob/followers";
+        let addressed = |to: &[&str]| {
+            let mut n = note("https://remote.example/notes/1", Some(bob));
+            n.to = to.iter().map(|t| t.to_string()).collect::<Vec<_>>().into();
+            n
+        };
+
+        let public = addressed(&[PUBLIC]);
+        assert!(visible(&public, None, None));
+        assert!(visible(&public, Some(alice), None));
+
+        let followers_only = addressed(&[bobs_followers]);
+        assert!(!visible(&followers_only, None, None));
+        assert!(!visible(&followers_only, Some(alice), None));
+        assert!(visible(&followers_only, Some(alice), Some(bobs_followers)));
+
+        let direct = addressed(&[alice]);
+        assert!(!visible(&direct, None, None));
+        assert!(visible(&direct, Some(alice), None));
+        assert!(!visible(
+            &direct,
+            Some("https://sparrow.example/users/carol"),
+            None
+        ));
+
+        let own = note("https://sparrow.example/statuses/1", Some(alice));
+        assert!(visible(&own, Some(alice), None));
+        assert!(!visible(&own, None, None));
+    }
+}
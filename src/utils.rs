@@ -36,36 +36,10 @@ pub async fn get_current_time_in_iso_8601() -> String {
     current_time.format("%Y-%m-%dT%H:%M:%SZ").to_string()
 }
 
+// Actor url for any form of federation id, see `fed_id`.
 pub async fn get_actor_url_from_id(id: String) -> Result<String> {
-    let a: Vec<&str> = id.as_str().split("@").collect();
-    let host = a.last().unwrap().to_string();
-
-    // @seungjin@mstd.seungjin.net -> https://mstd.seungjin.net/@sungjin
-
-    // 1. Call https://mstd.seungjin.net/.well-known/webfinger?resource=acct:seungjin@mstd.seungjin.net
-
-    let webfinger_url =
-        format!("https://{host}/.well-known/webfinger?resource=acct:{id}");
-    tracing::debug!(webfinger_url);
-    let request = Request::builder()
-        .method(Method::Get)
-        .header("Accept", "application/activity+json")
-        .uri(webfinger_url)
-        .build();
-    let response: Response = spin_sdk::http::send(request).await?;
-    let body = str::from_utf8(response.body()).unwrap();
-    let body_obj: Value = serde_json::from_str(body).unwrap();
-    let a = body_obj.get("links").unwrap();
-
-    for e in a.as_array().unwrap() {
-        let l = e.get::<&str>("rel").unwrap().as_str().unwrap();
-        if l.eq("self") {
-            let ll = e.get::<&str>("href").unwrap().as_str().unwrap();
-            return Ok(ll.to_string());
-        }
-    }
-
-    Err(anyhow!("no url available"))
+    let fed_id: crate::fed_id::FederationId = id.parse()?;
+    fed_id.resolve().await
 }
 
 pub async fn get_local_user(id: i64) -> Result<(String, String)> {
@@ -97,15 +71,22 @@ pub async fn get_privatekey_with_user_name(name: &str) -> Result<String> {
     Ok(private_key.to_string())
 }
 
+// Key of a local user, or of the instance actor.
 pub async fn get_privatekey_with_actor_url(
     actor_url: String,
 ) -> Result<String> {
+    if crate::instance::is_instance_actor(&actor_url) {
+        return crate::instance::private_key(&actor_url).await;
+    }
     let qr = db::Connection::builder().await.execute(
     "SELECT privateKey FROM signing_key JOIN user ON user.id = signing_key.userId WHERE user.federationId = ?", 
-    &[SV::Text(actor_url)]).await;
-    let private_key =
-        qr.rows().next().unwrap().get::<&str>("privateKey").unwrap();
-    Ok(private_key.to_string())
+    &[SV::Text(actor_url.clone())]).await;
+    let private_key = qr
+        .rows()
+        .next()
+        .and_then(|r| r.get::<&str>("privateKey").map(|k| k.to_string()))
+        .ok_or_else(|| anyhow!("no signing key for {actor_url}"))?;
+    Ok(private_key)
 }
 
 pub async fn get_privatekey_with_db_user_id(id: u16) -> Result<String> {
@@ -122,70 +103,40 @@ pub async fn get_privatekey_with_db_user_id(id: u16) -> Result<String> {
 }
 
 pub async fn get_public_key(actor_url_str: &str) -> Result<String> {
-    tracing::debug!(actor_url_str);
+    let actor = crate::remote_actor::get(actor_url_str).await?;
+    match actor.public_key_pem() {
+        Some(pem) => Ok(pem.to_string()),
+        None => Err(anyhow!("no publicKey: {actor_url_str}")),
+    }
+}
+
+// GET an ActivityPub document by its id.
+pub async fn fetch_object(url: &str) -> Result<Value> {
+    tracing::debug!(url);
     let req = Request::builder()
         .method(Method::Get)
-        .uri(actor_url_str)
+        .uri(url)
         .header("Accept", "application/activity+json")
         .build();
-
-    // Send the request and await the response
     let resp: Response = spin_sdk::http::send(req).await?;
 
-    let body = String::from_utf8(resp.body().to_vec()).unwrap();
-    tracing::debug!("---------");
-    tracing::debug!(body);
-
-    let json: serde_json::Value =
-        serde_json::from_str(&body).expect("JSON was not well-formatted");
-
     let status = resp.status();
-
-    tracing::debug!(status);
-
     if *status != 200u16 {
-        // ignore this request.
-        //tracing::debug!(status);
-        return Err(anyhow::Error::msg("get_public_key not getting 200"));
+        return Err(anyhow!("fetch_object not getting 200 ({status}): {url}"));
     }
 
-    // TODO: Get PublicKey and check id with key_id
-    let public_key = json.get("publicKey").expect(
-        format!("({status:?}) publicKey not found from: {body:?}").as_str(),
-    );
-    let public_key_owner: &str =
-        public_key.get("owner").unwrap().as_str().unwrap();
-    let public_key_pem = public_key
-        .get("publicKeyPem")
-        .unwrap()
-        .as_str()
-        .unwrap()
-        .to_string(); // Keeping '\n' and new line.
-
-    if actor_url_str.to_string() == public_key_owner.to_string() {
-        tracing::debug!(public_key_pem);
-        return Ok(public_key_pem);
-    }
-    // TODO: This shoud not return Err but Ok(None)
-    Err(anyhow::Error::msg(format!(
-        "key_id not matched {} : {}",
-        actor_url_str.to_string(),
-        public_key_owner.to_string()
-    )))
+    let body = String::from_utf8(resp.body().to_vec())?;
+    Ok(serde_json::from_str(&body)?)
 }
 
 pub async fn get_inbox_from_actor(actor: String) -> Result<String> {
-    let req = Request::builder()
-        .method(Method::Get)
-        .uri(actor)
-        .header("Accept", "application/activity+json")
-        .build();
-    let resp: Response = spin_sdk::http::send(req).await?;
-    let body = String::from_utf8(resp.body().to_vec()).unwrap();
-    let json: serde_json::Value =
-        serde_json::from_str(&body).expect("JSON was not well-formatted");
-    let a = json.get::<&str>("inbox").unwrap().as_str().unwrap();
-    Ok(a.to_string())
+    Ok(crate::remote_actor::get(&actor).await?.actor.inbox)
+}
+
+// sharedInbox when the actor advertises one, inbox otherwise.
+pub async fn get_shared_inbox_from_actor(actor: String) -> Result<String> {
+    let actor = crate::remote_actor::get(&actor).await?;
+    Ok(actor.delivery_inbox().to_string())
 }
 
 pub async fn generate_uuid_v7() -> String {
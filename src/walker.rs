@@ -0,0 +1,267 @@
+// Walking remote collections
+// https://www.w3.org/TR/activitystreams-core/#paging
+//
+// Reads a remote (Ordered)Collection item by item: root -> first -> next ...
+// Pages may be embedded or given by url, items may be ids or objects.
+// Used to backfill a followed account's outbox or import a follower list.
+// Pages are only followed on the origin of the collection itself.
+//
+//     let mut walker = Walker::new(outbox).max_items(200).signed_by(me);
+//     while let Some(item) = walker.next().await? { ... }
+
+use anyhow::{anyhow, Result};
+use serde_json::Value;
+use std::collections::{HashSet, VecDeque};
+use tracing::debug;
+
+use crate::apo::context;
+use crate::apo::{same_origin, IdOrObject};
+
+enum Page {
+    Url(String),
+    Embedded(Value),
+}
+
+pub struct Walker {
+    // Id of the collection, once known.
+    id: Option<String>,
+    pending: Option<Page>,
+    items: VecDeque<IdOrObject<Value>>,
+    visited: HashSet<String>,
+    signer: Option<String>,
+    max_pages: usize,
+    max_items: usize,
+    pages: usize,
+    yielded: usize,
+    total_items: Option<u64>,
+}
+
+impl Walker {
+    pub fn new(url: &str) -> Self {
+        Self {
+            id: Some(url.to_string()),
+            pending: Some(Page::Url(url.to_string())),
+            items: VecDeque::new(),
+            visited: HashSet::new(),
+            signer: None,
+            max_pages: 100,
+            max_items: 2000,
+            pages: 0,
+            yielded: 0,
+            total_items: None,
+        }
+    }
+
+    // A collection we already hold, like the `replies` embedded in a note.
+    pub fn from_value(collection: Value) -> Self {
+        let mut walker = Self::new("");
+        walker.id = None;
+        walker.pending = Some(Page::Embedded(collection));
+        walker
+    }
+
+    // Sign fetches with the key of local actor `actor`.
+    pub fn signed_by(mut self, actor: &str) -> Self {
+        self.signer = Some(actor.to_string());
+        self
+    }
+
+    // Documents fetched or read, the root included.
+    pub fn max_pages(mut self, max: usize) -> Self {
+        self.max_pages = max;
+        self
+    }
+
+    pub fn max_items(mut self, max: usize) -> Self {
+        self.max_items = max;
+        self
+    }
+
+    // totalItems of the collection, once its root was read.
+    pub fn total_items(&self) -> Option<u64> {
+        self.total_items
+    }
+
+    pub async fn next(&mut self) -> Result<Option<IdOrObject<Value>>> {
+        loop {
+            if self.yielded >= self.max_items {
+                return Ok(None);
+            }
+            if let Some(item) = self.items.pop_front() {
+                self.yielded += 1;
+                return Ok(Some(item));
+            }
+            if self.pages >= self.max_pages {
+                return Ok(None);
+            }
+
+            let page = match self.pending.take() {
+                Some(Page::Url(url)) => {
+                    let id = self.id.as_deref().unwrap_or_default();
+                    if !same_origin(&url, id) {
+                        debug!("page {url} is not on the origin of {id}");
+                        return Ok(None);
+                    }
+                    // Broken servers link pages in circles.
+                    if !self.visited.insert(url.clone()) {
+                        return Ok(None);
+                    }
+                    self.fetch(&url).await?
+                }
+                Some(Page::Embedded(page)) => page,
+                None => return Ok(None),
+            };
+            self.pages += 1;
+            self.read(page);
+        }
+    }
+
+    pub async fn collect(mut self) -> Result<Vec<IdOrObject<Value>>> {
+        let mut items = Vec::new();
+        while let Some(item) = self.next().await? {
+            items.push(item);
+        }
+        Ok(items)
+    }
+
+    fn read(&mut self, page: Value) {
+        if let Some(id) = page.get("id").and_then(|i| i.as_str()) {
+            self.visited.insert(id.to_string());
+            if self.id.is_none() {
+                self.id = Some(id.to_string());
+            }
+        }
+        if self.total_items.is_none() {
+            self.total_items = page.get("totalItems").and_then(|t| t.as_u64());
+        }
+
+        for key in ["orderedItems", "items"] {
+            match page.get(key) {
+                Some(Value::Array(items)) => {
+                    self.items.extend(items.iter().filter_map(item))
+                }
+                Some(i) => self.items.extend(item(i)),
+                None => {}
+            }
+        }
+
+        // A page links to the next one, the root to the first.
+        self.pending = page.get("next").or(page.get("first")).and_then(link);
+    }
+
+    async fn fetch(&self, url: &str) -> Result<Value> {
+        let page = match &self.signer {
+            Some(signer) => {
+                let (status, body) =
+                    crate::send::signed_get(signer, url).await?;
+                if status != 200u16 {
+                    return Err(anyhow!(
+                        "signed_get not getting 200 ({status}): {url}"
+                    ));
+                }
+                serde_json::from_str(&body)?
+            }
+            None => crate::utils::fetch_object(url).await?,
+        };
+        Ok(context::normalize(page))
+    }
+}
+
+fn item(v: &Value) -> Option<IdOrObject<Value>> {
+    match v {
+        Value::String(id) => Some(IdOrObject::Id(id.to_owned())),
+        Value::Object(_) => Some(IdOrObject::Object(Box::new(v.clone()))),
+        _ => None,
+    }
+}
+
+// Embedded pages are read as is, unless they only carry their id. Mastodon
+// embeds an empty first page of replies with just a `next`.
+fn link(v: &Value) -> Option<Page> {
+    match v {
+        Value::String(url) => Some(Page::Url(url.to_owned())),
+        Value::Object(o)
+            if o.contains_key("orderedItems")
+                || o.contains_key("items")
+                || !o.contains_key("id") =>
+        {
+            Some(Page::Embedded(v.clone()))
+        }
+        Value::Object(o) => o
+            .get("id")
+            .and_then(|i| i.as_str())
+            .map(|url| Page::Url(url.to_string())),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn items() {
+        assert!(matches!(
+            item(&json!("https://remote.example/notes/1")),
+            Some(IdOrObject::Id(id)) if id == "https://remote.example/notes/1"
+        ));
+        assert!(matches!(
+            item(&json!({ "id": "https://remote.example/notes/1" })),
+            Some(IdOrObject::Object(_))
+        ));
+        assert!(item(&json!(1)).is_none());
+        assert!(item(&Value::Null).is_none());
+    }
+
+    #[test]
+    fn links() {
+        assert!(matches!(
+            link(&json!("https://remote.example/c?page=1")),
+            Some(Page::Url(u)) if u == "https://remote.example/c?page=1"
+        ));
+        // Mastodon's first page of replies, only a pointer to the next.
+        let pointer = json!({
+            "id": "https://remote.example/c?page=1",
+            "type": "CollectionPage",
+            "next": "https://remote.example/c?page=2"
+        });
+        assert!(matches!(
+            link(&pointer),
+            Some(Page::Url(u)) if u == "https://remote.example/c?page=1"
+        ));
+        let page = json!({
+            "id": "https://remote.example/c?page=1",
+            "type": "CollectionPage",
+            "items": []
+        });
+        assert!(matches!(link(&page), Some(Page::Embedded(_))));
+        let anonymous = json!({ "type": "CollectionPage" });
+        assert!(matches!(link(&anonymous), Some(Page::Embedded(_))));
+        assert!(link(&json!(null)).is_none());
+    }
+
+    #[tokio::test]
+    async fn stays_on_origin() {
+        let collection = json!({
+            "id": "https://remote.example/notes/1/replies",
+            "type": "Collection",
+            "totalItems": 3,
+            "first": {
+                "type": "CollectionPage",
+                "items": ["https://remote.example/notes/2"],
+                "next": "https://elsewhere.example/page"
+            }
+        });
+        let items = Walker::from_value(collection).collect().await.unwrap();
+        assert_eq!(items.len(), 1);
+
+        // Nothing to tell where the collection lives.
+        let anonymous = json!({
+            "type": "Collection",
+            "first": "https://remote.example/page"
+        });
+        let mut walker = Walker::from_value(anonymous);
+        assert!(walker.next().await.unwrap().is_none());
+    }
+}
@@ -0,0 +1,293 @@
+// WebFinger
+// https://www.rfc-editor.org/rfc/rfc7033
+// https://docs.joinmastodon.org/spec/webfinger/
+//
+// GET /.well-known/webfinger?resource=acct:name@host
+//
+// Resolves our own users, by handle or by actor/profile url, to a JRD
+// pointing at their actor. `rel` parameters narrow the links returned.
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use spin_sdk::http::{IntoResponse, Params, Request, Response};
+use spin_sdk::sqlite::Value as SV;
+use url::Url;
+
+pub const PROFILE_PAGE: &str = "http://webfinger.net/rel/profile-page";
+pub const SUBSCRIBE: &str = "http://ostatus.org/schema/1.0/subscribe";
+
+// JSON Resource Descriptor
+#[derive(Serialize, Deserialize, Default, Debug, PartialEq, Eq, Clone)]
+pub struct Jrd {
+    // Missing from some documents, NodeInfo discovery for one.
+    #[serde(default)]
+    pub subject: String,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub aliases: Vec<String>,
+    #[serde(default)]
+    pub links: Vec<JrdLink>,
+}
+
+#[derive(Serialize, Deserialize, Default, Debug, PartialEq, Eq, Clone)]
+pub struct JrdLink {
+    pub rel: String,
+    #[serde(rename = "type")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub kind: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub href: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub template: Option<String>,
+}
+
+impl Jrd {
+    // JRD of local user `name` whose actor is `federation_id`.
+    pub fn local(name: &str, federation_id: &str) -> Result<Self> {
+        let url = Url::parse(federation_id)?;
+        let host = url.host_str().unwrap_or_default();
+        let base = format!("{}://{host}", url.scheme());
+        let profile = format!("{base}/@{name}");
+
+        Ok(Self {
+            subject: format!("acct:{name}@{host}"),
+            aliases: vec![federation_id.to_string(), profile.clone()],
+            links: vec![
+                JrdLink {
+                    rel: PROFILE_PAGE.to_string(),
+                    kind: Some("text/html".to_string()),
+                    href: Some(profile),
+                    template: None,
+                },
+                JrdLink {
+                    rel: "self".to_string(),
+                    kind: Some("application/activity+json".to_string()),
+                    href: Some(federation_id.to_string()),
+                    template: None,
+                },
+                JrdLink {
+                    rel: SUBSCRIBE.to_string(),
+                    kind: None,
+                    href: None,
+                    template: Some(format!(
+                        "{base}/authorize_interaction?uri={{uri}}"
+                    )),
+                },
+            ],
+        })
+    }
+
+    // Keep links whose rel is in `rels`. All of them if `rels` is empty.
+    pub fn filter(mut self, rels: &[String]) -> Self {
+        if !rels.is_empty() {
+            self.links.retain(|l| rels.contains(&l.rel));
+        }
+        self
+    }
+
+    // Actor id from the `self` link.
+    pub fn actor(&self) -> Option<&str> {
+        self.links
+            .iter()
+            .filter(|l| l.rel == "self")
+            .find(|l| {
+                l.kind.as_deref().is_some_and(|k| {
+                    k.starts_with("application/activity+json")
+                        || k.starts_with("application/ld+json")
+                })
+            })
+            .and_then(|l| l.href.as_deref())
+    }
+}
+
+pub async fn request(
+    req: Request,
+    _params: Params,
+) -> Result<impl IntoResponse> {
+    let mut resource = None;
+    let mut rels = Vec::new();
+    for (k, v) in url::form_urlencoded::parse(req.query().as_bytes()) {
+        match k.as_ref() {
+            "resource" => resource = Some(v.to_string()),
+            "rel" => rels.push(v.to_string()),
+            _ => {}
+        }
+    }
+    let resource = match resource {
+        Some(r) if !r.is_empty() => r,
+        _ => return Ok(error(400, "resource parameter is missing")),
+    };
+
+    let (name, federation_id) = match lookup(&resource).await {
+        Ok(Some(u)) => u,
+        Ok(None) => return Ok(error(404, "no such user")),
+        Err(e) => return Ok(error(400, &e.to_string())),
+    };
+    let jrd = Jrd::local(&name, &federation_id)?.filter(&rels);
+
+    Ok(Response::builder()
+        .status(200)
+        .header("Content-Type", "application/jrd+json; charset=utf-8")
+        .header("Access-Control-Allow-Origin", "*")
+        .body(serde_json::to_string(&jrd)?)
+        .build())
+}
+
+// What a `resource` parameter asks about.
+#[derive(Debug, PartialEq, Eq)]
+enum Resource {
+    // acct:name@host
+    Acct { name: String, host: String },
+    // https://host/@name
+    Profile { name: String, host: String },
+    // https://host/users/name, the actor itself
+    Actor { id: String, host: String },
+}
+
+impl Resource {
+    // Errors for resources that are no handle or url at all.
+    fn parse(resource: &str) -> Result<Self> {
+        if let Some(acct) = resource.strip_prefix("acct:") {
+            let acct = acct.trim_start_matches('@');
+            return match acct.split_once('@') {
+                Some((n, h)) if !n.is_empty() && !h.is_empty() => {
+                    Ok(Resource::Acct {
+                        name: n.to_string(),
+                        host: h.to_string(),
+                    })
+                }
+                _ => Err(anyhow::Error::msg("malformed acct resource")),
+            };
+        }
+        if !resource.starts_with("https://") && !resource.starts_with("http://")
+        {
+            return Err(anyhow::Error::msg("unsupported resource"));
+        }
+        let url = Url::parse(resource)?;
+        let host = match url.host_str() {
+            Some(h) => h.to_string(),
+            None => return Err(anyhow::Error::msg("resource without host")),
+        };
+        Ok(match url.path().strip_prefix("/@") {
+            Some(name) => Resource::Profile {
+                name: name.to_string(),
+                host,
+            },
+            None => Resource::Actor {
+                id: resource.trim_end_matches('/').to_string(),
+                host,
+            },
+        })
+    }
+
+    fn host(&self) -> &str {
+        match self {
+            Resource::Acct { host, .. }
+            | Resource::Profile { host, .. }
+            | Resource::Actor { host, .. } => host,
+        }
+    }
+}
+
+// (name, federationId) of the local user `resource` is about.
+async fn lookup(resource: &str) -> Result<Option<(String, String)>> {
+    let resource = Resource::parse(resource)?;
+    let row =
+        match &resource {
+            Resource::Acct { name, .. } | Resource::Profile { name, .. } => {
+                user("SELECT name, federationId FROM user WHERE name = ?", name)
+                    .await
+            }
+            Resource::Actor { id, .. } => user(
+                "SELECT name, federationId FROM user WHERE federationId = ?",
+                id,
+            )
+            .await,
+        };
+    // The host has to be ours.
+    Ok(row.filter(|(_, f)| {
+        Url::parse(f)
+            .ok()
+            .and_then(|u| {
+                u.host_str()
+                    .map(|h| h.eq_ignore_ascii_case(resource.host()))
+            })
+            .unwrap_or(false)
+    }))
+}
+
+async fn user(sql: &str, param: &str) -> Option<(String, String)> {
+    let qr = crate::db::Connection::builder()
+        .await
+        .execute(sql, &[SV::Text(param.to_string())])
+        .await;
+    let user = qr.rows().next().and_then(|r| {
+        Some((
+            r.get::<&str>("name")?.to_string(),
+            r.get::<&str>("federationId")?.to_string(),
+        ))
+    });
+    user
+}
+
+fn error(status: u16, message: &str) -> Response {
+    Response::builder()
+        .status(status)
+        .header("Content-Type", "application/json")
+        .header("Access-Control-Allow-Origin", "*")
+        .body(serde_json::json!({ "error": message }).to_string())
+        .build()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn resources() {
+        let acct = |name: &str, host: &str| Resource::Acct {
+            name: name.to_string(),
+            host: host.to_string(),
+        };
+        assert_eq!(
+            Resource::parse("acct:alice@example.com").unwrap(),
+            acct("alice", "example.com")
+        );
+        assert_eq!(
+            Resource::parse("acct:@alice@example.com").unwrap(),
+            acct("alice", "example.com")
+        );
+        assert_eq!(
+            Resource::parse("https://example.com/@alice").unwrap(),
+            Resource::Profile {
+                name: "alice".to_string(),
+                host: "example.com".to_string()
+            }
+        );
+        assert_eq!(
+            Resource::parse("https://example.com/users/alice/").unwrap(),
+            Resource::Actor {
+                id: "https://example.com/users/alice".to_string(),
+                host: "example.com".to_string()
+            }
+        );
+        assert!(Resource::parse("acct:alice").is_err());
+        assert!(Resource::parse("acct:@example.com").is_err());
+        assert!(Resource::parse("mailto:alice@example.com").is_err());
+    }
+
+    #[test]
+    fn filter() {
+        let jrd =
+            Jrd::local("alice", "https://example.com/users/alice").unwrap();
+        assert_eq!(jrd.links.len(), 3);
+        assert_eq!(jrd.actor(), Some("https://example.com/users/alice"));
+
+        let rels = |jrd: &Jrd| {
+            jrd.links.iter().map(|l| l.rel.clone()).collect::<Vec<_>>()
+        };
+        let only_self = jrd.clone().filter(&["self".to_string()]);
+        assert_eq!(rels(&only_self), vec!["self"]);
+        assert_eq!(rels(&jrd.clone().filter(&[])), rels(&jrd));
+        assert!(jrd.filter(&["unknown".to_string()]).links.is_empty());
+    }
+}